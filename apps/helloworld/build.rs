@@ -1,5 +1,6 @@
 fn main() {
     println!("cargo::rustc-link-arg=-Tlink.x");
-    println!("cargo::rustc-link-arg=-no-pie");
+    println!("cargo::rustc-link-arg=-pie");
+    println!("cargo::rustc-link-arg=--no-dynamic-linker");
     println!("cargo::rustc-link-arg=-znostart-stop-gc");
 }
@@ -2,6 +2,8 @@
 #![no_main]
 #![feature(used_with_arg)]
 
+extern crate alloc;
+
 #[bare_test::tests]
 mod tests {
 
@@ -10,8 +12,21 @@ mod tests {
 
     #[test]
     fn test2() {
-        let _fdt = match &global_val().platform_info {
-            PlatformInfoKind::DeviceTree(fdt) => fdt.get(),
+        let PlatformInfoKind::DeviceTree(fdt) = &global_val().platform_info else {
+            return;
         };
+        let _fdt = fdt.get();
+    }
+
+    #[test]
+    fn test_heap_no_leak() {
+        let mark = mem::heap_mark();
+        {
+            let v = alloc::vec![0u8; 4096];
+            let s = alloc::string::String::from("leak check");
+            assert_eq!(v.len() + s.len(), 4106);
+        }
+        let diff = mark.diff();
+        assert!(diff.is_empty(), "leaked: {diff:?}");
     }
 }
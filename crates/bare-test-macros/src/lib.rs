@@ -12,7 +12,8 @@ use syn::{Item, ItemMod, parse, spanned::Spanned};
 pub fn build_test_setup(_input: TokenStream) -> TokenStream {
     quote! {
     println!("cargo::rustc-link-arg-tests=-Ttest_case_link.ld");
-    println!("cargo::rustc-link-arg-tests=-no-pie");
+    println!("cargo::rustc-link-arg-tests=-pie");
+    println!("cargo::rustc-link-arg-tests=--no-dynamic-linker");
     println!("cargo::rustc-link-arg-tests=-znostart-stop-gc");
     }
     .into()
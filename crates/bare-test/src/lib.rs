@@ -14,11 +14,23 @@ pub use sparreal_kernel::*;
 
 mod test_case;
 
+kernel_param! {
+    /// 只运行名称包含该字符串的测试
+    #[param("test")]
+    static TEST_FILTER: &'static str = "";
+}
+
 #[sparreal_macros::entry]
 fn main() -> ! {
     println!("begin test");
 
+    let filter = TEST_FILTER.get();
     for test in test_case_list() {
+        if !test.name.contains(filter) {
+            println!("Skip test: {}", test.name);
+            continue;
+        }
+
         println!("Run test: {}", test.name);
 
         (test.test_fn)();
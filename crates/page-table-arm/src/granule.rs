@@ -0,0 +1,74 @@
+/// Translation granule of the VMSAv8-64 stage 1 tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Granule {
+    #[default]
+    Size4K,
+    Size16K,
+    Size64K,
+}
+
+impl Granule {
+    pub const fn page_shift(self) -> usize {
+        match self {
+            Granule::Size4K => 12,
+            Granule::Size16K => 14,
+            Granule::Size64K => 16,
+        }
+    }
+
+    pub const fn page_size(self) -> usize {
+        1 << self.page_shift()
+    }
+
+    /// Address bits resolved by one table level.
+    pub const fn index_bits(self) -> usize {
+        self.page_shift() - 3
+    }
+
+    /// Virtual address bits translated by each TTBR.
+    ///
+    /// 16K uses 47 bits so that the top level is a full table.
+    pub const fn va_bits(self) -> usize {
+        match self {
+            Granule::Size4K | Granule::Size64K => 48,
+            Granule::Size16K => 47,
+        }
+    }
+
+    /// Number of lookup levels for [`Self::va_bits`].
+    pub const fn levels(self) -> usize {
+        (self.va_bits() - self.page_shift()).div_ceil(self.index_bits())
+    }
+
+    /// `TnSZ` value of `TCR_ELx`.
+    pub const fn tsz(self) -> u64 {
+        64 - self.va_bits() as u64
+    }
+
+    /// Output address bits of table, block and page descriptors.
+    pub const fn paddr_mask(self) -> u64 {
+        ((1 << 48) - 1) & !(self.page_size() as u64 - 1)
+    }
+
+    /// Byte offset of the entries walked through `TTBR1`.
+    ///
+    /// When the top level is smaller than a page, software indexes the full
+    /// table with the sign-extended upper address bits, so upper-half entries
+    /// sit at the end of the table.
+    pub const fn ttbr1_offset(self) -> usize {
+        let top_shift = self.page_shift() + (self.levels() - 1) * self.index_bits();
+        let hw_entries = 1 << (self.va_bits() - top_shift);
+        let sw_entries = self.page_size() / 8;
+        (sw_entries - hw_entries) * 8
+    }
+
+    /// Value of the `PAGE_SIZE` field in the Linux arm64 Image header flags.
+    pub const fn image_header_flag(self) -> usize {
+        let v = match self {
+            Granule::Size4K => 1,
+            Granule::Size16K => 2,
+            Granule::Size64K => 3,
+        };
+        v << 1
+    }
+}
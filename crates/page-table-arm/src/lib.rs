@@ -1,16 +1,127 @@
 #![no_std]
 
+mod granule;
 mod pte64;
+mod stage2;
+pub use granule::*;
+use page_table_generic::CacheSetting;
 pub use pte64::*;
+pub use stage2::*;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MAIRKind {
+    /// Device-nGnRnE.
     Device,
+    /// Normal, Write-Back Read/Write-Allocate.
     Normal,
+    /// Normal, Non-cacheable.
     NonCache,
+    /// Normal, Write-Through Read/Write-Allocate.
+    WriteThrough,
+    /// Device-nGnRE, allows early write acknowledgement.
+    DeviceNGnRE,
+    /// Device-GRE, for prefetchable MMIO such as PCIe BARs and framebuffers.
+    DeviceGRE,
 }
 
+impl MAIRKind {
+    /// The 8-bit `Attr<n>` field of `MAIR_ELx`.
+    pub const fn attr(self) -> u8 {
+        match self {
+            MAIRKind::Device => 0x00,
+            MAIRKind::DeviceNGnRE => 0x04,
+            MAIRKind::DeviceGRE => 0x0c,
+            MAIRKind::NonCache => 0x44,
+            MAIRKind::WriteThrough => 0xbb,
+            MAIRKind::Normal => 0xff,
+        }
+    }
+
+    pub const fn is_device(self) -> bool {
+        matches!(
+            self,
+            MAIRKind::Device | MAIRKind::DeviceNGnRE | MAIRKind::DeviceGRE
+        )
+    }
+
+    /// Default attribute for a generic cache setting.
+    pub const fn from_cache(cache: CacheSetting) -> Self {
+        match cache {
+            CacheSetting::Normal => MAIRKind::Normal,
+            CacheSetting::Device => MAIRKind::Device,
+            CacheSetting::NonCache => MAIRKind::NonCache,
+        }
+    }
+
+    /// The closest generic cache setting.
+    pub const fn cache_setting(self) -> CacheSetting {
+        match self {
+            MAIRKind::Normal | MAIRKind::WriteThrough => CacheSetting::Normal,
+            MAIRKind::NonCache => CacheSetting::NonCache,
+            MAIRKind::Device | MAIRKind::DeviceNGnRE | MAIRKind::DeviceGRE => CacheSetting::Device,
+        }
+    }
+}
+
+/// Memory attributes programmed into `MAIR_ELx` and how descriptors use them.
 pub trait MAIRSetting {
-    fn get_idx(kind: MAIRKind) -> usize;
-    fn from_idx(idx: usize) -> MAIRKind;
+    /// Attributes in `AttrIndx` order, at most 8.
+    const ATTRS: &'static [MAIRKind];
+
+    fn get_idx(kind: MAIRKind) -> usize {
+        Self::ATTRS
+            .iter()
+            .position(|k| *k == kind)
+            .expect("memory attribute not in MAIR")
+    }
+
+    fn from_idx(idx: usize) -> MAIRKind {
+        *Self::ATTRS.get(idx).expect("invalid mair index")
+    }
+
+    /// Attribute used for a generic cache setting.
+    ///
+    /// Override to map a setting to another kind in `ATTRS`, e.g. `Device` to `DeviceNGnRE`.
+    fn kind_of(cache: CacheSetting) -> MAIRKind {
+        MAIRKind::from_cache(cache)
+    }
+
+    /// `SH` bits of block and page descriptors using `kind`.
+    ///
+    /// Device memory is always treated as Outer Shareable, the field is left clear.
+    fn shareability(kind: MAIRKind) -> PTEFlags {
+        if kind.is_device() {
+            PTEFlags::empty()
+        } else {
+            PTEFlags::INNER | PTEFlags::SHAREABLE
+        }
+    }
+
+    fn mair_value() -> u64 {
+        mair_value(Self::ATTRS)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn mair_el1_apply() {
+        use aarch64_cpu::registers::*;
+        MAIR_EL1.set(Self::mair_value());
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn mair_el2_apply() {
+        use aarch64_cpu::registers::*;
+        MAIR_EL2.set(Self::mair_value());
+    }
+}
+
+/// `MAIR_ELx` value holding `attrs` in order.
+pub const fn mair_value(attrs: &[MAIRKind]) -> u64 {
+    assert!(attrs.len() <= 8, "MAIR holds at most 8 attributes");
+    let mut value = 0;
+    let mut i = 0;
+    while i < attrs.len() {
+        value |= (attrs[i].attr() as u64) << (8 * i);
+        i += 1;
+    }
+    value
 }
@@ -1,59 +1,20 @@
-use crate::{MAIRKind, MAIRSetting};
+use page_table_generic::AccessSetting;
 
-pub struct MAIRDefault;
-
-#[cfg(target_arch = "aarch64")]
-impl MAIRDefault {
-    pub const fn mair_value() -> u64 {
-        // Device-nGnRnE memory
-        use aarch64_cpu::registers::*;
-        let attr0 = MAIR_EL1::Attr0_Device::nonGathering_nonReordering_noEarlyWriteAck.value;
-        // Normal memory
-        let attr1 = MAIR_EL1::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc.value
-            | MAIR_EL1::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc.value;
-        let attr2 = MAIR_EL1::Attr2_Normal_Inner::NonCacheable.value
-            | MAIR_EL1::Attr2_Normal_Outer::NonCacheable.value;
-        attr0 | attr1 | attr2
-    }
-    pub const fn mair_el2_value() -> u64 {
-        // Device-nGnRnE memory
-        use aarch64_cpu::registers::*;
-        let attr0 = MAIR_EL2::Attr0_Device::nonGathering_nonReordering_noEarlyWriteAck.value;
-        // Normal memory
-        let attr1 = MAIR_EL2::Attr1_Normal_Inner::WriteBack_NonTransient_ReadWriteAlloc.value
-            | MAIR_EL2::Attr1_Normal_Outer::WriteBack_NonTransient_ReadWriteAlloc.value;
-        let attr2 = MAIR_EL2::Attr2_Normal_Inner::NonCacheable.value
-            | MAIR_EL2::Attr2_Normal_Outer::NonCacheable.value;
-        attr0 | attr1 | attr2
-    }
-    pub fn mair_el1_apply() {
-        use aarch64_cpu::registers::*;
-        MAIR_EL1.set(Self::mair_value());
-    }
+use crate::{Granule, MAIRKind, MAIRSetting};
 
-    pub fn mair_el2_apply() {
-        use aarch64_cpu::registers::*;
-        MAIR_EL2.set(Self::mair_el2_value());
-    }
-}
+pub struct MAIRDefault;
 
+/// Device-nGnRnE, Normal WB and Normal NC at indices 0..3, followed by the
+/// additional memory types.
 impl MAIRSetting for MAIRDefault {
-    fn get_idx(kind: MAIRKind) -> usize {
-        match kind {
-            MAIRKind::Device => 0,
-            MAIRKind::Normal => 1,
-            MAIRKind::NonCache => 2,
-        }
-    }
-
-    fn from_idx(idx: usize) -> MAIRKind {
-        match idx {
-            0 => MAIRKind::Device,
-            1 => MAIRKind::Normal,
-            2 => MAIRKind::NonCache,
-            _ => panic!("invalid mair index"),
-        }
-    }
+    const ATTRS: &'static [MAIRKind] = &[
+        MAIRKind::Device,
+        MAIRKind::Normal,
+        MAIRKind::NonCache,
+        MAIRKind::WriteThrough,
+        MAIRKind::DeviceNGnRE,
+        MAIRKind::DeviceGRE,
+    ];
 }
 
 #[derive(Clone, Copy)]
@@ -79,6 +40,16 @@ impl PTE {
         (self.0 & Self::PHYS_ADDR_MASK) as _
     }
 
+    /// Like [`Self::from_paddr`], masking with the output address bits of `granule`.
+    pub const fn from_paddr_with(paddr: usize, granule: Granule) -> Self {
+        PTE(paddr as u64 & granule.paddr_mask())
+    }
+
+    /// Output address of a descriptor in a `granule` table.
+    pub fn paddr_with(&self, granule: Granule) -> usize {
+        (self.0 & granule.paddr_mask()) as _
+    }
+
     pub fn set_flags(&mut self, flags: PTEFlags) {
         self.0 |= flags.bits();
     }
@@ -94,6 +65,56 @@ impl PTE {
     pub fn get_mair_idx(&self) -> usize {
         ((self.0 >> 2) & 0b111) as usize
     }
+
+    pub fn clear_flags(&mut self, flags: PTEFlags) {
+        self.0 &= !flags.bits();
+    }
+
+    /// The Access flag, set by hardware on access when `TCR_ELx.HA` is enabled.
+    pub fn is_accessed(&self) -> bool {
+        self.get_flags().contains(PTEFlags::AF)
+    }
+
+    /// Whether the page can be written without a permission fault.
+    ///
+    /// With [`PTEFlags::DBM`] and `TCR_ELx.HD` enabled, hardware clears
+    /// [`PTEFlags::AP_RO`] on the first write, so this is the dirty state.
+    /// Writable pages without `DBM` are always dirty.
+    pub fn is_dirty(&self) -> bool {
+        !self.get_flags().contains(PTEFlags::AP_RO)
+    }
+
+    pub fn clear_accessed(&mut self) {
+        self.clear_flags(PTEFlags::AF);
+    }
+
+    /// Mark a `DBM` page clean. Pages without `DBM` are left writable.
+    pub fn clear_dirty(&mut self) {
+        if self.get_flags().contains(PTEFlags::DBM) {
+            self.set_flags(PTEFlags::AP_RO);
+        }
+    }
+}
+
+/// Hardware update of the Access flag and dirty state (FEAT_HAFDBS).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HwUpdate {
+    None,
+    AccessFlag,
+    AccessFlagDirty,
+}
+
+impl HwUpdate {
+    /// Level implemented by the current CPU, from `ID_AA64MMFR1_EL1.HAFDBS`.
+    #[cfg(target_arch = "aarch64")]
+    pub fn detect() -> Self {
+        use aarch64_cpu::registers::*;
+        match ID_AA64MMFR1_EL1.read(ID_AA64MMFR1_EL1::HAFDBS) {
+            0 => Self::None,
+            1 => Self::AccessFlag,
+            _ => Self::AccessFlagDirty,
+        }
+    }
 }
 
 impl From<u64> for PTE {
@@ -116,8 +137,8 @@ bitflags::bitflags! {
 
         /// Whether the descriptor is valid.
         const VALID =       1 << 0;
-        /// The descriptor gives the address of the next level of translation table or 4KB page.
-        /// (not a 2M, 1G block)
+        /// The descriptor gives the address of the next level of translation table or a page.
+        /// (not a block)
         const NON_BLOCK =   1 << 1;
 
         /// Non-secure bit. For memory accesses from Secure state, specifies whether the output
@@ -135,6 +156,8 @@ bitflags::bitflags! {
         const AF =          1 << 10;
         /// The not global bit.
         const NG =          1 << 11;
+        /// Dirty Bit Modifier, hardware clears `AP_RO` on write instead of faulting.
+        const DBM =         1 <<  51;
         /// Indicates that 16 adjacent translation table entries point to contiguous memory regions.
         const CONTIGUOUS =  1 <<  52;
         /// The Privileged execute-never field.
@@ -157,3 +180,29 @@ bitflags::bitflags! {
         const NS_TABLE =            1 << 63;
     }
 }
+
+impl PTEFlags {
+    /// `AP`, `PXN` and `UXN` for a stage 1 block or page descriptor.
+    ///
+    /// `AP` has no encoding for EL1 read/write with EL0 read-only, so a page
+    /// readable but not writable at EL0 is read-only at EL1 as well.
+    pub fn from_access(privilege: AccessSetting, user: AccessSetting) -> Self {
+        let mut flags = if user.writable() {
+            PTEFlags::AP_EL0
+        } else if user.readable() {
+            PTEFlags::AP_EL0 | PTEFlags::AP_RO
+        } else if privilege.writable() {
+            PTEFlags::empty()
+        } else {
+            PTEFlags::AP_RO
+        };
+
+        if !privilege.executable() {
+            flags |= PTEFlags::PXN;
+        }
+        if !user.executable() {
+            flags |= PTEFlags::UXN;
+        }
+        flags
+    }
+}
@@ -0,0 +1,300 @@
+use core::marker::PhantomData;
+
+use page_table_generic::{AccessSetting, CacheSetting, PTEArch, PTEGeneric, PTESetting};
+
+use crate::{Granule, MAIRKind};
+
+/// Stage 2 (IPA -> PA) descriptor.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct S2PTE(u64);
+
+impl S2PTE {
+    const MEM_ATTR_MASK: u64 = 0b1111 << 2;
+
+    pub const fn empty() -> Self {
+        S2PTE(0)
+    }
+
+    pub const fn from_paddr_with(paddr: usize, granule: Granule) -> Self {
+        S2PTE(paddr as u64 & granule.paddr_mask())
+    }
+
+    pub fn paddr_with(&self, granule: Granule) -> usize {
+        (self.0 & granule.paddr_mask()) as _
+    }
+
+    pub fn set_flags(&mut self, flags: S2PTEFlags) {
+        self.0 |= flags.bits();
+    }
+
+    pub fn get_flags(&self) -> S2PTEFlags {
+        S2PTEFlags::from_bits_truncate(self.0)
+    }
+
+    pub fn set_mem_attr(&mut self, attr: S2MemAttr) {
+        self.0 = (self.0 & !Self::MEM_ATTR_MASK) | (attr as u64) << 2;
+    }
+
+    /// `None` if the field holds an encoding not produced by [`Self::set_mem_attr`].
+    pub fn get_mem_attr(&self) -> Option<S2MemAttr> {
+        S2MemAttr::from_bits(((self.0 & Self::MEM_ATTR_MASK) >> 2) as u8)
+    }
+}
+
+impl From<u64> for S2PTE {
+    fn from(value: u64) -> Self {
+        Self(value)
+    }
+}
+
+impl From<S2PTE> for u64 {
+    fn from(value: S2PTE) -> Self {
+        value.0
+    }
+}
+
+bitflags::bitflags! {
+    /// Attribute fields in the stage 2 VMSAv8-64 Block and Page descriptors.
+    #[derive(Debug, Clone, Copy)]
+    pub struct S2PTEFlags: u64 {
+        /// Whether the descriptor is valid.
+        const VALID =       1 << 0;
+        /// The descriptor gives the address of the next level of translation table or a page.
+        /// (not a block)
+        const NON_BLOCK =   1 << 1;
+        /// Stage 2 access permission: read.
+        const S2AP_R =      1 << 6;
+        /// Stage 2 access permission: write.
+        const S2AP_W =      1 << 7;
+        /// Shareability: Inner Shareable (otherwise Outer Shareable).
+        const INNER =       1 << 8;
+        /// Shareability: Inner or Outer Shareable (otherwise Non-shareable).
+        const SHAREABLE =   1 << 9;
+        /// The Access flag.
+        const AF =          1 << 10;
+        /// Indicates that 16 adjacent translation table entries point to contiguous memory regions.
+        const CONTIGUOUS =  1 << 52;
+        /// Execute-never, for both EL1 and EL0 of the guest.
+        const XN =          1 << 54;
+    }
+}
+
+/// `MemAttr[3:0]` of a stage 2 descriptor, used directly instead of a MAIR index.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum S2MemAttr {
+    /// Device-nGnRnE.
+    Device = 0b0000,
+    /// Device-nGnRE.
+    DeviceNGnRE = 0b0001,
+    /// Device-GRE.
+    DeviceGRE = 0b0011,
+    /// Normal, Outer and Inner Non-cacheable.
+    NonCache = 0b0101,
+    /// Normal, Outer and Inner Write-Through Cacheable.
+    WriteThrough = 0b1010,
+    /// Normal, Outer and Inner Write-Back Cacheable.
+    Normal = 0b1111,
+}
+
+impl S2MemAttr {
+    pub const fn from_bits(bits: u8) -> Option<Self> {
+        match bits {
+            0b0000 => Some(Self::Device),
+            0b0001 => Some(Self::DeviceNGnRE),
+            0b0011 => Some(Self::DeviceGRE),
+            0b0101 => Some(Self::NonCache),
+            0b1010 => Some(Self::WriteThrough),
+            0b1111 => Some(Self::Normal),
+            _ => None,
+        }
+    }
+}
+
+impl From<MAIRKind> for S2MemAttr {
+    fn from(value: MAIRKind) -> Self {
+        match value {
+            MAIRKind::Device => Self::Device,
+            MAIRKind::Normal => Self::Normal,
+            MAIRKind::NonCache => Self::NonCache,
+            MAIRKind::WriteThrough => Self::WriteThrough,
+            MAIRKind::DeviceNGnRE => Self::DeviceNGnRE,
+            MAIRKind::DeviceGRE => Self::DeviceGRE,
+        }
+    }
+}
+
+impl From<S2MemAttr> for MAIRKind {
+    fn from(value: S2MemAttr) -> Self {
+        match value {
+            S2MemAttr::Device => Self::Device,
+            S2MemAttr::Normal => Self::Normal,
+            S2MemAttr::NonCache => Self::NonCache,
+            S2MemAttr::WriteThrough => Self::WriteThrough,
+            S2MemAttr::DeviceNGnRE => Self::DeviceNGnRE,
+            S2MemAttr::DeviceGRE => Self::DeviceGRE,
+        }
+    }
+}
+
+/// Layout of a stage 2 table.
+pub trait Stage2Config: Sync + Send + Clone + Copy + 'static {
+    const GRANULE: Granule;
+    /// Input (IPA) address bits, the table must start with a single page at the top level.
+    const IPA_BITS: usize;
+
+    /// Memory attribute used for a generic cache setting, see [`crate::MAIRSetting::kind_of`].
+    fn kind_of(cache: CacheSetting) -> MAIRKind {
+        MAIRKind::from_cache(cache)
+    }
+
+    /// Number of lookup levels.
+    fn levels() -> usize {
+        (Self::IPA_BITS - Self::GRANULE.page_shift()).div_ceil(Self::GRANULE.index_bits())
+    }
+
+    /// `VTCR_EL2` value for this layout, with `pa_bits` as the output size.
+    ///
+    /// Table walks are Inner Shareable and Write-Back cacheable.
+    fn vtcr(pa_bits: usize) -> u64 {
+        let levels = Self::levels();
+        let (tg0, sl0) = match Self::GRANULE {
+            Granule::Size4K => (0b00, levels - 2),
+            Granule::Size16K => (0b10, levels - 1),
+            Granule::Size64K => (0b01, levels - 1),
+        };
+        let ps = match pa_bits {
+            ..=32 => 0b000,
+            33..=36 => 0b001,
+            37..=40 => 0b010,
+            41..=42 => 0b011,
+            43..=44 => 0b100,
+            _ => 0b101,
+        };
+        (64 - Self::IPA_BITS as u64)
+            | (sl0 as u64) << 6
+            | 0b01 << 8 // IRGN0: WBRAWA
+            | 0b01 << 10 // ORGN0: WBRAWA
+            | 0b11 << 12 // SH0: Inner
+            | tg0 << 14
+            | ps << 16
+            | 1 << 31 // RES1
+    }
+
+    /// Write `VTCR_EL2` and `VTTBR_EL2`, then invalidate the stage 2 TLB entries of `vmid`.
+    #[cfg(target_arch = "aarch64")]
+    fn apply(table: usize, vmid: u16, pa_bits: usize) {
+        use aarch64_cpu::{asm::barrier::*, registers::*};
+
+        VTCR_EL2.set(Self::vtcr(pa_bits));
+        VTTBR_EL2.write(VTTBR_EL2::VMID.val(vmid as _) + VTTBR_EL2::BADDR.val(table as u64 >> 1));
+        isb(SY);
+        unsafe { core::arch::asm!("tlbi vmalls12e1is; dsb ish; isb") };
+    }
+}
+
+/// 4K granule, 39-bit IPA, lookup starts at level 1 with a single table.
+#[derive(Clone, Copy)]
+pub struct Stage2Default;
+
+impl Stage2Config for Stage2Default {
+    const GRANULE: Granule = Granule::Size4K;
+    const IPA_BITS: usize = 39;
+}
+
+/// [`PTEArch`] of stage 2 tables laid out by `C`.
+///
+/// Stage 2 permissions apply to the guest as a whole, so only
+/// `privilege_access` is encoded and `user_access` reads back the same value.
+#[derive(Clone, Copy)]
+pub struct Stage2<C: Stage2Config = Stage2Default>(PhantomData<C>);
+
+impl<C: Stage2Config> PTEArch for Stage2<C> {
+    fn page_size() -> usize {
+        C::GRANULE.page_size()
+    }
+
+    fn level() -> usize {
+        C::levels()
+    }
+
+    fn new_pte(config: PTEGeneric) -> usize {
+        let mut pte = S2PTE::from_paddr_with(config.paddr, C::GRANULE);
+        let mut flags = S2PTEFlags::empty();
+
+        if config.is_valid {
+            flags |= S2PTEFlags::VALID | S2PTEFlags::AF;
+        }
+
+        if !config.is_block {
+            flags |= S2PTEFlags::NON_BLOCK;
+        }
+
+        let kind = C::kind_of(config.setting.cache_setting);
+        pte.set_mem_attr(kind.into());
+        if !kind.is_device() {
+            flags |= S2PTEFlags::INNER | S2PTEFlags::SHAREABLE;
+        }
+
+        let access = &config.setting.privilege_access;
+
+        if access.readable() {
+            flags |= S2PTEFlags::S2AP_R;
+        }
+
+        if access.writable() {
+            flags |= S2PTEFlags::S2AP_W;
+        }
+
+        if !access.executable() {
+            flags |= S2PTEFlags::XN;
+        }
+
+        pte.set_flags(flags);
+
+        let out: u64 = pte.into();
+
+        out as _
+    }
+
+    fn read_pte(pte: usize) -> PTEGeneric {
+        let pte = S2PTE::from(pte as u64);
+        let paddr = pte.paddr_with(C::GRANULE);
+        let flags = pte.get_flags();
+        let is_valid = flags.contains(S2PTEFlags::VALID);
+        let is_block = !flags.contains(S2PTEFlags::NON_BLOCK);
+        let mut access = AccessSetting::empty();
+        let mut cache_setting = CacheSetting::Normal;
+
+        if is_valid {
+            cache_setting = pte
+                .get_mem_attr()
+                .map_or(CacheSetting::Normal, |a| MAIRKind::from(a).cache_setting());
+
+            if flags.contains(S2PTEFlags::S2AP_R) {
+                access |= AccessSetting::Read;
+            }
+
+            if flags.contains(S2PTEFlags::S2AP_W) {
+                access |= AccessSetting::Write;
+            }
+
+            if !flags.contains(S2PTEFlags::XN) {
+                access |= AccessSetting::Execute;
+            }
+        }
+
+        PTEGeneric {
+            paddr,
+            is_block,
+            is_valid,
+            setting: PTESetting {
+                is_global: true,
+                privilege_access: access,
+                user_access: access,
+                cache_setting,
+            },
+        }
+    }
+}
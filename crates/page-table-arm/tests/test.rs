@@ -16,4 +16,189 @@ mod test {
         assert_eq!(pte.paddr(), 0x1234_5678_9000);
         assert_eq!(pte.get_mair_idx(), 5);
     }
+
+    #[test]
+    fn test_granule_levels() {
+        assert_eq!(Granule::Size4K.levels(), 4);
+        assert_eq!(Granule::Size16K.levels(), 3);
+        assert_eq!(Granule::Size64K.levels(), 3);
+
+        assert_eq!(Granule::Size4K.ttbr1_offset(), 0);
+        assert_eq!(Granule::Size16K.ttbr1_offset(), 0);
+        // 64 entries at the top level, at the end of a 8192 entry table
+        assert_eq!(Granule::Size64K.ttbr1_offset(), (8192 - 64) * 8);
+    }
+
+    #[test]
+    fn test_granule_paddr() {
+        let pte = PTE::from_paddr_with(0x1234_5678_9000, Granule::Size64K);
+        assert_eq!(pte.paddr_with(Granule::Size64K), 0x1234_5678_0000);
+
+        let pte = PTE::from_paddr_with(0x1234_5678_c000, Granule::Size16K);
+        assert_eq!(pte.paddr_with(Granule::Size16K), 0x1234_5678_c000);
+    }
+
+    #[test]
+    fn test_stage2_pte() {
+        use page_table_generic::*;
+
+        let mut setting = PTESetting {
+            is_global: true,
+            privilege_access: AccessSetting::Read | AccessSetting::Write,
+            user_access: AccessSetting::empty(),
+            cache_setting: CacheSetting::Device,
+        };
+        let raw = Stage2::<Stage2Default>::new_pte(PTEGeneric {
+            paddr: 0x8000_0000,
+            is_block: true,
+            is_valid: true,
+            setting,
+        });
+        let pte = S2PTE::from(raw as u64);
+        let flags = pte.get_flags();
+        assert!(flags.contains(S2PTEFlags::VALID | S2PTEFlags::AF | S2PTEFlags::S2AP_W));
+        assert!(flags.contains(S2PTEFlags::XN));
+        assert!(!flags.contains(S2PTEFlags::NON_BLOCK));
+        assert_eq!(pte.get_mem_attr(), Some(S2MemAttr::Device));
+
+        let back = Stage2::<Stage2Default>::read_pte(raw);
+        assert_eq!(back.paddr, 0x8000_0000);
+        assert!(back.is_block);
+        // stage 2 has a single permission set for the guest
+        setting.user_access = setting.privilege_access;
+        assert!(back.setting == setting);
+    }
+
+    #[test]
+    fn test_stage2_vtcr() {
+        assert_eq!(Stage2Default::levels(), 3);
+        // T0SZ=25, SL0=level 1, inner shareable WB walks, PS=40 bits
+        assert_eq!(Stage2Default::vtcr(40), 0x8002_3559);
+    }
+
+    #[test]
+    fn test_mair() {
+        // the first three attributes keep their indices
+        assert_eq!(MAIRDefault::mair_value() & 0xff_ffff, 0x44_ff00);
+        assert_eq!(MAIRDefault::mair_value(), 0x0c04_bb44_ff00);
+        assert_eq!(MAIRDefault::get_idx(MAIRKind::DeviceGRE), 5);
+        assert_eq!(MAIRDefault::from_idx(3), MAIRKind::WriteThrough);
+        assert!(MAIRDefault::shareability(MAIRKind::Device).is_empty());
+    }
+
+    #[derive(Clone, Copy)]
+    struct Stage2Io;
+
+    impl Stage2Config for Stage2Io {
+        const GRANULE: Granule = Granule::Size4K;
+        const IPA_BITS: usize = 39;
+
+        fn kind_of(cache: page_table_generic::CacheSetting) -> MAIRKind {
+            match cache {
+                page_table_generic::CacheSetting::Device => MAIRKind::DeviceGRE,
+                _ => MAIRKind::WriteThrough,
+            }
+        }
+    }
+
+    struct MAIRIo;
+
+    impl MAIRSetting for MAIRIo {
+        const ATTRS: &'static [MAIRKind] = MAIRDefault::ATTRS;
+
+        fn kind_of(cache: page_table_generic::CacheSetting) -> MAIRKind {
+            match cache {
+                page_table_generic::CacheSetting::Device => MAIRKind::DeviceNGnRE,
+                _ => MAIRKind::from_cache(cache),
+            }
+        }
+    }
+
+    #[test]
+    fn test_mair_kind_of() {
+        use page_table_generic::*;
+
+        let kind = MAIRIo::kind_of(CacheSetting::Device);
+        assert_eq!(kind, MAIRKind::DeviceNGnRE);
+        assert_eq!(MAIRIo::get_idx(kind), 4);
+        assert_eq!(MAIRIo::kind_of(CacheSetting::Normal), MAIRKind::Normal);
+
+        let new = |cache_setting| {
+            let raw = Stage2::<Stage2Io>::new_pte(PTEGeneric {
+                paddr: 0x8000_0000,
+                is_block: false,
+                is_valid: true,
+                setting: PTESetting {
+                    is_global: true,
+                    privilege_access: AccessSetting::Read,
+                    user_access: AccessSetting::empty(),
+                    cache_setting,
+                },
+            });
+            S2PTE::from(raw as u64)
+        };
+
+        let pte = new(CacheSetting::Device);
+        assert_eq!(pte.get_mem_attr(), Some(S2MemAttr::DeviceGRE));
+        assert!(!pte.get_flags().contains(S2PTEFlags::SHAREABLE));
+
+        let pte = new(CacheSetting::Normal);
+        assert_eq!(pte.get_mem_attr(), Some(S2MemAttr::WriteThrough));
+        assert!(pte.get_flags().contains(S2PTEFlags::SHAREABLE));
+    }
+
+    #[test]
+    fn test_dirty() {
+        let mut pte = PTE::from_paddr(0x4000_0000);
+        pte.set_flags(PTEFlags::VALID | PTEFlags::AF | PTEFlags::DBM);
+        assert!(pte.is_accessed());
+        assert!(pte.is_dirty());
+
+        pte.clear_accessed();
+        pte.clear_dirty();
+        assert!(!pte.is_accessed());
+        assert!(!pte.is_dirty());
+        assert!(pte.get_flags().contains(PTEFlags::AP_RO));
+
+        // without DBM the page must stay writable
+        let mut pte = PTE::from_paddr(0x4000_0000);
+        pte.set_flags(PTEFlags::VALID | PTEFlags::AF);
+        pte.clear_dirty();
+        assert!(pte.is_dirty());
+    }
+
+    #[test]
+    fn test_user_access() {
+        use page_table_generic::AccessSetting;
+
+        let ap = PTEFlags::AP_EL0 | PTEFlags::AP_RO;
+        let kernel_rw = AccessSetting::Read | AccessSetting::Write;
+
+        // R: read-only at both levels, even if EL1 asks for write
+        for privilege in [AccessSetting::Read, kernel_rw] {
+            let flags = PTEFlags::from_access(privilege, AccessSetting::Read);
+            assert_eq!(flags.bits() & ap.bits(), ap.bits());
+            assert!(flags.contains(PTEFlags::PXN | PTEFlags::UXN));
+        }
+
+        // RW: AP=01, never executable
+        let flags = PTEFlags::from_access(kernel_rw, kernel_rw);
+        assert_eq!(flags.bits() & ap.bits(), PTEFlags::AP_EL0.bits());
+        assert!(flags.contains(PTEFlags::PXN | PTEFlags::UXN));
+
+        // RX: AP=11, executable at EL0 only
+        let flags = PTEFlags::from_access(
+            AccessSetting::Read,
+            AccessSetting::Read | AccessSetting::Execute,
+        );
+        assert_eq!(flags.bits() & ap.bits(), ap.bits());
+        assert!(flags.contains(PTEFlags::PXN));
+        assert!(!flags.contains(PTEFlags::UXN));
+
+        // kernel only
+        let flags = PTEFlags::from_access(kernel_rw, AccessSetting::empty());
+        assert_eq!(flags.bits() & ap.bits(), 0);
+        let flags = PTEFlags::from_access(AccessSetting::Read, AccessSetting::empty());
+        assert_eq!(flags.bits() & ap.bits(), PTEFlags::AP_RO.bits());
+    }
 }
@@ -0,0 +1,116 @@
+//! 内核堆 `KernelHeap` 在随机负载下的单次分配/释放耗时
+//!
+//! 在宿主机上运行，`max` 含操作系统调度抖动，以 p99.9 为准。后端由 feature 决定，分别运行两次比较：
+//!
+//! `cargo bench -p sparreal-kernel --bench heap_latency --features mmu`
+//!
+//! `cargo bench -p sparreal-kernel --bench heap_latency --features "mmu tlsf"`
+
+use std::{alloc::Layout, hint::black_box, ptr::NonNull, time::Instant};
+
+use sparreal_kernel::mem::KernelHeap;
+
+const POOL_SIZE: usize = 64 * 1024 * 1024;
+const OPS: usize = 200_000;
+const LIVE: usize = 4096;
+
+const BACKEND: &str = if cfg!(feature = "tlsf") {
+    "tlsf"
+} else {
+    "buddy"
+};
+
+/// xorshift，固定种子，两个后端看到完全相同的请求序列
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn layout(&mut self) -> Layout {
+        let r = self.next();
+        // 多数为小对象，少量页级和大块
+        let size = match r % 100 {
+            0..=79 => 16 + (r >> 8) as usize % 2048,
+            80..=97 => 4096 * (1 + (r >> 8) as usize % 8),
+            _ => 64 * 1024 * (1 + (r >> 8) as usize % 8),
+        };
+        let align = 1 << ((r >> 32) % 7 + 3);
+        Layout::from_size_align(size, align).unwrap()
+    }
+}
+
+struct Report {
+    alloc: Vec<u64>,
+    dealloc: Vec<u64>,
+    failed: usize,
+}
+
+fn run(heap: &mut KernelHeap) -> Report {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut live: Vec<Option<(NonNull<u8>, Layout)>> = vec![None; LIVE];
+    let mut report = Report {
+        alloc: Vec::with_capacity(OPS),
+        dealloc: Vec::with_capacity(OPS),
+        failed: 0,
+    };
+
+    for _ in 0..OPS {
+        let slot = rng.next() as usize % LIVE;
+        if let Some((ptr, layout)) = live[slot].take() {
+            let t = Instant::now();
+            heap.dealloc(ptr, layout);
+            report.dealloc.push(t.elapsed().as_nanos() as u64);
+        } else {
+            let layout = rng.layout();
+            let t = Instant::now();
+            let ptr = black_box(heap.alloc(layout).ok());
+            report.alloc.push(t.elapsed().as_nanos() as u64);
+            match ptr {
+                Some(ptr) => live[slot] = Some((ptr, layout)),
+                None => report.failed += 1,
+            }
+        }
+    }
+
+    for (ptr, layout) in live.into_iter().flatten() {
+        heap.dealloc(ptr, layout);
+    }
+    report
+}
+
+fn summary(name: &str, op: &str, samples: &mut [u64]) {
+    samples.sort_unstable();
+    let pct = |p: usize| samples[(samples.len() - 1) * p / 1000];
+    println!(
+        "{name:>6} {op:<8} n={:<7} p50={:>6}ns p99={:>6}ns p99.9={:>6}ns max={:>8}ns",
+        samples.len(),
+        pct(500),
+        pct(990),
+        pct(999),
+        samples.last().copied().unwrap_or_default()
+    );
+}
+
+fn main() {
+    let layout = Layout::from_size_align(POOL_SIZE, 4096).unwrap();
+    let pool = unsafe { std::alloc::alloc_zeroed(layout) } as usize;
+    let mut heap = KernelHeap::empty();
+    unsafe { heap.add_to_heap(pool, pool + POOL_SIZE) };
+
+    let mut r = run(&mut heap);
+    summary(BACKEND, "alloc", &mut r.alloc);
+    summary(BACKEND, "dealloc", &mut r.dealloc);
+    if r.failed > 0 {
+        println!("{BACKEND:>6} {} allocations failed", r.failed);
+    }
+    let stats = heap.stats();
+    println!(
+        "{BACKEND:>6} peak {:#x} of {:#x}, failures {}",
+        stats.peak, stats.total, stats.failures
+    );
+}
@@ -5,18 +5,25 @@ use crate::{
     globals::{self, global_val},
     io::print::*,
     mem::{mmu::*, region::init_boot_rsv_region, stack_top},
-    platform::{PlatformInfoKind, regsions},
+    platform::{FirmwareInfo, PlatformInfoKind, regsions},
     platform_if::MMUImpl,
 };
 
-pub fn start(text_va_offset: usize, platform_info: PlatformInfoKind) -> Result<(), &'static str> {
+/// `kaslr_offset` 为 0 时不做随机化，见 [`kaslr_offset_from_seed`]
+pub fn start(
+    text_va_offset: usize,
+    kaslr_offset: usize,
+    platform_info: PlatformInfoKind,
+    firmware: FirmwareInfo,
+) -> Result<(), &'static str> {
     early_dbgln("Booting up");
     unsafe {
         set_text_va_offset(text_va_offset);
+        set_kaslr_offset(kaslr_offset);
         init_boot_rsv_region();
     }
 
-    if let Err(e) = unsafe { globals::setup(platform_info) } {
+    if let Err(e) = unsafe { globals::setup(platform_info, firmware) } {
         early_dbgln("setup globle error: ");
         early_dbgln(e);
     }
@@ -4,7 +4,7 @@ use ansi_rgb::{Foreground, orange};
 use log::LevelFilter;
 
 use crate::{
-    driver,
+    cmdline, driver, fs,
     globals::{self, global_val},
     io::{self, print::*},
     irq,
@@ -33,6 +33,9 @@ pub extern "C" fn __start() -> ! {
     let _ = log::set_logger(&KLogger);
     log::set_max_level(LevelFilter::Trace);
 
+    cmdline::init();
+    log::set_max_level(cmdline::LOGLEVEL.get());
+
     mem::init_heap();
 
     unsafe { globals::setup_percpu() };
@@ -41,6 +44,8 @@ pub extern "C" fn __start() -> ! {
 
     mem::init_page_and_memory();
 
+    fs::init();
+
     driver::init();
 
     irq::enable_all();
@@ -68,10 +73,18 @@ fn print_start_msg() {
     print_pair!("Kernel Stack Top", "{}", VirtAddr::from(stack_top()));
     print_pair!("Start CPU", "{}", platform::cpu_hard_id());
 
+    #[cfg(feature = "mmu")]
+    if mem::mmu::kaslr_offset() != 0 {
+        print_pair!("KASLR Offset", "{:#x}", mem::mmu::kaslr_offset());
+    }
+
     match &global_val().platform_info {
         globals::PlatformInfoKind::DeviceTree(fdt) => {
             print_pair!("FDT", "{:p}", fdt.get_addr());
         }
+        globals::PlatformInfoKind::Acpi(acpi) => {
+            print_pair!("ACPI RSDP", "{}", acpi.rsdp());
+        }
     }
 
     if let Some(debug) = global_val().platform_info.debugcon() {
@@ -79,6 +92,25 @@ fn print_start_msg() {
             print_pair!("Debug Serial", "{}", c);
         }
     }
+
+    if let Some(fb) = &global_val().firmware.framebuffer {
+        print_pair!(
+            "Framebuffer",
+            "{} {}x{} {:?}",
+            fb.addr,
+            fb.width,
+            fb.height,
+            fb.format
+        );
+    }
+
+    if let Some(initrd) = global_val().platform_info.initrd() {
+        print_pair!("Initrd", "[{}, {})", initrd.start, initrd.end);
+    }
+
+    if !cmdline::cmdline().is_empty() {
+        print_pair!("Command Line", "{}", cmdline::cmdline());
+    }
 }
 
 static LOGO: &str = r#"
@@ -0,0 +1,252 @@
+//! 内核命令行：解析 `/chosen/bootargs`，为 [`kernel_param!`](crate::kernel_param) 声明的参数赋值
+//!
+//! 参数形如 `name=value` 或 `name`，名称中 `-` 与 `_` 等价，值可用双引号包含空格。
+//! `--` 之后的部分不作为内核参数，留给应用通过 [`app_args`] 获取。
+
+use log::{LevelFilter, debug, warn};
+use spin::{Mutex, Once};
+
+use crate::globals::global_val;
+use crate::platform_if::PlatformImpl;
+
+static CMDLINE: Once<&'static str> = Once::new();
+
+crate::kernel_param! {
+    /// 日志级别：off、error、warn、info、debug、trace
+    #[param("loglevel")]
+    pub static LOGLEVEL: LevelFilter = LevelFilter::Trace;
+
+    /// 最多使用的 CPU 数量，0 表示不限制
+    #[param("maxcpus")]
+    pub static MAXCPUS: usize = 0;
+}
+
+/// 链接到 `.kernel.param` 段的参数描述
+#[repr(C)]
+pub struct KernelParam {
+    pub name: &'static str,
+    pub help: &'static str,
+    /// 解析并保存值，值无效时返回 `false`
+    pub set: fn(&'static str) -> bool,
+}
+
+/// 参数值的类型
+pub trait ParamValue: Copy + Send {
+    /// 不带 `=` 的参数传入空字符串
+    fn parse(s: &'static str) -> Option<Self>;
+}
+
+impl ParamValue for bool {
+    fn parse(s: &'static str) -> Option<Self> {
+        match s {
+            "" | "1" | "y" | "yes" | "on" | "true" => Some(true),
+            "0" | "n" | "no" | "off" | "false" => Some(false),
+            _ => None,
+        }
+    }
+}
+
+impl ParamValue for &'static str {
+    fn parse(s: &'static str) -> Option<Self> {
+        Some(s)
+    }
+}
+
+impl ParamValue for LevelFilter {
+    fn parse(s: &'static str) -> Option<Self> {
+        s.parse().ok()
+    }
+}
+
+macro_rules! impl_param_int {
+    ($($t:ty),*) => {
+        $(
+            impl ParamValue for $t {
+                fn parse(s: &'static str) -> Option<Self> {
+                    match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                        Some(hex) => <$t>::from_str_radix(hex, 16).ok(),
+                        None => s.parse().ok(),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_param_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+/// 带默认值的内核参数，由 [`kernel_param!`](crate::kernel_param) 声明
+pub struct Param<T> {
+    name: &'static str,
+    value: Mutex<T>,
+}
+
+impl<T: ParamValue> Param<T> {
+    pub const fn new(name: &'static str, default: T) -> Self {
+        Self {
+            name,
+            value: Mutex::new(default),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn get(&self) -> T {
+        *self.value.lock()
+    }
+
+    #[doc(hidden)]
+    pub fn set(&self, s: &'static str) -> bool {
+        match T::parse(s) {
+            Some(v) => {
+                *self.value.lock() = v;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// 声明内核参数，启动时按命令行中同名的参数赋值
+///
+/// ```ignore
+/// kernel_param! {
+///     /// 帮助信息
+///     #[param("name")]
+///     pub static NAME: usize = 0;
+/// }
+/// ```
+#[macro_export]
+macro_rules! kernel_param {
+    ($(
+        $(#[doc = $doc:literal])*
+        #[param($name:literal)]
+        $vis:vis static $ident:ident: $ty:ty = $default:expr;
+    )+) => {
+        $(
+            $(#[doc = $doc])*
+            $vis static $ident: $crate::cmdline::Param<$ty> =
+                $crate::cmdline::Param::new($name, $default);
+
+            const _: () = {
+                #[used]
+                #[unsafe(link_section = ".kernel.param")]
+                static PARAM: $crate::cmdline::KernelParam = $crate::cmdline::KernelParam {
+                    name: $name,
+                    help: concat!("" $(, $doc)*),
+                    set: |s| $ident.set(s),
+                };
+            };
+        )+
+    };
+}
+
+/// `.kernel.param` 段，内容为 [`KernelParam`] 数组
+#[repr(C)]
+pub struct KernelParamSlice {
+    data: *const u8,
+    len: usize,
+}
+
+impl KernelParamSlice {
+    pub fn from_raw(data: &'static [u8]) -> Self {
+        Self {
+            data: data.as_ptr(),
+            len: data.len(),
+        }
+    }
+
+    pub fn as_slice(&self) -> &'static [KernelParam] {
+        unsafe {
+            core::slice::from_raw_parts(
+                self.data as *const KernelParam,
+                self.len / size_of::<KernelParam>(),
+            )
+        }
+    }
+}
+
+/// 所有已注册的参数
+pub fn params() -> &'static [KernelParam] {
+    PlatformImpl::kernel_params().as_slice()
+}
+
+/// 完整的命令行，没有时为空
+pub fn cmdline() -> &'static str {
+    CMDLINE.get().copied().unwrap_or_default()
+}
+
+/// `--` 之后留给应用的参数
+pub fn app_args() -> impl Iterator<Item = (&'static str, Option<&'static str>)> {
+    let mut args = parse(cmdline());
+    args.by_ref().any(|(k, v)| k == "--" && v.is_none());
+    args
+}
+
+/// 比较参数名，`-` 与 `_` 等价
+pub fn name_eq(a: &str, b: &str) -> bool {
+    let norm = |c: u8| if c == b'-' { b'_' } else { c };
+    a.len() == b.len() && a.bytes().map(norm).eq(b.bytes().map(norm))
+}
+
+/// 把命令行拆分为 `(name, value)`，去掉值两侧的引号
+pub fn parse(cmdline: &str) -> impl Iterator<Item = (&str, Option<&str>)> {
+    let mut rest = cmdline;
+    core::iter::from_fn(move || {
+        rest = rest.trim_start();
+        if rest.is_empty() {
+            return None;
+        }
+        let mut quoted = false;
+        let end = rest
+            .char_indices()
+            .find(|&(_, c)| {
+                if c == '"' {
+                    quoted = !quoted;
+                }
+                c.is_whitespace() && !quoted
+            })
+            .map_or(rest.len(), |(i, _)| i);
+        let (token, tail) = rest.split_at(end);
+        rest = tail;
+
+        Some(match token.split_once('=') {
+            Some((k, v)) => (k, Some(unquote(v))),
+            None => (unquote(token), None),
+        })
+    })
+}
+
+fn unquote(s: &str) -> &str {
+    s.strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(s)
+}
+
+/// 解析命令行并为已注册的参数赋值，不分配内存，可在堆初始化前调用
+pub fn init() {
+    let line: &'static str = CMDLINE.call_once(|| {
+        let g = global_val();
+        g.firmware
+            .bootargs
+            .as_deref()
+            .or_else(|| g.platform_info.bootargs())
+            .unwrap_or_default()
+    });
+
+    for (name, value) in parse(line) {
+        if name == "--" && value.is_none() {
+            break;
+        }
+        match params().iter().find(|p| name_eq(p.name, name)) {
+            Some(p) => {
+                if !(p.set)(value.unwrap_or_default()) {
+                    warn!("invalid value {value:?} for kernel parameter `{name}`");
+                }
+            }
+            None => debug!("unknown kernel parameter `{name}`"),
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use crate::{globals::global_val, irq, platform, time};
+use alloc::vec;
+use core::ptr::NonNull;
 use log::debug;
 pub use rdrive::*;
 pub use sparreal_macros::module_driver;
@@ -8,6 +10,17 @@ pub fn init() {
         crate::globals::PlatformInfoKind::DeviceTree(fdt) => DriverInfoKind::Fdt {
             addr: fdt.get_addr(),
         },
+        crate::globals::PlatformInfoKind::Acpi(acpi) => {
+            // 驱动只能从设备树探测，按 ACPI 表生成一份，需 8 字节对齐且常驻
+            let dtb = acpi.to_fdt();
+            let mut buff = vec![0u64; dtb.len().div_ceil(8)];
+            let bytes =
+                unsafe { core::slice::from_raw_parts_mut(buff.as_mut_ptr() as *mut u8, dtb.len()) };
+            bytes.copy_from_slice(&dtb);
+            let addr = NonNull::new(buff.leak().as_mut_ptr() as *mut u8).unwrap();
+
+            DriverInfoKind::Fdt { addr }
+        }
     };
 
     rdrive::init(info);
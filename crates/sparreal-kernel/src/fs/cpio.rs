@@ -0,0 +1,136 @@
+//! newc 格式 cpio 归档的解析，即 Linux initramfs 所用的格式
+
+const MAGIC: &[u8] = b"070701";
+const MAGIC_CRC: &[u8] = b"070702";
+const HEADER_SIZE: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+/// `mode` 中的文件类型
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum CpioError {
+    #[error("bad magic at offset {0:#x}, compressed archives are not supported")]
+    BadMagic(usize),
+    #[error("bad header field at offset {0:#x}")]
+    BadHeader(usize),
+    #[error("archive truncated")]
+    Truncated,
+}
+
+/// 归档中的一项，名称不含结尾的 NUL
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry<'a> {
+    pub name: &'a str,
+    pub mode: u32,
+    pub mtime: u32,
+    /// 普通文件的内容或符号链接的目标
+    pub data: &'a [u8],
+}
+
+impl Entry<'_> {
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+/// 依次取出归档中的项
+///
+/// 支持 initramfs 中首尾相接的多个归档，归档之间可以填充 0。
+pub struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { data, offset: 0 }
+    }
+
+    fn hex(&self, field: usize) -> Result<u32, CpioError> {
+        let at = self.offset + 6 + field * 8;
+        let raw = self.data.get(at..at + 8).ok_or(CpioError::Truncated)?;
+        core::str::from_utf8(raw)
+            .ok()
+            .and_then(|s| u32::from_str_radix(s, 16).ok())
+            .ok_or(CpioError::BadHeader(at))
+    }
+
+    fn next_entry(&mut self) -> Result<Option<Entry<'a>>, CpioError> {
+        loop {
+            while self.data.get(self.offset) == Some(&0) {
+                self.offset += 1;
+            }
+            let Some(rest) = self.data.get(self.offset..) else {
+                return Ok(None);
+            };
+            if rest.is_empty() {
+                return Ok(None);
+            }
+            if rest.len() < HEADER_SIZE {
+                return Err(CpioError::Truncated);
+            }
+            if !matches!(&rest[..6], MAGIC | MAGIC_CRC) {
+                return Err(CpioError::BadMagic(self.offset));
+            }
+
+            let mode = self.hex(1)?;
+            let mtime = self.hex(5)?;
+            let file_size = self.hex(6)? as usize;
+            let name_size = self.hex(11)? as usize;
+
+            let name_start = self.offset + HEADER_SIZE;
+            let name = self
+                .data
+                .get(name_start..name_start + name_size)
+                .ok_or(CpioError::Truncated)?;
+            let name = name
+                .strip_suffix(&[0])
+                .and_then(|n| core::str::from_utf8(n).ok())
+                .ok_or(CpioError::BadHeader(name_start))?;
+
+            let data_start = (name_start + name_size).next_multiple_of(4);
+            let data = self
+                .data
+                .get(data_start..data_start + file_size)
+                .ok_or(CpioError::Truncated)?;
+            self.offset = (data_start + file_size).next_multiple_of(4);
+
+            // 每个归档以 TRAILER!!! 结束，之后可能还有下一个归档
+            if name == TRAILER {
+                continue;
+            }
+
+            return Ok(Some(Entry {
+                name,
+                mode,
+                mtime,
+                data,
+            }));
+        }
+    }
+}
+
+impl<'a> Iterator for Reader<'a> {
+    type Item = Result<Entry<'a>, CpioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let ret = self.next_entry().transpose();
+        if matches!(ret, Some(Err(_))) {
+            // 出错后不再继续
+            self.offset = self.data.len();
+        }
+        ret
+    }
+}
@@ -0,0 +1,276 @@
+//! 只读的内存文件系统，启动时从 initrd 中的 cpio 归档解包
+//!
+//! 文件内容不复制，直接引用 initrd 所在的内存，该内存在启动时已保留。
+
+use alloc::{
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+};
+use core::ops::Bound;
+use log::{info, warn};
+use spin::Once;
+
+use crate::globals::global_val;
+use crate::platform_if::RegionKind;
+
+pub mod cpio;
+
+use cpio::{CpioError, Reader};
+
+/// 解析路径时最多跟随的符号链接数
+const MAX_SYMLINKS: usize = 8;
+
+static RAMFS: Once<RamFs<'static>> = Once::new();
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum FsError {
+    #[error("no such file or directory")]
+    NotFound,
+    #[error("not a directory")]
+    NotADirectory,
+    #[error("is a directory")]
+    IsADirectory,
+    #[error("too many levels of symbolic links")]
+    TooManyLinks,
+    #[error("cpio: {0}")]
+    Cpio(#[from] CpioError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    /// 权限位
+    pub mode: u32,
+    pub len: usize,
+    pub mtime: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Node<'a> {
+    meta: Metadata,
+    data: &'a [u8],
+}
+
+impl Node<'_> {
+    fn dir() -> Self {
+        Self {
+            meta: Metadata {
+                file_type: FileType::Dir,
+                mode: 0o755,
+                len: 0,
+                mtime: 0,
+            },
+            data: &[],
+        }
+    }
+}
+
+/// 以不带开头 `/` 的路径为键，根目录为空字符串
+#[derive(Debug, Clone)]
+pub struct RamFs<'a> {
+    nodes: BTreeMap<String, Node<'a>>,
+}
+
+impl<'a> RamFs<'a> {
+    /// 解包 newc 格式的 cpio 归档，只保留目录、普通文件和符号链接
+    pub fn from_cpio(data: &'a [u8]) -> Result<Self, FsError> {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(String::new(), Node::dir());
+
+        for entry in Reader::new(data) {
+            let entry = entry?;
+            let file_type = if entry.is_dir() {
+                FileType::Dir
+            } else if entry.is_file() {
+                FileType::File
+            } else if entry.is_symlink() {
+                FileType::Symlink
+            } else {
+                continue;
+            };
+
+            let path = normalize(entry.name);
+            if path.is_empty() {
+                continue;
+            }
+            // 归档中可以省略父目录
+            let mut parent = path.as_str();
+            while let Some((p, _)) = parent.rsplit_once('/') {
+                nodes.entry(p.to_string()).or_insert_with(Node::dir);
+                parent = p;
+            }
+
+            let data = if file_type == FileType::Dir {
+                &[]
+            } else {
+                entry.data
+            };
+            nodes.insert(
+                path,
+                Node {
+                    meta: Metadata {
+                        file_type,
+                        mode: entry.mode & 0o7777,
+                        len: data.len(),
+                        mtime: entry.mtime,
+                    },
+                    data,
+                },
+            );
+        }
+
+        Ok(Self { nodes })
+    }
+
+    /// 项数，包括根目录
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() <= 1
+    }
+
+    /// 跟随符号链接，得到规范化的路径
+    fn resolve(&self, path: &str) -> Result<String, FsError> {
+        let mut resolved = String::new();
+        let mut rest: VecDeque<String> = components(path).map(String::from).collect();
+        let mut links = 0;
+
+        while let Some(c) = rest.pop_front() {
+            if c == ".." {
+                let len = resolved.rfind('/').unwrap_or(0);
+                resolved.truncate(len);
+                continue;
+            }
+            if self.nodes[&resolved].meta.file_type != FileType::Dir {
+                return Err(FsError::NotADirectory);
+            }
+            let next = if resolved.is_empty() {
+                c
+            } else {
+                alloc::format!("{resolved}/{c}")
+            };
+            let node = self.nodes.get(&next).ok_or(FsError::NotFound)?;
+            if node.meta.file_type == FileType::Symlink {
+                links += 1;
+                if links > MAX_SYMLINKS {
+                    return Err(FsError::TooManyLinks);
+                }
+                let target = core::str::from_utf8(node.data).map_err(|_| FsError::NotFound)?;
+                if target.starts_with('/') {
+                    resolved.clear();
+                }
+                for c in components(target).rev() {
+                    rest.push_front(c.to_string());
+                }
+            } else {
+                resolved = next;
+            }
+        }
+        Ok(resolved)
+    }
+
+    fn node(&self, path: &str) -> Result<&Node<'a>, FsError> {
+        let path = self.resolve(path)?;
+        Ok(&self.nodes[&path])
+    }
+
+    pub fn metadata(&self, path: &str) -> Result<Metadata, FsError> {
+        self.node(path).map(|n| n.meta)
+    }
+
+    pub fn exists(&self, path: &str) -> bool {
+        self.node(path).is_ok()
+    }
+
+    /// 文件的全部内容
+    pub fn read(&self, path: &str) -> Result<&'a [u8], FsError> {
+        let node = self.node(path)?;
+        match node.meta.file_type {
+            FileType::Dir => Err(FsError::IsADirectory),
+            _ => Ok(node.data),
+        }
+    }
+
+    /// 目录下各项的名称和元数据，按名称排序
+    pub fn read_dir(
+        &self,
+        path: &str,
+    ) -> Result<impl Iterator<Item = (&str, Metadata)> + '_, FsError> {
+        let dir = self.resolve(path)?;
+        if self.nodes[&dir].meta.file_type != FileType::Dir {
+            return Err(FsError::NotADirectory);
+        }
+        let prefix = if dir.is_empty() { dir } else { dir + "/" };
+        let len = prefix.len();
+
+        Ok(self
+            .nodes
+            .range::<str, _>((Bound::Included(prefix.as_str()), Bound::Unbounded))
+            .take_while(move |(k, _)| k.starts_with(prefix.as_str()))
+            .map(move |(k, n)| (&k[len..], n.meta))
+            .filter(|(name, _)| !name.is_empty() && !name.contains('/')))
+    }
+}
+
+fn components(path: &str) -> impl DoubleEndedIterator<Item = &str> {
+    path.split('/').filter(|c| !c.is_empty() && *c != ".")
+}
+
+/// 去掉 `.` 和多余的 `/`，归档中的名称不含 `..`
+fn normalize(path: &str) -> String {
+    let mut out = String::new();
+    for c in components(path) {
+        if !out.is_empty() {
+            out.push('/');
+        }
+        out.push_str(c);
+    }
+    out
+}
+
+/// 解包 initrd，没有 initrd 时什么都不做
+pub fn init() {
+    let Some(initrd) = global_val().platform_info.initrd() else {
+        return;
+    };
+    let data = unsafe {
+        core::slice::from_raw_parts(
+            (initrd.start.raw() + RegionKind::Other.va_offset()) as *const u8,
+            initrd.end - initrd.start,
+        )
+    };
+
+    match RamFs::from_cpio(data) {
+        Ok(fs) => {
+            info!("initramfs: {} entries", fs.len() - 1);
+            RAMFS.call_once(|| fs);
+        }
+        Err(e) => warn!("initramfs: {e}"),
+    }
+}
+
+/// 从 initrd 解包的文件系统
+pub fn ramfs() -> Option<&'static RamFs<'static>> {
+    RAMFS.get()
+}
+
+pub fn read(path: &str) -> Result<&'static [u8], FsError> {
+    ramfs().ok_or(FsError::NotFound)?.read(path)
+}
+
+pub fn metadata(path: &str) -> Result<Metadata, FsError> {
+    ramfs().ok_or(FsError::NotFound)?.metadata(path)
+}
+
+pub fn read_dir(path: &str) -> Result<impl Iterator<Item = (&'static str, Metadata)>, FsError> {
+    ramfs().ok_or(FsError::NotFound)?.read_dir(path)
+}
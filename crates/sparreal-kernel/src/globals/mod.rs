@@ -12,7 +12,7 @@ use log::debug;
 pub use crate::platform::PlatformInfoKind;
 use crate::{
     mem::{self, PhysAddr},
-    platform::{self, CPUHardId, CPUId, cpu_list, fdt::Fdt},
+    platform::{self, CPUHardId, CPUId, FirmwareInfo, cpu_list, fdt::Fdt},
 };
 
 mod once;
@@ -22,7 +22,10 @@ pub(crate) use percpu::*;
 
 pub struct GlobalVal {
     pub platform_info: PlatformInfoKind,
+    pub firmware: FirmwareInfo,
     pub main_memory: Range<PhysAddr>,
+    /// 从主内存划出的 DMA 池
+    pub dma_pool: Range<PhysAddr>,
     percpu: BTreeMap<CPUId, percpu::PerCPU>,
 }
 
@@ -72,12 +75,18 @@ unsafe fn get_mut() -> &'static mut GlobalVal {
 
 /// # Safty
 /// 只能在其他CPU启动前调用
-pub(crate) unsafe fn setup(platform_info: PlatformInfoKind) -> Result<(), &'static str> {
+pub(crate) unsafe fn setup(
+    platform_info: PlatformInfoKind,
+    firmware: FirmwareInfo,
+) -> Result<(), &'static str> {
     let main_memory = platform::memory_main_available(&platform_info)?;
+    let (dma_pool, main_memory) = mem::dma::carve_pool(main_memory);
 
     let g = GlobalVal {
         platform_info,
+        firmware,
         main_memory,
+        dma_pool,
         percpu: Default::default(),
     };
 
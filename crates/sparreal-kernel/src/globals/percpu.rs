@@ -11,8 +11,9 @@ use log::debug;
 use crate::{
     irq,
     mem::{PhysAddr, region::boot_regions},
-    platform::{CPUHardId, CPUId, cpu_hard_id, cpu_list, kstack_size},
+    platform::{CPUHardId, CPUId, cpu_hard_id, cpu_list, kstack_size, page_size},
     platform_if::{MMUImpl, RegionKind},
+    task::TaskControlBlock,
     time::TimerData,
 };
 
@@ -39,6 +40,10 @@ pub struct PerCPU {
     pub irq_chips: irq::CpuIrqChips,
     pub timer: TimerData,
     pub stack: Range<PhysAddr>,
+    /// 正在切出的已结束任务，见 `task::schedule::finish_switch`
+    pub exiting: Option<TaskControlBlock>,
+    /// 本 CPU 正在执行内存不足回收，见 `mem::oom::reclaim`
+    pub in_oom: AtomicBool,
 }
 
 /// 初始化PerCPU
@@ -75,7 +80,7 @@ fn add_cpu(cpu: CPUHardId, idx: usize) {
             region.range.start
         } else {
             let stack =
-                alloc::alloc::alloc(Layout::from_size_align(kstack_size(), 0x1000).unwrap());
+                alloc::alloc::alloc(Layout::from_size_align(kstack_size(), page_size()).unwrap());
             PhysAddr::from(stack as usize - RegionKind::Other.va_offset())
         };
 
@@ -85,6 +90,8 @@ fn add_cpu(cpu: CPUHardId, idx: usize) {
                 irq_chips: Default::default(),
                 timer: Default::default(),
                 stack: stack_bottom..stack_bottom + kstack_size(),
+                exiting: None,
+                in_oom: AtomicBool::new(false),
             },
         );
         (*HARD_TO_SOFT.get()).insert(cpu, id);
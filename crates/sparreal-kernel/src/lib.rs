@@ -6,18 +6,25 @@ extern crate alloc;
 
 pub mod __export;
 pub mod boot;
+pub mod cmdline;
 pub mod globals;
 pub mod io;
 
 pub mod async_std;
 pub mod driver;
+pub mod fs;
 pub mod irq;
+#[cfg(target_os = "none")]
 mod lang_items;
 mod logger;
 pub mod mem;
 pub mod platform;
 pub mod platform_if;
 pub mod prelude;
+#[cfg(feature = "mmu")]
+pub mod process;
+#[cfg(feature = "mmu")]
+pub mod syscall;
 pub mod task;
 pub mod time;
 
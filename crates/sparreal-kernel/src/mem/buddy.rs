@@ -0,0 +1,113 @@
+//! 伙伴分配器，算法与 `buddy_system_allocator::Heap` 相同
+//!
+//! 该 crate 的空闲链表是私有的，统计最大空闲块只能反复试分配，因此在这里保留一份可遍历的实现。
+//! 释放时在同级链表中线性查找伙伴，与上游 `Heap::dealloc` 的开销一致。
+
+use core::{
+    alloc::Layout,
+    cmp::{max, min},
+    ptr::NonNull,
+};
+
+use buddy_system_allocator::linked_list::LinkedList;
+
+pub struct Buddy<const ORDER: usize> {
+    free_list: [LinkedList; ORDER],
+    allocated: usize,
+    total: usize,
+}
+
+impl<const ORDER: usize> Buddy<ORDER> {
+    pub const fn empty() -> Self {
+        Self {
+            free_list: [LinkedList::new(); ORDER],
+            allocated: 0,
+            total: 0,
+        }
+    }
+
+    /// 块的实际大小
+    pub fn block_size(layout: Layout) -> usize {
+        max(
+            layout.size().next_power_of_two(),
+            max(layout.align(), size_of::<usize>()),
+        )
+    }
+
+    /// # Safety
+    /// `[start, end)` 必须是未被使用的可写内存
+    pub unsafe fn add_to_heap(&mut self, start: usize, end: usize) {
+        let start = start.next_multiple_of(size_of::<usize>());
+        let end = end & !(size_of::<usize>() - 1);
+
+        let mut current = start;
+        while current + size_of::<usize>() <= end {
+            // 受起点对齐和剩余长度限制
+            let order = current
+                .trailing_zeros()
+                .min((end - current).ilog2())
+                .min(ORDER as u32 - 1) as usize;
+            unsafe { self.free_list[order].push(current as *mut usize) };
+            self.total += 1 << order;
+            current += 1 << order;
+        }
+    }
+
+    pub fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        let size = Self::block_size(layout);
+        let class = size.trailing_zeros() as usize;
+        let order = (class..ORDER).find(|&i| !self.free_list[i].is_empty())?;
+
+        // 逐级拆分，较低一半留作下一级继续拆分
+        for j in (class + 1..=order).rev() {
+            let block = self.free_list[j].pop()?;
+            unsafe {
+                self.free_list[j - 1].push((block as usize + (1 << (j - 1))) as *mut usize);
+                self.free_list[j - 1].push(block);
+            }
+        }
+
+        let ptr = NonNull::new(self.free_list[class].pop()? as *mut u8)?;
+        self.allocated += size;
+        Some(ptr)
+    }
+
+    pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        let size = Self::block_size(layout);
+        let mut current = ptr.as_ptr() as usize;
+        let mut class = size.trailing_zeros() as usize;
+
+        // 伙伴也空闲时合并到上一级
+        while class < ORDER - 1 {
+            let buddy = current ^ (1 << class);
+            let Some(node) = self.free_list[class]
+                .iter_mut()
+                .find(|n| n.value() as usize == buddy)
+            else {
+                break;
+            };
+            node.pop();
+            current = min(current, buddy);
+            class += 1;
+        }
+        unsafe { self.free_list[class].push(current as *mut usize) };
+        self.allocated -= size;
+    }
+
+    /// 已分配块的总大小
+    pub fn allocated(&self) -> usize {
+        self.allocated
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// 最大的空闲块，即最高的非空一级
+    pub fn largest_free(&self) -> usize {
+        (0..ORDER)
+            .rev()
+            .find(|&i| !self.free_list[i].is_empty())
+            .map_or(0, |i| 1 << i)
+    }
+}
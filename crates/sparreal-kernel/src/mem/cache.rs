@@ -1,28 +1,30 @@
 use core::ptr::NonNull;
 
 use dma_api::Impl;
+use log::error;
 
-use crate::platform_if::{CacheOp, PlatformImpl};
-
-use super::{PhysAddr, VirtAddr, mmu::RegionKind};
+use super::dma;
 
 struct DMAImpl;
 
 impl Impl for DMAImpl {
-    fn map(addr: NonNull<u8>, _size: usize, _direction: dma_api::Direction) -> u64 {
-        let vaddr = VirtAddr::from(addr);
-        let paddr = PhysAddr::new(vaddr.raw() - RegionKind::Other.va_offset());
-        paddr.raw() as _
+    fn map(addr: NonNull<u8>, size: usize, direction: dma_api::Direction) -> u64 {
+        dma::map_single(addr, size, direction).unwrap_or_else(|e| {
+            error!("dma map {addr:p} size {size:#x}: {e}");
+            dma::DMA_MAPPING_ERROR
+        })
     }
 
-    fn unmap(_addr: NonNull<u8>, _size: usize) {}
+    fn unmap(addr: NonNull<u8>, size: usize) {
+        dma::unmap_single(addr, size);
+    }
 
     fn flush(addr: NonNull<u8>, size: usize) {
-        PlatformImpl::dcache_range(CacheOp::Clean, addr.as_ptr() as _, size);
+        dma::sync_for_device(addr, size);
     }
 
     fn invalidate(addr: NonNull<u8>, size: usize) {
-        PlatformImpl::dcache_range(CacheOp::Invalidate, addr.as_ptr() as _, size);
+        dma::sync_for_cpu(addr, size);
     }
 }
 
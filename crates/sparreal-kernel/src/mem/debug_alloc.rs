@@ -0,0 +1,223 @@
+//! 调试分配器：每块前后加红区，释放时填充毒值并放入隔离区延迟归还
+
+use core::{alloc::Layout, ptr::NonNull};
+
+use log::error;
+use spin::Mutex;
+
+use super::track::{AllocRecord, find_record, for_each_live};
+
+const REDZONE: usize = 32;
+const REDZONE_BYTE: u8 = 0xfb;
+const POISON_BYTE: u8 = 0x6b;
+const MAGIC: usize = 0x5aa5_d00d_5aa5_d00d;
+const QUARANTINE_LEN: usize = 256;
+const QUARANTINE_BYTES: usize = 8 * 1024 * 1024;
+
+#[repr(C)]
+struct Header {
+    size: usize,
+    magic: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Freed {
+    user: *mut u8,
+    layout: Layout,
+    record: Option<AllocRecord>,
+}
+
+struct Quarantine {
+    ring: [Option<Freed>; QUARANTINE_LEN],
+    head: usize,
+    bytes: usize,
+}
+
+unsafe impl Send for Quarantine {}
+
+static QUARANTINE: Mutex<Quarantine> = Mutex::new(Quarantine {
+    ring: [None; QUARANTINE_LEN],
+    head: 0,
+    bytes: 0,
+});
+
+#[derive(Debug, Clone, Copy)]
+enum Fault {
+    Header,
+    Underflow(usize),
+    Overflow(usize),
+    UseAfterFree(usize),
+}
+
+/// 用户区之前的字节数：头部 + 红区，按对齐补齐
+fn front(layout: Layout) -> usize {
+    (size_of::<Header>() + REDZONE).next_multiple_of(layout.align().max(align_of::<Header>()))
+}
+
+/// 实际向后端申请的布局
+pub(crate) fn outer(layout: Layout) -> Layout {
+    let align = layout.align().max(align_of::<Header>());
+    Layout::from_size_align(front(layout) + layout.size() + REDZONE, align).unwrap()
+}
+
+/// 写入头部和红区，返回用户指针
+pub(crate) unsafe fn arm(raw: *mut u8, layout: Layout) -> *mut u8 {
+    let front = front(layout);
+    unsafe {
+        let user = raw.add(front);
+        raw.cast::<Header>().write(Header {
+            size: layout.size(),
+            magic: MAGIC,
+        });
+        let hdr = size_of::<Header>();
+        raw.add(hdr).write_bytes(REDZONE_BYTE, front - hdr);
+        user.add(layout.size()).write_bytes(REDZONE_BYTE, REDZONE);
+        user
+    }
+}
+
+fn raw_of(user: *mut u8, layout: Layout) -> *mut u8 {
+    unsafe { user.sub(front(layout)) }
+}
+
+fn find_byte(start: *const u8, len: usize, expect: u8) -> Option<usize> {
+    (0..len).find(|&i| unsafe { start.add(i).read_volatile() } != expect)
+}
+
+fn verify(user: *mut u8, size: usize, align: usize) -> Result<(), Fault> {
+    let layout = Layout::from_size_align(size, align).unwrap();
+    let raw = raw_of(user, layout);
+    let hdr = unsafe { &*raw.cast::<Header>() };
+    if hdr.magic != MAGIC || hdr.size != size {
+        return Err(Fault::Header);
+    }
+    let front = front(layout) - size_of::<Header>();
+    if let Some(i) = find_byte(unsafe { raw.add(size_of::<Header>()) }, front, REDZONE_BYTE) {
+        return Err(Fault::Underflow(front - i));
+    }
+    if let Some(i) = find_byte(unsafe { user.add(size) }, REDZONE, REDZONE_BYTE) {
+        return Err(Fault::Overflow(i));
+    }
+    Ok(())
+}
+
+fn report(fault: Fault, user: *const u8, size: usize, record: Option<AllocRecord>) {
+    match fault {
+        Fault::Header => error!("heap corruption: header of {user:p} ({size} bytes) overwritten"),
+        Fault::Underflow(n) => {
+            error!("heap corruption: write {n} bytes before {user:p} ({size} bytes)")
+        }
+        Fault::Overflow(n) => {
+            error!(
+                "heap corruption: write at offset {} of {user:p} ({size} bytes)",
+                size + n
+            )
+        }
+        Fault::UseAfterFree(n) => {
+            error!("use after free: offset {n} of {user:p} ({size} bytes) written after free")
+        }
+    }
+    match record {
+        Some(r) => {
+            error!(
+                "  allocated #{} tag {:?} at {:?} by {:?}",
+                r.seq,
+                r.tag,
+                r.site.map(|s| (s.file(), s.line())),
+                r.owner
+            );
+            for (i, pc) in r.backtrace().enumerate() {
+                error!("    #{i} {pc:#x}");
+            }
+        }
+        None => error!("  allocation site unknown"),
+    }
+}
+
+/// 释放前检查，红区损坏时报告分配位置并 panic
+pub(crate) fn check_on_free(user: *mut u8, layout: Layout) {
+    if let Err(fault) = verify(user, layout.size(), layout.align()) {
+        report(fault, user, layout.size(), find_record(user));
+        panic!("heap corruption detected at {user:p}");
+    }
+}
+
+/// 毒化后放入隔离区，返回需要真正归还给后端的块
+pub(crate) fn quarantine(user: *mut u8, layout: Layout) -> Option<(NonNull<u8>, Layout)> {
+    unsafe { user.write_bytes(POISON_BYTE, layout.size()) };
+    let freed = Freed {
+        user,
+        layout,
+        record: find_record(user),
+    };
+
+    let evicted = {
+        let mut q = QUARANTINE.lock();
+        let head = q.head;
+        let evicted = q.ring[head].replace(freed);
+        q.head = (head + 1) % QUARANTINE_LEN;
+        q.bytes += layout.size();
+        if let Some(e) = &evicted {
+            q.bytes -= e.layout.size();
+        }
+        evicted
+    };
+
+    let evicted = evicted.or_else(evict_over_budget)?;
+    if !check_poison(&evicted) {
+        panic!("heap corruption detected at {:p}", evicted.user);
+    }
+    Some((
+        NonNull::new(raw_of(evicted.user, evicted.layout)).unwrap(),
+        outer(evicted.layout),
+    ))
+}
+
+/// 超出字节预算时取出最旧的一块
+fn evict_over_budget() -> Option<Freed> {
+    let mut q = QUARANTINE.lock();
+    if q.bytes <= QUARANTINE_BYTES {
+        return None;
+    }
+    let head = q.head;
+    for i in 0..QUARANTINE_LEN {
+        let idx = (head + i) % QUARANTINE_LEN;
+        if let Some(e) = q.ring[idx].take() {
+            q.bytes -= e.layout.size();
+            return Some(e);
+        }
+    }
+    None
+}
+
+fn check_poison(freed: &Freed) -> bool {
+    let size = freed.layout.size();
+    let fault = match find_byte(freed.user, size, POISON_BYTE) {
+        Some(i) => Fault::UseAfterFree(i),
+        None => match verify(freed.user, size, freed.layout.align()) {
+            Ok(()) => return true,
+            Err(f) => f,
+        },
+    };
+    report(fault, freed.user, size, freed.record);
+    false
+}
+
+/// 检查所有存活分配的红区与隔离区的毒值，返回发现的损坏块数
+pub fn check_heap() -> usize {
+    let mut bad = 0;
+    for_each_live(|r| {
+        if let Err(fault) = verify(r.ptr as *mut u8, r.size, r.align) {
+            report(fault, r.ptr as *const u8, r.size, Some(*r));
+            bad += 1;
+        }
+    });
+
+    let q = QUARANTINE.lock();
+    for freed in q.ring.iter().flatten() {
+        if !check_poison(freed) {
+            bad += 1;
+        }
+    }
+    bad
+}
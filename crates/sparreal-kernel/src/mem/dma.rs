@@ -0,0 +1,436 @@
+use core::{
+    alloc::Layout,
+    ops::Range,
+    ptr::NonNull,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
+use dma_api::Direction;
+use log::{debug, warn};
+use spin::Mutex;
+
+use crate::globals::global_val;
+use crate::platform_if::{CacheOp, PlatformImpl};
+
+#[cfg(feature = "mmu")]
+use super::mmu::RegionKind;
+use super::{Address, KernelHeap, PhysAddr, VirtAddr};
+
+const CACHE_LINE: usize = 64;
+
+/// DMA 池的非缓存映射区，物理地址 `p` 映射在 `p + DMA_COHERENT_OFFSET`
+#[cfg(feature = "mmu")]
+pub const DMA_COHERENT_OFFSET: usize = 0xffff_e800_0000_0000;
+
+/// 从主内存低端划出的 DMA 池大小
+pub const DMA_POOL_SIZE: usize = 4 * 1024 * 1024;
+
+/// 流式映射失败时交给驱动的总线地址
+pub const DMA_MAPPING_ERROR: u64 = u64::MAX;
+
+/// 一致性内存与 bounce 缓冲区都从 DMA 池分配，池不在线性区中映射，不会有可缓存的别名
+static POOL: Mutex<KernelHeap> = Mutex::new(KernelHeap::empty());
+
+static DMA_MASK: AtomicU64 = AtomicU64::new(u64::MAX);
+static BOUNCES: Mutex<BTreeMap<usize, Bounce>> = Mutex::new(BTreeMap::new());
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum DmaError {
+    #[error("dma pool exhausted")]
+    NoMemory,
+    #[error("{0} is not addressable by the device")]
+    NotAddressable(PhysAddr),
+    /// 缓冲区与尚未解除的 bounce 映射重叠
+    #[error("{0:#x} is already mapped")]
+    AlreadyMapped(usize),
+}
+
+/// 一段 CPU 物理地址到设备总线地址的线性映射
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DmaRange {
+    pub cpu: usize,
+    pub bus: u64,
+    pub size: u64,
+}
+
+/// 设备视角的总线地址转换表
+///
+/// `None` 表示总线地址等于物理地址，空表表示设备不能做 DMA。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DmaRanges(Option<Vec<DmaRange>>);
+
+impl DmaRanges {
+    pub const fn identity() -> Self {
+        Self(None)
+    }
+
+    /// 没有任何窗口，设备不能访问内存
+    pub const fn unreachable() -> Self {
+        Self(Some(Vec::new()))
+    }
+
+    pub fn new(ranges: Vec<DmaRange>) -> Self {
+        Self(Some(ranges))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.0.is_none()
+    }
+
+    pub fn is_unreachable(&self) -> bool {
+        self.0.as_ref().is_some_and(|r| r.is_empty())
+    }
+
+    pub fn ranges(&self) -> &[DmaRange] {
+        self.0.as_deref().unwrap_or_default()
+    }
+
+    /// `[paddr, paddr + size)` 必须完整落在同一窗口内
+    pub fn to_bus(&self, paddr: PhysAddr, size: usize) -> Option<u64> {
+        if self.is_identity() {
+            return Some(paddr.raw() as u64);
+        }
+        let cpu = paddr.raw() as u64;
+        self.ranges().iter().find_map(|r| {
+            let off = cpu.checked_sub(r.cpu as u64)?;
+            (off + size as u64 <= r.size).then_some(r.bus + off)
+        })
+    }
+
+    pub fn to_cpu(&self, bus: u64) -> Option<PhysAddr> {
+        if self.is_identity() {
+            return Some(PhysAddr::new(bus as usize));
+        }
+        self.ranges().iter().find_map(|r| {
+            let off = bus.checked_sub(r.bus)?;
+            (off < r.size).then(|| PhysAddr::new(r.cpu + off as usize))
+        })
+    }
+
+    /// 填充了总线地址的 [`Address`]
+    pub fn address(&self, paddr: PhysAddr, virt: Option<*mut u8>) -> Address {
+        Address::new(paddr.raw(), virt, self.to_bus(paddr, 0))
+    }
+}
+
+/// 某个设备的 DMA 寻址能力：它所在总线的地址转换和地址掩码
+///
+/// 转换来自设备树中该设备各级总线的 `dma-ranges`，见
+/// [`GetDmaRanges`](crate::platform::fdt::GetDmaRanges)。
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmaDevice {
+    pub ranges: DmaRanges,
+    /// 设备可寻址的最大总线地址
+    pub mask: u64,
+}
+
+impl DmaDevice {
+    pub fn new(ranges: DmaRanges, mask: u64) -> Self {
+        Self { ranges, mask }
+    }
+
+    /// 设备无法访问该物理区间时返回 `None`
+    pub fn phys_to_bus(&self, paddr: PhysAddr, size: usize) -> Option<u64> {
+        let bus = self.ranges.to_bus(paddr, size)?;
+        let addressable = size == 0
+            || bus
+                .checked_add(size as u64 - 1)
+                .is_some_and(|end| end <= self.mask);
+        addressable.then_some(bus)
+    }
+
+    /// 从 DMA 池分配清零的一致性内存，池已用尽或设备无法寻址时返回 `None`
+    pub fn alloc_coherent(&self, size: usize) -> Option<DmaCoherent> {
+        #[cfg(feature = "mmu")]
+        let page = super::mmu::page_size();
+        #[cfg(not(feature = "mmu"))]
+        let page = 0x1000;
+
+        let layout = Layout::from_size_align(size.max(1).next_multiple_of(page), page).ok()?;
+        let ptr = POOL.lock().alloc(layout).ok()?;
+        let Some(bus) = self.phys_to_bus(virt_to_phys(ptr), layout.size()) else {
+            POOL.lock().dealloc(ptr, layout);
+            return None;
+        };
+        unsafe { ptr.as_ptr().write_bytes(0, layout.size()) };
+
+        Some(DmaCoherent { ptr, bus, layout })
+    }
+
+    /// 流式 DMA 映射，设备无法寻址时经由 DMA 池中的 bounce 缓冲区中转
+    pub fn map_single(
+        &self,
+        addr: NonNull<u8>,
+        size: usize,
+        direction: Direction,
+    ) -> Result<u64, DmaError> {
+        let start = addr.as_ptr() as usize;
+
+        if let Some(bus) = self.phys_to_bus(virt_to_phys(addr), size) {
+            let op = match direction {
+                Direction::ToDevice => CacheOp::Clean,
+                Direction::FromDevice | Direction::Bidirectional => CacheOp::CleanAndInvalidate,
+            };
+            dcache(op, start, size);
+            return Ok(bus);
+        }
+
+        let layout = Layout::from_size_align(size.max(1), CACHE_LINE).unwrap();
+        let buf = POOL.lock().alloc(layout).map_err(|_| DmaError::NoMemory)?;
+        let bounce_paddr = virt_to_phys(buf);
+        let Some(bounce_bus) = self.phys_to_bus(bounce_paddr, size) else {
+            POOL.lock().dealloc(buf, layout);
+            return Err(DmaError::NotAddressable(bounce_paddr));
+        };
+
+        let bounce = Bounce {
+            buf,
+            size,
+            layout,
+            direction,
+        };
+        if matches!(direction, Direction::ToDevice | Direction::Bidirectional) {
+            bounce.sync_to_device(start, start, size);
+        }
+
+        let mut bounces = BOUNCES.lock();
+        let overlapped = bounces
+            .range(..start + size.max(1))
+            .next_back()
+            .is_some_and(|(&orig, b)| start < orig + b.size.max(1));
+        if overlapped {
+            drop(bounces);
+            POOL.lock().dealloc(buf, layout);
+            return Err(DmaError::AlreadyMapped(start));
+        }
+        bounces.insert(start, bounce);
+        Ok(bounce_bus)
+    }
+
+    pub fn unmap_single(&self, addr: NonNull<u8>, size: usize) {
+        unmap_single(addr, size);
+    }
+
+    pub fn sync_for_device(&self, addr: NonNull<u8>, size: usize) {
+        sync_for_device(addr, size);
+    }
+
+    pub fn sync_for_cpu(&self, addr: NonNull<u8>, size: usize) {
+        sync_for_cpu(addr, size);
+    }
+}
+
+/// `dma_api` 不区分设备，按总线地址等于物理地址处理，需要转换的设备应使用 [`DmaDevice`]
+fn default_device() -> DmaDevice {
+    DmaDevice::new(DmaRanges::identity(), dma_mask())
+}
+
+/// 默认设备可寻址的最大总线地址
+pub fn dma_mask() -> u64 {
+    DMA_MASK.load(Ordering::Relaxed)
+}
+
+pub fn set_dma_mask(mask: u64) {
+    DMA_MASK.store(mask, Ordering::Relaxed);
+}
+
+/// 默认设备无法访问该物理区间时返回 `None`
+pub fn phys_to_bus(paddr: PhysAddr, size: usize) -> Option<u64> {
+    default_device().phys_to_bus(paddr, size)
+}
+
+/// 从主内存低端划出 DMA 池，返回 `(池, 剩余的主内存)`，主内存太小时池为空
+pub(crate) fn carve_pool(main: Range<PhysAddr>) -> (Range<PhysAddr>, Range<PhysAddr>) {
+    if main.end - main.start < DMA_POOL_SIZE * 4 {
+        return (main.start..main.start, main);
+    }
+    let end = main.start + DMA_POOL_SIZE;
+    (main.start..end, end..main.end)
+}
+
+fn pool_va(paddr: PhysAddr) -> usize {
+    #[cfg(feature = "mmu")]
+    {
+        paddr.raw() + DMA_COHERENT_OFFSET
+    }
+    #[cfg(not(feature = "mmu"))]
+    {
+        paddr.raw()
+    }
+}
+
+/// 以非缓存方式映射 DMA 池，交给池分配器
+pub(crate) fn init_pool() {
+    let pool = global_val().dma_pool.clone();
+    let size = pool.end - pool.start;
+    if size == 0 {
+        warn!("no memory for dma pool");
+        return;
+    }
+
+    #[cfg(feature = "mmu")]
+    {
+        use page_table_generic::{AccessSetting, CacheSetting};
+
+        if let Err(e) = super::mmu::map_kernel(
+            pool_va(pool.start),
+            pool.start.raw(),
+            size,
+            AccessSetting::Read | AccessSetting::Write,
+            CacheSetting::NonCache,
+        ) {
+            warn!("dma pool map failed: {e}");
+            return;
+        }
+    }
+
+    let start = pool_va(pool.start);
+    unsafe { POOL.lock().add_to_heap(start, start + size) };
+    debug!("dma pool [{}, {})", pool.start, pool.end);
+}
+
+fn virt_to_phys(addr: NonNull<u8>) -> PhysAddr {
+    let vaddr = VirtAddr::from(addr).raw();
+    let pool = &global_val().dma_pool;
+    let pool_start = pool_va(pool.start);
+    if (pool_start..pool_start + (pool.end - pool.start)).contains(&vaddr) {
+        return pool.start + (vaddr - pool_start);
+    }
+    #[cfg(feature = "mmu")]
+    {
+        PhysAddr::new(vaddr - RegionKind::Other.va_offset())
+    }
+    #[cfg(not(feature = "mmu"))]
+    {
+        PhysAddr::new(vaddr)
+    }
+}
+
+fn dcache(op: CacheOp, addr: usize, size: usize) {
+    PlatformImpl::dcache_range(op, addr, size);
+}
+
+/// 一致性 DMA 内存，CPU 侧以非缓存方式访问，drop 时释放
+pub struct DmaCoherent {
+    ptr: NonNull<u8>,
+    bus: u64,
+    layout: Layout,
+}
+
+unsafe impl Send for DmaCoherent {}
+unsafe impl Sync for DmaCoherent {}
+
+impl DmaCoherent {
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.ptr
+    }
+
+    pub fn bus_addr(&self) -> u64 {
+        self.bus
+    }
+
+    pub fn size(&self) -> usize {
+        self.layout.size()
+    }
+}
+
+impl Drop for DmaCoherent {
+    fn drop(&mut self) {
+        POOL.lock().dealloc(self.ptr, self.layout);
+    }
+}
+
+/// 为默认设备分配一致性内存，见 [`DmaDevice::alloc_coherent`]
+pub fn dma_alloc_coherent(size: usize) -> Option<DmaCoherent> {
+    default_device().alloc_coherent(size)
+}
+
+struct Bounce {
+    buf: NonNull<u8>,
+    size: usize,
+    layout: Layout,
+    direction: Direction,
+}
+
+unsafe impl Send for Bounce {}
+
+impl Bounce {
+    /// 原缓冲区 `[orig, orig + size)` 中从 `addr` 开始的一段在 bounce 中的位置
+    fn window(&self, orig: usize, addr: usize, size: usize) -> (usize, usize) {
+        let off = addr - orig;
+        (off, size.min(self.size - off))
+    }
+
+    // bounce 缓冲区在非缓存的 DMA 池中，复制即可，不需要维护缓存
+    fn sync_to_device(&self, orig: usize, addr: usize, size: usize) {
+        let (off, len) = self.window(orig, addr, size);
+        unsafe {
+            let dst = self.buf.as_ptr().add(off);
+            core::ptr::copy_nonoverlapping(addr as *const u8, dst, len);
+        }
+    }
+
+    fn sync_for_cpu(&self, orig: usize, addr: usize, size: usize) {
+        let (off, len) = self.window(orig, addr, size);
+        unsafe {
+            let src = self.buf.as_ptr().add(off);
+            core::ptr::copy_nonoverlapping(src, addr as *mut u8, len);
+        }
+    }
+}
+
+/// 查找覆盖 `addr` 的 bounce 记录
+fn with_bounce<R>(addr: usize, f: impl FnOnce(usize, &Bounce) -> R) -> Option<R> {
+    let bounces = BOUNCES.lock();
+    let (&orig, b) = bounces.range(..=addr).next_back()?;
+    if addr < orig + b.size {
+        Some(f(orig, b))
+    } else {
+        None
+    }
+}
+
+pub(crate) fn map_single(
+    addr: NonNull<u8>,
+    size: usize,
+    direction: Direction,
+) -> Result<u64, DmaError> {
+    default_device().map_single(addr, size, direction)
+}
+
+pub(crate) fn unmap_single(addr: NonNull<u8>, size: usize) {
+    let start = addr.as_ptr() as usize;
+    let Some(bounce) = BOUNCES.lock().remove(&start) else {
+        return;
+    };
+    if bounce.size != size {
+        warn!(
+            "dma unmap {:p} size {:#x} differs from mapped {:#x}",
+            addr, size, bounce.size
+        );
+    }
+    if matches!(
+        bounce.direction,
+        Direction::FromDevice | Direction::Bidirectional
+    ) {
+        bounce.sync_for_cpu(start, start, bounce.size);
+    }
+    POOL.lock().dealloc(bounce.buf, bounce.layout);
+}
+
+/// CPU 写入后交给设备
+pub(crate) fn sync_for_device(addr: NonNull<u8>, size: usize) {
+    let start = addr.as_ptr() as usize;
+    if with_bounce(start, |orig, b| b.sync_to_device(orig, start, size)).is_none() {
+        dcache(CacheOp::Clean, start, size);
+    }
+}
+
+/// 设备写入后交给 CPU
+pub(crate) fn sync_for_cpu(addr: NonNull<u8>, size: usize) {
+    let start = addr.as_ptr() as usize;
+    if with_bounce(start, |orig, b| b.sync_for_cpu(orig, start, size)).is_none() {
+        dcache(CacheOp::Invalidate, start, size);
+    }
+}
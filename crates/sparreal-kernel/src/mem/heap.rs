@@ -0,0 +1,183 @@
+use core::{alloc::Layout, ptr::NonNull};
+
+#[cfg(not(feature = "tlsf"))]
+type Backend = super::buddy::Buddy<32>;
+
+/// 一级 28 档覆盖到 `GRANULARITY << 28`，二级 32 档
+#[cfg(feature = "tlsf")]
+type Backend = rlsf::Tlsf<'static, u32, u32, 28, 32>;
+
+/// 内核堆后端，默认伙伴分配器，`tlsf` feature 下为 O(1) 的 TLSF
+pub struct KernelHeap {
+    inner: Backend,
+    /// 已插入 TLSF 的内存池，统计时遍历其中的块
+    #[cfg(feature = "tlsf")]
+    pools: Option<NonNull<Pool>>,
+    total: usize,
+    used: usize,
+    peak: usize,
+    failures: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeapStats {
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+    /// `used` 的历史最大值
+    pub peak: usize,
+    pub largest_free: usize,
+    pub failures: usize,
+}
+
+/// 池头放在池内存的开头，其后是交给 TLSF 的部分
+#[cfg(feature = "tlsf")]
+struct Pool {
+    next: Option<NonNull<Pool>>,
+    block: NonNull<[u8]>,
+}
+
+unsafe impl Send for KernelHeap {}
+
+impl KernelHeap {
+    pub const fn empty() -> Self {
+        Self {
+            #[cfg(not(feature = "tlsf"))]
+            inner: super::buddy::Buddy::empty(),
+            #[cfg(feature = "tlsf")]
+            inner: rlsf::Tlsf::new(),
+            #[cfg(feature = "tlsf")]
+            pools: None,
+            total: 0,
+            used: 0,
+            peak: 0,
+            failures: 0,
+        }
+    }
+
+    /// # Safety
+    /// `[start, end)` 必须是未被使用的可写内存
+    pub unsafe fn add_to_heap(&mut self, start: usize, end: usize) {
+        #[cfg(not(feature = "tlsf"))]
+        unsafe {
+            let before = self.inner.total();
+            self.inner.add_to_heap(start, end);
+            self.total += self.inner.total() - before;
+        };
+
+        #[cfg(feature = "tlsf")]
+        unsafe {
+            let hdr = start.next_multiple_of(align_of::<Pool>());
+            let data = hdr + size_of::<Pool>();
+            if data >= end {
+                return;
+            }
+            let block = core::ptr::slice_from_raw_parts_mut(data as *mut u8, end - data);
+            let Some(len) = self
+                .inner
+                .insert_free_block_ptr(NonNull::new_unchecked(block))
+            else {
+                return;
+            };
+            let block = core::ptr::slice_from_raw_parts_mut(data as *mut u8, len.get());
+            let pool = hdr as *mut Pool;
+            pool.write(Pool {
+                next: self.pools,
+                block: NonNull::new_unchecked(block),
+            });
+            self.pools = Some(NonNull::new_unchecked(pool));
+            self.total += len.get();
+        }
+    }
+
+    #[allow(clippy::result_unit_err)]
+    pub fn alloc(&mut self, layout: Layout) -> Result<NonNull<u8>, ()> {
+        #[cfg(not(feature = "tlsf"))]
+        let res = self.inner.alloc(layout).ok_or(());
+
+        #[cfg(feature = "tlsf")]
+        let res = self.inner.allocate(layout).ok_or(());
+
+        match res {
+            Ok(ptr) => {
+                self.used += unsafe { Self::footprint(ptr, layout) };
+                self.peak = self.peak.max(self.used);
+            }
+            Err(_) => self.failures += 1,
+        }
+        res
+    }
+
+    pub fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        self.used -= unsafe { Self::footprint(ptr, layout) };
+
+        #[cfg(not(feature = "tlsf"))]
+        self.inner.dealloc(ptr, layout);
+
+        #[cfg(feature = "tlsf")]
+        unsafe {
+            self.inner.deallocate(ptr, layout.align())
+        };
+    }
+
+    /// 分配占用的整块大小，包括块头和对齐填充
+    ///
+    /// # Safety
+    /// `ptr` 是以 `layout` 从本堆分配且尚未释放的内存
+    unsafe fn footprint(ptr: NonNull<u8>, layout: Layout) -> usize {
+        #[cfg(not(feature = "tlsf"))]
+        {
+            let _ = ptr;
+            Backend::block_size(layout)
+        }
+
+        #[cfg(feature = "tlsf")]
+        unsafe {
+            use rlsf::GRANULARITY;
+
+            // 块头紧挨 payload；对齐不小于 GRANULARITY 时中间有填充，payload 前一个字记录块头地址
+            let payload = ptr.as_ptr() as usize;
+            let hdr = if layout.align() < GRANULARITY {
+                payload - GRANULARITY / 2
+            } else {
+                *(payload as *const usize).sub(1)
+            };
+            payload + Backend::allocation_usable_size(ptr) - hdr
+        }
+    }
+
+    /// 遍历空闲链表或池中的块，不做任何分配
+    fn largest_free(&self) -> usize {
+        #[cfg(not(feature = "tlsf"))]
+        {
+            self.inner.largest_free()
+        }
+
+        #[cfg(feature = "tlsf")]
+        {
+            let mut largest = 0;
+            let mut next = self.pools;
+            while let Some(pool) = next {
+                let pool = unsafe { pool.as_ref() };
+                for block in unsafe { self.inner.iter_blocks(pool.block) } {
+                    if !block.is_occupied() {
+                        largest = largest.max(block.max_payload_size());
+                    }
+                }
+                next = pool.next;
+            }
+            largest
+        }
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            total: self.total,
+            used: self.used,
+            free: self.total - self.used,
+            peak: self.peak,
+            largest_free: self.largest_free(),
+            failures: self.failures,
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use core::{fmt::Debug, ptr::NonNull};
+
+use page_table_generic::{CacheSetting, err::PagingError};
+
+use super::PhysAddr;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum IoMapError {
+    #[error("iomap size is zero")]
+    ZeroSize,
+    #[error("{paddr} already mapped as {existing:?}, requested {requested:?}")]
+    AttrConflict {
+        paddr: PhysAddr,
+        existing: CacheSetting,
+        requested: CacheSetting,
+    },
+    #[error("page table: {0}")]
+    Paging(#[from] PagingError),
+}
+
+/// 设备内存映射句柄，drop 时解除映射
+pub struct IoMem {
+    ptr: NonNull<u8>,
+    paddr: PhysAddr,
+    size: usize,
+    tracked: bool,
+}
+
+unsafe impl Send for IoMem {}
+unsafe impl Sync for IoMem {}
+
+impl IoMem {
+    pub(crate) fn new(ptr: NonNull<u8>, paddr: PhysAddr, size: usize, tracked: bool) -> Self {
+        Self {
+            ptr,
+            paddr,
+            size,
+            tracked,
+        }
+    }
+
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        self.ptr
+    }
+
+    pub fn paddr(&self) -> PhysAddr {
+        self.paddr
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// 放弃句柄，映射保持到系统关闭
+    pub fn leak(self) -> NonNull<u8> {
+        let ptr = self.ptr;
+        core::mem::forget(self);
+        ptr
+    }
+}
+
+impl Debug for IoMem {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(
+            f,
+            "IoMem [{}, {}) -> {:p}",
+            self.paddr,
+            self.paddr + self.size,
+            self.ptr
+        )
+    }
+}
+
+impl Drop for IoMem {
+    fn drop(&mut self) {
+        if !self.tracked {
+            return;
+        }
+        #[cfg(feature = "mmu")]
+        super::mmu::iounmap(self.paddr, self.size);
+    }
+}
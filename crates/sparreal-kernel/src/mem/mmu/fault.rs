@@ -0,0 +1,170 @@
+//! 缺页处理：在登记的惰性区域内按需分配并映射物理页
+
+use core::{alloc::Layout, ops::Range, ptr::NonNull};
+
+use alloc::collections::btree_map::BTreeMap;
+use log::{debug, error};
+use page_table_generic::err::PagingError;
+use spin::Mutex;
+
+use super::{AccessSetting, CacheSetting, RegionKind, map_kernel, page_size, unmap_kernel, walk};
+use crate::mem::{ALLOCATOR, VirtAddr, heap_alloc};
+
+/// 惰性区域使用的虚拟地址窗口
+const LAZY_BASE: usize = 0xffff_e400_0000_0000;
+const LAZY_SIZE: usize = 0x100_0000_0000;
+
+static REGIONS: Mutex<BTreeMap<usize, LazyRegion>> = Mutex::new(BTreeMap::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultAccess {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PageFault {
+    pub vaddr: VirtAddr,
+    pub access: FaultAccess,
+    /// 来自 EL0
+    pub user: bool,
+    pub pc: usize,
+}
+
+struct LazyRegion {
+    name: &'static str,
+    range: Range<usize>,
+    access: AccessSetting,
+    /// 已映射页的虚拟地址 -> 分配到的内核地址
+    pages: BTreeMap<usize, usize>,
+}
+
+impl LazyRegion {
+    fn allows(&self, access: FaultAccess) -> bool {
+        match access {
+            FaultAccess::Read => self.access.contains(AccessSetting::Read),
+            FaultAccess::Write => self.access.contains(AccessSetting::Write),
+            FaultAccess::Execute => self.access.contains(AccessSetting::Execute),
+        }
+    }
+}
+
+/// 预留 `size` 字节的惰性区域，首次访问时才分配物理页
+///
+/// 区域之间至少隔一页不映射的保护页，向下增长的栈越界会直接报错。
+pub fn reserve_lazy(
+    name: &'static str,
+    size: usize,
+    access: AccessSetting,
+) -> Result<Range<VirtAddr>, PagingError> {
+    let page_size = page_size();
+    let size = size.next_multiple_of(page_size);
+    let mut regions = REGIONS.lock();
+
+    // 首次适配，起点前留出保护页
+    let mut start = LAZY_BASE + page_size;
+    for r in regions.values() {
+        if start + size + page_size <= r.range.start {
+            break;
+        }
+        start = r.range.end + page_size;
+    }
+    if start + size > LAZY_BASE + LAZY_SIZE {
+        return Err(PagingError::NoMemory);
+    }
+
+    debug!("lazy region {name} [{:#x}, {:#x})", start, start + size);
+    regions.insert(
+        start,
+        LazyRegion {
+            name,
+            range: start..start + size,
+            access,
+            pages: BTreeMap::new(),
+        },
+    );
+    Ok(VirtAddr::from(start)..VirtAddr::from(start + size))
+}
+
+/// 释放 [`reserve_lazy`] 得到的区域及其已分配的页
+pub fn release_lazy(start: VirtAddr) {
+    let Some(region) = REGIONS.lock().remove(&start.raw()) else {
+        return;
+    };
+    let layout = page_layout();
+    for (va, page) in region.pages {
+        unmap_kernel(va, layout.size());
+        ALLOCATOR
+            .inner
+            .lock()
+            .dealloc(unsafe { NonNull::new_unchecked(page as *mut u8) }, layout);
+    }
+}
+
+/// 惰性区域中已分配的字节数
+pub fn lazy_resident(start: VirtAddr) -> Option<usize> {
+    let regions = REGIONS.lock();
+    let region = regions.get(&start.raw())?;
+    Some(region.pages.len() * page_size())
+}
+
+fn page_layout() -> Layout {
+    let page_size = page_size();
+    Layout::from_size_align(page_size, page_size).unwrap()
+}
+
+/// 处理缺页，返回 `true` 表示已映射，可以重新执行出错指令
+pub fn handle_page_fault(fault: &PageFault) -> bool {
+    let layout = page_layout();
+    let va = fault.vaddr.raw() & !(layout.size() - 1);
+
+    let mut regions = REGIONS.lock();
+    let Some((_, region)) = regions.range_mut(..=va).next_back() else {
+        return false;
+    };
+    if !region.range.contains(&va) || fault.user || !region.allows(fault.access) {
+        return false;
+    }
+    if region.pages.contains_key(&va) {
+        // 其他 CPU 已经映射，或是权限错误
+        return walk(fault.vaddr).last().is_some_and(|s| s.pte.valid());
+    }
+
+    let Some(page) = heap_alloc(layout) else {
+        error!("no memory for lazy region {}", region.name);
+        return false;
+    };
+    unsafe { page.as_ptr().write_bytes(0, layout.size()) };
+    let paddr = page.as_ptr() as usize - RegionKind::Other.va_offset();
+
+    if let Err(e) = map_kernel(
+        va,
+        paddr,
+        layout.size(),
+        region.access,
+        CacheSetting::Normal,
+    ) {
+        error!("map lazy page {va:#x} failed: {e:?}");
+        ALLOCATOR.inner.lock().dealloc(page, layout);
+        return false;
+    }
+    region.pages.insert(va, page.as_ptr() as usize);
+    true
+}
+
+/// 地址所在或紧邻其上的惰性区域，用于错误信息
+pub fn describe_fault_addr(vaddr: VirtAddr) -> Option<(&'static str, bool)> {
+    let page_size = page_size();
+    let va = vaddr.raw();
+    let regions = REGIONS.lock();
+    regions.values().find_map(|r| {
+        if r.range.contains(&va) {
+            Some((r.name, false))
+        } else if va < r.range.start && va + page_size >= r.range.start {
+            Some((r.name, true))
+        } else {
+            None
+        }
+    })
+}
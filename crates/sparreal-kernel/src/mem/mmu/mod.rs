@@ -21,13 +21,25 @@ use crate::{
     platform_if::{MMUImpl, PlatformImpl},
 };
 
+mod fault;
 mod paging;
+mod state;
+mod walker;
 
+pub use fault::*;
 pub use paging::init_table;
-pub use paging::iomap;
+pub use paging::{PTEImpl, iomap, iounmap};
+pub(crate) use paging::{map_kernel, map_table, new_table, release_table, unmap_kernel};
+pub use state::*;
+pub use walker::*;
 
 pub const LINER_OFFSET: usize = 0xffff_f000_0000_0000;
+/// 随机偏移的粒度，保持 2M 块映射可用
+pub const KASLR_ALIGN: usize = 0x20_0000;
+/// 随机偏移的上限
+pub const KASLR_RANGE: usize = 0x80_0000_0000;
 static TEXT_OFFSET: OnceStatic<usize> = OnceStatic::new(0);
+static KASLR_OFFSET: OnceStatic<usize> = OnceStatic::new(0);
 static IS_MMU_ENABLED: OnceStatic<bool> = OnceStatic::new(false);
 
 pub fn set_mmu_enabled() {
@@ -53,6 +65,25 @@ pub fn get_text_va_offset() -> usize {
     *TEXT_OFFSET.get_ref()
 }
 
+/// 由种子得到按 [`KASLR_ALIGN`] 对齐、小于 [`KASLR_RANGE`] 的偏移
+pub fn kaslr_offset_from_seed(seed: u64) -> usize {
+    let slots = (KASLR_RANGE / KASLR_ALIGN) as u64;
+    (seed % slots) as usize * KASLR_ALIGN
+}
+
+/// 设置内核随机偏移，线性映射整体上移该值，镜像的偏移已含在 text 偏移中.
+///
+/// # Safety
+///
+/// 应在建立页表前执行
+pub unsafe fn set_kaslr_offset(offset: usize) {
+    unsafe { KASLR_OFFSET.set(offset) };
+}
+
+pub fn kaslr_offset() -> usize {
+    *KASLR_OFFSET.get_ref()
+}
+
 struct PageHeap(Heap<32>);
 
 impl page_table_generic::Access for PageHeap {
@@ -140,7 +171,7 @@ impl RegionKind {
         match self {
             RegionKind::KImage => get_text_va_offset(),
             RegionKind::Stack => STACK_BOTTOM - globals::cpu_global().stack.start.raw(),
-            RegionKind::Other => LINER_OFFSET,
+            RegionKind::Other => LINER_OFFSET + kaslr_offset(),
         }
     }
 }
@@ -166,6 +197,7 @@ pub fn new_boot_table() -> Result<usize, &'static str> {
     let mut table =
         PageTableRef::create_empty(&mut access).map_err(|_| "page table allocator no memory")?;
 
+    // 恒等映射：开启 MMU 后跳转到高地址前仍在这里执行
     for memory in platform::phys_memorys() {
         let region = BootRegion::new(
             memory,
@@ -184,7 +216,7 @@ pub fn new_boot_table() -> Result<usize, &'static str> {
     let main_memory = BootRegion::new(
         global_val().main_memory.clone(),
         c"main memory",
-        AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute,
+        AccessSetting::Read | AccessSetting::Write,
         CacheSetting::Normal,
         RegionKind::Other,
     );
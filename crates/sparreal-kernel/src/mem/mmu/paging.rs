@@ -1,12 +1,13 @@
 use core::sync::atomic::{Ordering, fence};
 
+use alloc::{collections::btree_map::BTreeMap, vec::Vec};
 use log::*;
 use page_table_generic::{Access, PTEArch, PTEGeneric};
-use spin::MutexGuard;
+use spin::{Mutex, MutexGuard};
 
 use crate::{
     globals::global_val,
-    mem::{ALLOCATOR, PhysAddr, VirtAddr},
+    mem::{ALLOCATOR, IoMapError, IoMem, KernelHeap, PhysAddr, VirtAddr},
 };
 
 use super::*;
@@ -41,7 +42,7 @@ impl PTEArch for PTEImpl {
     }
 }
 
-struct HeapGuard<'a>(MutexGuard<'a, Heap<32>>);
+struct HeapGuard<'a>(MutexGuard<'a, KernelHeap>);
 
 impl Access for HeapGuard<'_> {
     fn va_offset(&self) -> usize {
@@ -87,6 +88,7 @@ pub fn init_table() {
                         &mut access,
                     )
                     .unwrap();
+                track_dirty(table.paddr(), vaddr.raw(), size);
             }
 
             drop(access);
@@ -95,35 +97,304 @@ pub fn init_table() {
             debug!("Kernel table -> {:#x}", table.paddr());
             set_kernel_table(table.paddr());
         }
-        drop(regions);
+        // 启动时的恒等映射所在内存已交给堆，TTBR0 换成空表，直到加载第一个用户地址空间
+        set_user_table(crate::process::empty_root());
         flush_tlb_all();
     };
+    check_wx(&regions);
 }
 
-pub fn iomap(paddr: PhysAddr, size: usize) -> NonNull<u8> {
+/// 从当前页表核对各区域权限，内核段须与声明一致，任何区域都不能同时可写可执行；
+/// `TTBR0` 中不能再有映射
+fn check_wx(regions: &[BootRegion]) {
+    let page_bits = page_size().trailing_zeros() as usize;
+    let index_bits = page_bits - size_of::<usize>().trailing_zeros() as usize;
+    let mask = AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute;
+    let mut bad = 0;
+
+    for region in regions {
+        let va_offset = region.kind.va_offset();
+        let exact = !matches!(region.kind, RegionKind::Other);
+        let end = region.range.end.raw() + va_offset;
+        let mut va = region.range.start.raw() + va_offset;
+
+        while va < end {
+            let steps = walk(va.into());
+            let step = steps.last().unwrap();
+            let span = 1usize << (page_bits + (step.level - 1) * index_bits);
+
+            let access = step.pte.setting.privilege_access & mask;
+            if !step.pte.valid() {
+                error!("W^X: {} {va:#x} not mapped", region.name());
+                bad += 1;
+            } else if access.contains(AccessSetting::Write | AccessSetting::Execute)
+                || (exact && access != region.access & mask)
+            {
+                error!(
+                    "W^X: {} {va:#x} mapped {}, expect {}",
+                    region.name(),
+                    rwx(access),
+                    rwx(region.access)
+                );
+                bad += 1;
+            }
+            va = (va & !(span - 1)) + span;
+        }
+    }
+
+    user_table().for_each_region(|r| {
+        error!("W^X: TTBR0 still maps {r:?}");
+        bad += 1;
+    });
+
+    if bad > 0 {
+        panic!("{bad} kernel mappings violate W^X");
+    }
+    debug!("W^X check passed");
+}
+
+struct IoPage {
+    refs: usize,
+    cache: CacheSetting,
+    /// 映射由 `iomap` 建立，引用归零时才解除
+    owned: bool,
+}
+
+static IO_PAGES: Mutex<BTreeMap<usize, IoPage>> = Mutex::new(BTreeMap::new());
+
+pub fn iomap(paddr: PhysAddr, size: usize, cache: CacheSetting) -> Result<IoMem, IoMapError> {
+    let page_size = page_size();
+    let start = paddr.align_down(page_size);
+    let end = (paddr + size).align_up(page_size);
+    let va_offset = RegionKind::Other.va_offset();
+    let vaddr = NonNull::new((paddr.raw() + va_offset) as *mut u8).unwrap();
+
+    // 内存已在线性区以 Normal 映射，不能再以其他属性建立别名
+    let mut banks = platform::phys_memorys();
+    banks.retain(|ram| ram.start < end && start < ram.end);
+    if !banks.is_empty() {
+        banks.sort_unstable_by_key(|ram| ram.start.raw());
+        // 相邻的内存区间合并后须完整覆盖请求
+        let mut covered = start;
+        for ram in &banks {
+            if ram.start > covered {
+                break;
+            }
+            if ram.end > covered {
+                covered = ram.end;
+            }
+        }
+        if covered < end || cache != CacheSetting::Normal {
+            let first = banks[0].start;
+            return Err(IoMapError::AttrConflict {
+                paddr: if first > start { first } else { start },
+                existing: CacheSetting::Normal,
+                requested: cache,
+            });
+        }
+        return Ok(IoMem::new(vaddr, paddr, size, false));
+    }
+
+    let mut pages = IO_PAGES.lock();
+    let table = get_kernel_table();
+
+    let mut new_pages = Vec::new();
+    for pa in (start.raw()..end.raw()).step_by(page_size) {
+        let existing = match pages.get(&pa) {
+            Some(page) => Some(page.cache),
+            None => unsafe { find_pte(table.paddr(), pa + va_offset) }
+                .map(|(_, pte)| pte.setting.cache_setting),
+        };
+
+        if let Some(existing) = existing
+            && existing != cache
+        {
+            return Err(IoMapError::AttrConflict {
+                paddr: pa.into(),
+                existing,
+                requested: cache,
+            });
+        }
+
+        if !pages.contains_key(&pa) {
+            new_pages.push((pa, existing.is_none()));
+        }
+    }
+
+    let mut mapped: Vec<(usize, usize)> = Vec::new();
+    for run in new_pages.chunk_by(|a, b| a.1 && b.1 && a.0 + page_size == b.0) {
+        let (run_start, owned) = run[0];
+        if !owned {
+            continue;
+        }
+        let run_size = run.len() * page_size;
+
+        if let Err(e) = map_kernel(
+            run_start + va_offset,
+            run_start,
+            run_size,
+            AccessSetting::Read | AccessSetting::Write,
+            cache,
+        ) {
+            for (pa, size) in mapped {
+                unmap_kernel(pa + va_offset, size);
+            }
+            return Err(e.into());
+        }
+        mapped.push((run_start, run_size));
+    }
+
+    for (pa, owned) in new_pages {
+        pages.insert(
+            pa,
+            IoPage {
+                refs: 0,
+                cache,
+                owned,
+            },
+        );
+    }
+    for pa in (start.raw()..end.raw()).step_by(page_size) {
+        if let Some(page) = pages.get_mut(&pa) {
+            page.refs += 1;
+        }
+    }
+
+    Ok(IoMem::new(vaddr, paddr, size, true))
+}
+
+pub fn iounmap(paddr: PhysAddr, size: usize) {
+    let page_size = page_size();
+    let start = paddr.align_down(page_size);
+    let end = (paddr + size).align_up(page_size);
+    let va_offset = RegionKind::Other.va_offset();
+    let table = get_kernel_table();
+
+    let mut pages = IO_PAGES.lock();
+    for pa in (start.raw()..end.raw()).step_by(page_size) {
+        let Some(page) = pages.get_mut(&pa) else {
+            warn!("iounmap {:#x} not mapped", pa);
+            continue;
+        };
+        page.refs -= 1;
+        if page.refs > 0 {
+            continue;
+        }
+        if let Some(page) = pages.remove(&pa)
+            && page.owned
+        {
+            unsafe { unmap_page(table.paddr(), pa + va_offset) };
+        }
+    }
+}
+
+/// 在内核页表中以页粒度建立映射
+pub(crate) fn map_kernel(
+    vaddr: usize,
+    paddr: usize,
+    size: usize,
+    access: AccessSetting,
+    cache: CacheSetting,
+) -> Result<(), PagingError> {
+    let mut table = get_kernel_table();
+    let mut heap = HeapGuard(ALLOCATOR.inner.lock());
     unsafe {
-        let mut table = get_kernel_table();
-        let paddr = paddr.align_down(0x1000);
-        let vaddr = VirtAddr::from(paddr.raw() + RegionKind::Other.va_offset());
-        let size = size.max(0x1000);
-
-        let mut heap = HeapGuard(ALLOCATOR.inner.lock());
-
-        let _ = table.map_region_with_handle(
-            MapConfig::new(
-                vaddr.into(),
-                paddr.into(),
-                AccessSetting::Read | AccessSetting::Write,
-                CacheSetting::Device,
-            ),
+        table.map_region_with_handle(
+            MapConfig::new(vaddr as _, paddr, access, cache),
             size,
             false,
             &mut heap,
             Some(&|p| {
                 unsafe { MMUImpl::flush_tlb(p) };
             }),
-        );
+        )?
+    }
+    track_dirty(table.paddr(), vaddr, size);
+    Ok(())
+}
 
-        NonNull::new(vaddr.into()).unwrap()
+/// 解除 [`map_kernel`] 建立的映射
+pub(crate) fn unmap_kernel(vaddr: usize, size: usize) {
+    let table = get_kernel_table();
+    for va in (vaddr..vaddr + size).step_by(page_size()) {
+        unsafe { unmap_page(table.paddr(), va) };
+    }
+}
+
+/// 新建空页表，用于用户地址空间
+pub(crate) fn new_table() -> Result<PageTableRef<'static>, PagingError> {
+    let mut heap = HeapGuard(ALLOCATOR.inner.lock());
+    PageTableRef::create_empty(&mut heap)
+}
+
+/// 在 `table` 中以页粒度建立映射，已有的项直接覆盖
+pub(crate) fn map_table(
+    table: &mut PageTableRef<'_>,
+    vaddr: usize,
+    paddr: usize,
+    size: usize,
+    setting: PTESetting,
+) -> Result<(), PagingError> {
+    let mut heap = HeapGuard(ALLOCATOR.inner.lock());
+    let config = MapConfig {
+        vaddr: vaddr as _,
+        paddr,
+        setting,
+    };
+    unsafe { table.map_region(config, size, false, &mut heap)? };
+    track_dirty(table.paddr(), vaddr, size);
+    Ok(())
+}
+
+/// 页表库生成表描述符和末级项用的是同一个 `new_pte`，脏状态跟踪只能在映射后对末级项开启
+fn track_dirty(root: usize, vaddr: usize, size: usize) {
+    if page_state_tracked().dirty {
+        KernelTableWalker::new(root, RegionKind::Other.va_offset())
+            .track_dirty(vaddr..vaddr + size);
+    }
+}
+
+/// 释放页表自身占用的内存，映射的物理页由调用者释放
+pub(crate) fn release_table(table: &mut PageTableRef<'_>) {
+    let mut heap = HeapGuard(ALLOCATOR.inner.lock());
+    table.release(&mut heap);
+}
+
+/// 查找 `vaddr` 对应的末级表项，返回表项地址和内容
+///
+/// # Safety
+///
+/// `table` 为有效页表的物理地址
+unsafe fn find_pte(table: usize, vaddr: usize) -> Option<(*mut usize, PTEGeneric)> {
+    let page_size = page_size();
+    let entries = page_size / size_of::<usize>();
+    let page_bits = page_size.trailing_zeros() as usize;
+    let index_bits = entries.trailing_zeros() as usize;
+    let va_offset = RegionKind::Other.va_offset();
+
+    let mut table = table;
+    for level in (1..=table_level()).rev() {
+        let shift = page_bits + (level - 1) * index_bits;
+        let idx = (vaddr >> shift) & (entries - 1);
+        let entry = unsafe { ((table + va_offset) as *mut usize).add(idx) };
+        let pte = PTEImpl::read_pte(unsafe { entry.read_volatile() });
+        if !pte.valid() {
+            return None;
+        }
+        if level == 1 || pte.is_block {
+            return Some((entry, pte));
+        }
+        table = pte.paddr;
+    }
+    None
+}
+
+unsafe fn unmap_page(table: usize, vaddr: usize) {
+    unsafe {
+        if let Some((entry, _)) = find_pte(table, vaddr) {
+            entry.write_volatile(0);
+            fence(Ordering::SeqCst);
+            MMUImpl::flush_tlb(vaddr as _);
+        }
     }
 }
@@ -0,0 +1,81 @@
+//! 页的访问、脏状态，由硬件维护（如 aarch64 FEAT_HAFDBS）
+
+use core::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::{KernelTableWalker, flush_tlb_all};
+use crate::platform_if::MMUImpl;
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PageState {
+    pub accessed: bool,
+    pub dirty: bool,
+}
+
+impl PageState {
+    pub const ACCESSED: Self = Self {
+        accessed: true,
+        dirty: false,
+    };
+    pub const DIRTY: Self = Self {
+        accessed: false,
+        dirty: true,
+    };
+    pub const ALL: Self = Self {
+        accessed: true,
+        dirty: true,
+    };
+
+    fn and(self, other: Self) -> Self {
+        Self {
+            accessed: self.accessed && other.accessed,
+            dirty: self.dirty && other.dirty,
+        }
+    }
+}
+
+/// 硬件会维护的状态，未维护的状态查询时总为 `true`，也不能清除
+pub fn page_state_tracked() -> PageState {
+    MMUImpl::page_state_tracked()
+}
+
+impl KernelTableWalker {
+    /// 遍历 `range` 内已映射的页或块及其状态
+    pub fn page_state(&self, range: Range<usize>, mut f: impl FnMut(Range<usize>, PageState)) {
+        self.for_each_leaf(range, |va, size, entry| {
+            let pte = unsafe { entry.read_volatile() };
+            f(va..va + size, MMUImpl::read_page_state(pte));
+        });
+    }
+
+    /// 清除 `range` 内各项的 `clear` 状态，硬件不维护的部分忽略
+    pub fn clear_page_state(&self, range: Range<usize>, clear: PageState) {
+        let clear = clear.and(page_state_tracked());
+        if clear == PageState::default() {
+            return;
+        }
+        self.update_leaves(range, |pte| MMUImpl::clear_page_state(pte, clear));
+        flush_tlb_all();
+    }
+
+    /// 为 `range` 内新建的末级项开启脏状态跟踪，映射完成后调用
+    pub(crate) fn track_dirty(&self, range: Range<usize>) {
+        self.update_leaves(range, MMUImpl::track_dirty);
+    }
+
+    fn update_leaves(&self, range: Range<usize>, f: impl Fn(usize) -> usize) {
+        self.for_each_leaf(range, |_, _, entry| {
+            // 硬件可能同时更新表项，用 CAS 避免丢失其写入的状态
+            let entry = unsafe { AtomicUsize::from_ptr(entry) };
+            let mut pte = entry.load(Ordering::Acquire);
+            while let Err(now) =
+                entry.compare_exchange_weak(pte, f(pte), Ordering::AcqRel, Ordering::Acquire)
+            {
+                pte = now;
+            }
+        });
+    }
+}
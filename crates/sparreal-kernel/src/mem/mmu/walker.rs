@@ -0,0 +1,253 @@
+//! 软件遍历页表：地址转换与映射区域列表
+
+use core::{fmt, marker::PhantomData, ops::Range};
+
+use alloc::vec::Vec;
+use arrayvec::ArrayVec;
+use page_table_generic::{AccessSetting, PTEArch, PTEGeneric, PTESetting};
+
+use super::{PTEImpl, RegionKind, get_user_table, paging::get_kernel_table};
+use crate::mem::VirtAddr;
+
+/// 页表遍历中的一级
+#[derive(Debug, Clone)]
+pub struct WalkStep {
+    /// 所在表的物理地址
+    pub table: usize,
+    pub level: usize,
+    pub index: usize,
+    pub raw: usize,
+    pub pte: PTEGeneric,
+}
+
+/// 虚拟地址的转换结果
+#[derive(Debug, Clone)]
+pub struct Translation {
+    pub paddr: usize,
+    pub pte: PTEGeneric,
+    /// 末级项所在的级别，1 为页
+    pub level: usize,
+}
+
+/// 虚拟、物理地址都连续且属性相同的一段映射
+#[derive(Clone)]
+pub struct MappedRegion {
+    pub vaddr: Range<usize>,
+    pub paddr: usize,
+    pub setting: PTESetting,
+}
+
+impl MappedRegion {
+    pub fn size(&self) -> usize {
+        self.vaddr.end - self.vaddr.start
+    }
+}
+
+impl fmt::Debug for MappedRegion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "[{:#018x}, {:#018x}) -> {:#014x} {:>#12x} P:{} U:{} {:?}{}",
+            self.vaddr.start,
+            self.vaddr.end,
+            self.paddr,
+            self.size(),
+            rwx(self.setting.privilege_access),
+            rwx(self.setting.user_access),
+            self.setting.cache_setting,
+            if self.setting.is_global { "" } else { " nG" },
+        )
+    }
+}
+
+/// 按 `P` 的格式遍历位于内存中的页表，`va_offset` 为访问表内存时物理地址到虚拟地址的偏移
+pub struct TableWalker<P: PTEArch> {
+    root: usize,
+    va_offset: usize,
+    _marker: PhantomData<P>,
+}
+
+impl<P: PTEArch> TableWalker<P> {
+    pub const fn new(root: usize, va_offset: usize) -> Self {
+        Self {
+            root,
+            va_offset,
+            _marker: PhantomData,
+        }
+    }
+
+    fn page_bits() -> usize {
+        P::page_size().trailing_zeros() as usize
+    }
+
+    fn entries() -> usize {
+        P::page_size() / size_of::<usize>()
+    }
+
+    fn shift(level: usize) -> usize {
+        Self::page_bits() + (level - 1) * Self::entries().trailing_zeros() as usize
+    }
+
+    fn read(&self, table: usize, index: usize) -> usize {
+        unsafe {
+            ((table + self.va_offset) as *const usize)
+                .add(index)
+                .read_volatile()
+        }
+    }
+
+    /// 逐级查找 `vaddr`，直到无效项或末级项
+    pub fn steps(&self, vaddr: usize) -> ArrayVec<WalkStep, 6> {
+        let mut out = ArrayVec::new();
+        let mut table = self.root;
+        for level in (1..=P::level()).rev() {
+            let index = (vaddr >> Self::shift(level)) & (Self::entries() - 1);
+            let raw = self.read(table, index);
+            let pte = P::read_pte(raw);
+            let next = (pte.valid() && !pte.is_block && level > 1).then_some(pte.paddr);
+            out.push(WalkStep {
+                table,
+                level,
+                index,
+                raw,
+                pte,
+            });
+            match next {
+                Some(paddr) => table = paddr,
+                None => break,
+            }
+        }
+        out
+    }
+
+    pub fn translate(&self, vaddr: usize) -> Option<Translation> {
+        let step = self.steps(vaddr).pop()?;
+        if !step.pte.valid() {
+            return None;
+        }
+        let span = 1usize << Self::shift(step.level);
+        Some(Translation {
+            paddr: step.pte.paddr + (vaddr & (span - 1)),
+            pte: step.pte,
+            level: step.level,
+        })
+    }
+
+    /// 遍历与 `range` 相交的末级项，`f` 参数为项的起始地址、大小和表项指针
+    pub fn for_each_leaf(&self, range: Range<usize>, mut f: impl FnMut(usize, usize, *mut usize)) {
+        let mut va = range.start;
+        while va < range.end {
+            let Some(step) = self.steps(va).pop() else {
+                return;
+            };
+            let span = 1usize << Self::shift(step.level);
+            let start = va & !(span - 1);
+            if step.pte.valid() {
+                let entry = (step.table + self.va_offset) as *mut usize;
+                f(start, span, unsafe { entry.add(step.index) });
+            }
+            match start.checked_add(span) {
+                Some(next) => va = next,
+                None => return,
+            }
+        }
+    }
+
+    /// 按虚拟地址顺序遍历合并后的映射区域
+    pub fn for_each_region(&self, mut f: impl FnMut(&MappedRegion)) {
+        let mut cur: Option<MappedRegion> = None;
+        self.visit(self.root, P::level(), 0, &mut |va, size, pte| {
+            if let Some(r) = &mut cur {
+                if r.vaddr.end == va && r.paddr + r.size() == pte.paddr && r.setting == pte.setting
+                {
+                    r.vaddr.end += size;
+                    return;
+                }
+                f(r);
+            }
+            cur = Some(MappedRegion {
+                vaddr: va..va + size,
+                paddr: pte.paddr,
+                setting: pte.setting,
+            });
+        });
+        if let Some(r) = &cur {
+            f(r);
+        }
+    }
+
+    pub fn regions(&self) -> Vec<MappedRegion> {
+        let mut out = Vec::new();
+        self.for_each_region(|r| out.push(r.clone()));
+        out
+    }
+
+    fn visit(
+        &self,
+        table: usize,
+        level: usize,
+        base: usize,
+        f: &mut impl FnMut(usize, usize, &PTEGeneric),
+    ) {
+        let shift = Self::shift(level);
+        // 顶级表的高位索引对应高半区地址，按最高位符号扩展
+        let top = Self::shift(P::level()) + Self::entries().trailing_zeros() as usize;
+        for index in 0..Self::entries() {
+            let pte = P::read_pte(self.read(table, index));
+            if !pte.valid() {
+                continue;
+            }
+            let mut va = base | (index << shift);
+            if top < usize::BITS as usize && va >> (top - 1) & 1 == 1 {
+                va |= !((1 << top) - 1);
+            }
+            if level > 1 && !pte.is_block {
+                self.visit(pte.paddr, level - 1, va, f);
+            } else {
+                f(va, 1 << shift, &pte);
+            }
+        }
+    }
+}
+
+impl<P: PTEArch> fmt::Display for TableWalker<P> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut res = Ok(());
+        self.for_each_region(|r| {
+            if res.is_ok() {
+                res = writeln!(f, "{r:?}");
+            }
+        });
+        res
+    }
+}
+
+pub type KernelTableWalker = TableWalker<PTEImpl>;
+
+/// 当前内核页表
+pub fn kernel_table() -> KernelTableWalker {
+    TableWalker::new(get_kernel_table().paddr(), RegionKind::Other.va_offset())
+}
+
+/// 当前用户页表
+pub fn user_table() -> KernelTableWalker {
+    TableWalker::new(get_user_table(), RegionKind::Other.va_offset())
+}
+
+/// 在内核页表中逐级查找 `vaddr`，直到无效项或末级项
+pub fn walk(vaddr: VirtAddr) -> ArrayVec<WalkStep, 6> {
+    kernel_table().steps(vaddr.raw())
+}
+
+/// 在内核页表中查询 `vaddr` 的物理地址和属性
+pub fn translate(vaddr: VirtAddr) -> Option<Translation> {
+    kernel_table().translate(vaddr.raw())
+}
+
+pub(super) fn rwx(access: AccessSetting) -> &'static str {
+    const NAMES: [&str; 8] = ["---", "r--", "-w-", "rw-", "--x", "r-x", "-wx", "rwx"];
+    let idx = access.readable() as usize
+        | (access.writable() as usize) << 1
+        | (access.executable() as usize) << 2;
+    NAMES[idx]
+}
@@ -1,12 +1,12 @@
 #![allow(unused)]
 
+use alloc::vec::Vec;
 use core::{
-    alloc::GlobalAlloc,
+    alloc::{GlobalAlloc, Layout},
     ptr::{NonNull, null_mut, slice_from_raw_parts_mut},
     sync::atomic::{AtomicUsize, Ordering},
 };
 
-use buddy_system_allocator::Heap;
 use log::debug;
 use mmu::RegionKind;
 use page_table_generic::{AccessSetting, CacheSetting};
@@ -15,29 +15,44 @@ use spin::Mutex;
 use crate::{globals::global_val, platform::kstack_size, println};
 
 mod addr;
+#[cfg(not(feature = "tlsf"))]
+mod buddy;
 mod cache;
+#[cfg(feature = "debug-alloc")]
+pub mod debug_alloc;
+pub mod dma;
+mod heap;
+mod iomem;
 #[cfg(feature = "mmu")]
 pub mod mmu;
 pub mod once;
+mod oom;
 pub mod region;
+pub mod slab;
+mod track;
 pub use addr::*;
+pub use heap::{HeapStats, KernelHeap};
+pub use iomem::*;
+pub use oom::{ShrinkFn, register_shrinker};
+pub use track::*;
 
-#[global_allocator]
+#[cfg_attr(target_os = "none", global_allocator)]
 static ALLOCATOR: KAllocator = KAllocator {
-    inner: Mutex::new(Heap::empty()),
+    inner: Mutex::new(KernelHeap::empty()),
 };
 
 pub struct KAllocator {
-    pub(crate) inner: Mutex<Heap<32>>,
+    pub(crate) inner: Mutex<KernelHeap>,
 }
 
 impl KAllocator {
     pub fn reset(&self, memory: &mut [u8]) {
         let mut g = self.inner.lock();
 
-        let mut h = Heap::empty();
+        let mut h = KernelHeap::empty();
+        let range = memory.as_mut_ptr_range();
 
-        unsafe { h.init(memory.as_mut_ptr() as usize, memory.len()) };
+        unsafe { h.add_to_heap(range.start as usize, range.end as usize) };
 
         *g = h;
     }
@@ -50,22 +65,86 @@ impl KAllocator {
     }
 }
 
-unsafe impl GlobalAlloc for KAllocator {
-    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
-        if let Ok(p) = self.inner.lock().alloc(layout) {
-            p.as_ptr()
+impl KAllocator {
+    fn raw_alloc(&self, layout: Layout) -> *mut u8 {
+        if let Some(cache) = slab::kmalloc_cache(layout) {
+            cache.alloc()
         } else {
-            null_mut()
+            heap_alloc(layout)
         }
+        .map_or(null_mut(), |p| p.as_ptr())
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+    unsafe fn raw_dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if let Some(cache) = slab::kmalloc_cache(layout) {
+            unsafe { cache.free(NonNull::new_unchecked(ptr)) };
+            return;
+        }
+
         self.inner
             .lock()
             .dealloc(unsafe { NonNull::new_unchecked(ptr) }, layout);
     }
 }
 
+unsafe impl GlobalAlloc for KAllocator {
+    unsafe fn alloc(&self, layout: core::alloc::Layout) -> *mut u8 {
+        #[cfg(not(feature = "debug-alloc"))]
+        let ptr = self.raw_alloc(layout);
+
+        #[cfg(feature = "debug-alloc")]
+        let ptr = match self.raw_alloc(debug_alloc::outer(layout)) {
+            p if p.is_null() => p,
+            p => unsafe { debug_alloc::arm(p, layout) },
+        };
+
+        if !ptr.is_null() {
+            track::on_alloc(ptr, layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: core::alloc::Layout) {
+        #[cfg(feature = "debug-alloc")]
+        {
+            debug_alloc::check_on_free(ptr, layout);
+            let evicted = debug_alloc::quarantine(ptr, layout);
+            track::on_dealloc(ptr, layout);
+            if let Some((raw, outer)) = evicted {
+                unsafe { self.raw_dealloc(raw.as_ptr(), outer) };
+            }
+        }
+
+        #[cfg(not(feature = "debug-alloc"))]
+        {
+            track::on_dealloc(ptr, layout);
+            unsafe { self.raw_dealloc(ptr, layout) };
+        }
+    }
+}
+
+/// 从堆后端分配，失败时走内存不足处理后重试
+pub(crate) fn heap_alloc(layout: Layout) -> Option<NonNull<u8>> {
+    let mut ptr = ALLOCATOR.inner.lock().alloc(layout).ok();
+    if ptr.is_none() {
+        oom::reclaim(layout, || {
+            ptr = ALLOCATOR.inner.lock().alloc(layout).ok();
+            ptr.is_some()
+        });
+    }
+    ptr
+}
+
+/// 归还 [`heap_alloc`] 分配的内存
+pub(crate) fn heap_dealloc(ptr: NonNull<u8>, layout: Layout) {
+    ALLOCATOR.inner.lock().dealloc(ptr, layout);
+}
+
+/// 堆后端统计，`used` 包含 slab 占用的整页
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.inner.lock().stats()
+}
+
 const STACK_BOTTOM: usize = 0xffff_e100_0000_0000;
 pub fn stack_bottom() -> usize {
     STACK_BOTTOM
@@ -89,19 +168,26 @@ pub(crate) fn init_page_and_memory() {
     #[cfg(feature = "mmu")]
     mmu::init_table();
 
+    dma::init_pool();
+
     let main = global_val().main_memory.clone();
 
+    // 引导映射和固件保留的区域不能交给堆
+    let mut holes: Vec<_> = region::boot_regions()
+        .iter()
+        .map(|r| r.range.start..r.range.end)
+        .collect();
+    holes.extend(crate::platform::reserved_regions());
+
+    // 其余内存在堆不足时按需加入
     for memory in global_val().platform_info.memorys() {
         if memory.contains(&main.start) {
             continue;
         }
-        // let start = VirtAddr::from(memory.start);
-        // let end = VirtAddr::from(memory.end);
-        // let len = memory.end - memory.start;
-
-        // debug!("Heap add memory [{}, {})", start, end);
-        // ALLOCATOR.add_to_heap(unsafe { &mut *slice_from_raw_parts_mut(start.as_mut_ptr(), len) });
+        oom::add_reserve(memory, &holes);
     }
+
+    register_shrinker("slab", slab::shrink_all);
 }
 
 #[repr(C)]
@@ -126,14 +212,29 @@ pub struct KernelRegions {
     pub bss: CMemRange,
 }
 
-pub fn iomap(paddr: PhysAddr, _size: usize) -> NonNull<u8> {
+/// 映射设备内存，重叠的请求共享映射并计数，属性冲突时返回错误
+pub fn iomap(paddr: PhysAddr, size: usize) -> Result<IoMem, IoMapError> {
+    iomap_with_cache(paddr, size, CacheSetting::Device)
+}
+
+pub fn iomap_with_cache(
+    paddr: PhysAddr,
+    size: usize,
+    cache: CacheSetting,
+) -> Result<IoMem, IoMapError> {
+    if size == 0 {
+        return Err(IoMapError::ZeroSize);
+    }
+
     #[cfg(feature = "mmu")]
     {
-        mmu::iomap(paddr, _size)
+        mmu::iomap(paddr, size, cache)
     }
 
     #[cfg(not(feature = "mmu"))]
-    unsafe {
-        NonNull::new_unchecked(paddr.as_usize() as *mut u8)
+    {
+        let _ = cache;
+        let ptr = unsafe { NonNull::new_unchecked(paddr.raw() as *mut u8) };
+        Ok(IoMem::new(ptr, paddr, size, false))
     }
 }
@@ -0,0 +1,144 @@
+use core::{
+    alloc::Layout,
+    ops::Range,
+    ptr::slice_from_raw_parts_mut,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use alloc::{vec, vec::Vec};
+use arrayvec::ArrayVec;
+use log::{debug, error, info};
+use spin::Mutex;
+
+use crate::{globals::cpu_global_meybeuninit, task};
+
+use super::{ALLOCATOR, PhysAddr, mmu::RegionKind, slab};
+
+/// 回收函数，返回释放回堆的字节数
+pub type ShrinkFn = fn() -> usize;
+
+const GROW_MIN: usize = 4 * 1024 * 1024;
+
+static SHRINKERS: Mutex<ArrayVec<(&'static str, ShrinkFn), 16>> = Mutex::new(ArrayVec::new_const());
+/// 尚未加入堆的物理内存
+static RESERVE: Mutex<ArrayVec<Range<PhysAddr>, 32>> = Mutex::new(ArrayVec::new_const());
+/// PerCPU 初始化前只有主 CPU 在运行
+static BOOT_IN_OOM: AtomicBool = AtomicBool::new(false);
+
+/// 注册内存不足时调用的回收函数，按注册顺序尝试
+pub fn register_shrinker(name: &'static str, f: ShrinkFn) {
+    if SHRINKERS.lock().try_push((name, f)).is_err() {
+        error!("too many shrinkers, {name} ignored");
+    }
+}
+
+/// 去掉 `holes` 覆盖的部分后留作扩堆
+pub(crate) fn add_reserve(range: Range<PhysAddr>, holes: &[Range<PhysAddr>]) {
+    let page = super::mmu::page_size();
+    let mut reserve = RESERVE.lock();
+    for r in subtract(range.start.raw()..range.end.raw(), holes) {
+        let r = r.start.next_multiple_of(page)..r.end / page * page;
+        if r.start >= r.end {
+            continue;
+        }
+        let r = PhysAddr::from(r.start)..PhysAddr::from(r.end);
+        debug!("reserve memory [{}, {})", r.start, r.end);
+        if reserve.try_push(r.clone()).is_err() {
+            error!("memory [{}, {}) dropped", r.start, r.end);
+        }
+    }
+}
+
+fn subtract(range: Range<usize>, holes: &[Range<PhysAddr>]) -> Vec<Range<usize>> {
+    let mut out = vec![range];
+    for hole in holes {
+        let hole = hole.start.raw()..hole.end.raw();
+        out = out
+            .into_iter()
+            .flat_map(|r| [r.start..hole.start.min(r.end), hole.end.max(r.start)..r.end])
+            .filter(|r| r.start < r.end)
+            .collect();
+    }
+    out
+}
+
+/// 分配失败后依次尝试扩堆和回收，返回 `true` 表示值得重试
+pub(crate) fn reclaim(layout: Layout, mut retry: impl FnMut() -> bool) -> bool {
+    // 回收函数自身分配失败时不再嵌套，其他 CPU 可以同时回收
+    let in_oom = cpu_global_meybeuninit().map_or(&BOOT_IN_OOM, |c| &c.in_oom);
+    if in_oom.swap(true, Ordering::Acquire) {
+        return false;
+    }
+
+    let ok = grow(layout) && retry() || shrink(&mut retry);
+    if !ok {
+        report(layout);
+    }
+
+    in_oom.store(false, Ordering::Release);
+    ok
+}
+
+fn grow(layout: Layout) -> bool {
+    let want = (layout.size() + layout.align())
+        .next_power_of_two()
+        .max(GROW_MIN);
+
+    let chunk = {
+        let mut reserve = RESERVE.lock();
+        let Some(idx) = reserve.iter().position(|r| r.end - r.start >= want) else {
+            return false;
+        };
+        let r = &mut reserve[idx];
+        // 多取一倍，未对齐的起点也能切出完整的 `want` 大小块
+        let size = (want * 2).min(r.end - r.start);
+        let start = r.start;
+        r.start = start + size;
+        if r.start == r.end {
+            reserve.remove(idx);
+        }
+        start..start + size
+    };
+
+    info!("heap grow [{}, {})", chunk.start, chunk.end);
+    let va = chunk.start.raw() + RegionKind::Other.va_offset();
+    let len = chunk.end - chunk.start;
+    ALLOCATOR.add_to_heap(unsafe { &mut *slice_from_raw_parts_mut(va as *mut u8, len) });
+    true
+}
+
+fn shrink(retry: &mut impl FnMut() -> bool) -> bool {
+    let shrinkers = SHRINKERS.lock().clone();
+    for (name, f) in shrinkers {
+        let freed = f();
+        if freed > 0 {
+            info!("shrinker {name} freed {freed:#x} bytes");
+            if retry() {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+fn report(layout: Layout) {
+    error!("out of memory: {layout:?}");
+    match task::current_pid() {
+        Some(pid) => error!("  task: {:?} {}", pid, task::current().name),
+        None => error!("  task: <boot>"),
+    }
+
+    let stats = ALLOCATOR.inner.lock().stats();
+    error!(
+        "  heap: total {:#x} used {:#x} peak {:#x} largest free {:#x} failures {}",
+        stats.total, stats.used, stats.peak, stats.largest_free, stats.failures
+    );
+    slab::for_each_stats(|s| {
+        if s.slabs > 0 || s.objects > 0 {
+            error!(
+                "  {:<20} size {:<8} slabs {:<4} objects {:<6} in use {}",
+                s.name, s.object_size, s.slabs, s.objects, s.in_use
+            );
+        }
+    });
+}
@@ -0,0 +1,476 @@
+use core::{
+    alloc::Layout,
+    ptr::{NonNull, null_mut},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicUsize, Ordering},
+};
+
+use alloc::vec::Vec;
+use spin::Mutex;
+
+use crate::{
+    globals::cpu_inited,
+    platform::{CPUId, cpu_hard_id},
+};
+
+use super::ALLOCATOR;
+
+/// 小对象 slab 的大小，slab 按自身大小对齐，头部位于起始处
+const SLAB_SIZE: usize = 0x4000;
+const MAGAZINE_SIZE: usize = 16;
+const MAX_CPUS: usize = 16;
+
+static KMALLOC: [KmemCache; 8] = [
+    KmemCache::new("kmalloc-16", 16, 16),
+    KmemCache::new("kmalloc-32", 32, 32),
+    KmemCache::new("kmalloc-64", 64, 64),
+    KmemCache::new("kmalloc-128", 128, 128),
+    KmemCache::new("kmalloc-256", 256, 256),
+    KmemCache::new("kmalloc-512", 512, 512),
+    KmemCache::new("kmalloc-1k", 1024, 1024),
+    KmemCache::new("kmalloc-2k", 2048, 2048),
+];
+
+static CACHES: AtomicPtr<KmemCache> = AtomicPtr::new(null_mut());
+
+/// 通用分配请求对应的尺寸类缓存，过大的请求直接交给伙伴分配器
+pub(crate) fn kmalloc_cache(layout: Layout) -> Option<&'static KmemCache> {
+    let size = layout.size().max(layout.align());
+    KMALLOC.iter().find(|c| c.layout.size() >= size)
+}
+
+fn for_each_cache(mut f: impl FnMut(&'static KmemCache)) {
+    let mut cur = CACHES.load(Ordering::Acquire);
+    while let Some(cache) = unsafe { cur.as_ref() } {
+        f(cache);
+        cur = cache.next.load(Ordering::Acquire);
+    }
+}
+
+/// 所有已使用过的缓存的统计
+pub fn slab_stats() -> Vec<SlabStats> {
+    let mut out = Vec::new();
+    for_each_cache(|cache| {
+        let stats = cache.stats();
+        out.push(stats);
+    });
+    out
+}
+
+/// 不分配、不等待锁的统计遍历，正被持有的缓存会被跳过
+pub(crate) fn for_each_stats(mut f: impl FnMut(&SlabStats)) {
+    for_each_cache(|cache| {
+        if let Some(stats) = cache.try_stats() {
+            f(&stats);
+        }
+    });
+}
+
+/// 归还所有缓存中的空闲对象与空 slab
+pub fn shrink_all() -> usize {
+    let mut freed = 0;
+    for_each_cache(|cache| freed += cache.shrink());
+    freed
+}
+
+#[derive(Debug, Clone)]
+pub struct SlabStats {
+    pub name: &'static str,
+    pub object_size: usize,
+    pub slabs: usize,
+    /// 已从 slab 取出的对象，含缓存在 magazine 中的
+    pub objects: usize,
+    pub in_use: usize,
+    pub allocs: usize,
+    pub frees: usize,
+    pub magazine_hits: usize,
+}
+
+struct FreeObj {
+    next: *mut FreeObj,
+}
+
+#[repr(C)]
+struct SlabHeader {
+    prev: *mut SlabHeader,
+    next: *mut SlabHeader,
+    free: *mut FreeObj,
+    inuse: usize,
+}
+
+struct Magazine {
+    objs: [*mut u8; MAGAZINE_SIZE],
+    len: usize,
+}
+
+unsafe impl Send for Magazine {}
+
+impl Magazine {
+    const fn new() -> Self {
+        Self {
+            objs: [null_mut(); MAGAZINE_SIZE],
+            len: 0,
+        }
+    }
+}
+
+struct Depot {
+    partial: *mut SlabHeader,
+    slabs: usize,
+    empty: usize,
+    objects: usize,
+}
+
+unsafe impl Send for Depot {}
+
+/// 固定大小对象缓存：每 CPU magazine 在前，slab 在后，最终由伙伴分配器供给
+pub struct KmemCache {
+    name: &'static str,
+    layout: Layout,
+    magazine_cap: usize,
+    depot: Mutex<Depot>,
+    magazines: [Mutex<Magazine>; MAX_CPUS],
+    allocs: AtomicUsize,
+    frees: AtomicUsize,
+    magazine_hits: AtomicUsize,
+    registered: AtomicBool,
+    next: AtomicPtr<KmemCache>,
+}
+
+impl KmemCache {
+    pub const fn new(name: &'static str, size: usize, align: usize) -> Self {
+        let layout = match Layout::from_size_align(size, align) {
+            Ok(l) => l.pad_to_align(),
+            Err(_) => panic!("invalid cache layout"),
+        };
+        // 大对象每个占一次伙伴分配，magazine 只保留少量
+        let magazine_cap = if Self::is_direct(layout) {
+            2
+        } else {
+            MAGAZINE_SIZE
+        };
+        Self {
+            name,
+            layout,
+            magazine_cap,
+            depot: Mutex::new(Depot {
+                partial: null_mut(),
+                slabs: 0,
+                empty: 0,
+                objects: 0,
+            }),
+            magazines: [const { Mutex::new(Magazine::new()) }; MAX_CPUS],
+            allocs: AtomicUsize::new(0),
+            frees: AtomicUsize::new(0),
+            magazine_hits: AtomicUsize::new(0),
+            registered: AtomicBool::new(false),
+            next: AtomicPtr::new(null_mut()),
+        }
+    }
+
+    const fn is_direct(layout: Layout) -> bool {
+        layout.size() > SLAB_SIZE / 8 || layout.align() >= SLAB_SIZE
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub fn alloc(&'static self) -> Option<NonNull<u8>> {
+        self.register();
+        self.allocs.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(mut mag) = self.magazine()
+            && mag.len > 0
+        {
+            mag.len -= 1;
+            self.magazine_hits.fetch_add(1, Ordering::Relaxed);
+            return NonNull::new(mag.objs[mag.len]);
+        }
+
+        let ptr = self.alloc_slow();
+        if ptr.is_none() {
+            self.allocs.fetch_sub(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    /// 向伙伴分配器申请时不持有 depot 锁，内存不足路径中的回收函数可能释放本缓存的对象
+    fn alloc_slow(&self) -> Option<NonNull<u8>> {
+        if Self::is_direct(self.layout) {
+            let ptr = buddy_alloc(self.layout)?;
+            self.depot.lock().objects += 1;
+            return Some(ptr);
+        }
+
+        loop {
+            if let Some(ptr) = self.depot.lock().take() {
+                return Some(ptr);
+            }
+            let base = buddy_alloc(slab_layout())?;
+            unsafe { self.depot.lock().add_slab(base.as_ptr(), self.layout) };
+        }
+    }
+
+    pub fn alloc_zeroed(&'static self) -> Option<NonNull<u8>> {
+        let ptr = self.alloc()?;
+        unsafe { ptr.as_ptr().write_bytes(0, self.layout.size()) };
+        Some(ptr)
+    }
+
+    /// # Safety
+    /// `ptr` 必须由同一缓存的 [`KmemCache::alloc`] 返回
+    pub unsafe fn free(&'static self, ptr: NonNull<u8>) {
+        self.frees.fetch_add(1, Ordering::Relaxed);
+
+        if let Some(mut mag) = self.magazine() {
+            if mag.len < self.magazine_cap {
+                let len = mag.len;
+                mag.objs[len] = ptr.as_ptr();
+                mag.len += 1;
+                return;
+            }
+
+            // magazine 已满，归还一半再放入
+            let mut depot = self.depot.lock();
+            let keep = self.magazine_cap / 2;
+            for &obj in &mag.objs[keep..mag.len] {
+                unsafe { depot.free(obj, self.layout) };
+            }
+            mag.len = keep;
+            let len = mag.len;
+            mag.objs[len] = ptr.as_ptr();
+            mag.len += 1;
+            return;
+        }
+
+        unsafe { self.depot.lock().free(ptr.as_ptr(), self.layout) };
+    }
+
+    /// 不放入 magazine，大对象直接归还给伙伴分配器，用于内存回收
+    ///
+    /// # Safety
+    /// `ptr` 必须由同一缓存的 [`KmemCache::alloc`] 返回
+    pub unsafe fn free_to_heap(&'static self, ptr: NonNull<u8>) {
+        self.frees.fetch_add(1, Ordering::Relaxed);
+        unsafe { self.depot.lock().free(ptr.as_ptr(), self.layout) };
+    }
+
+    pub fn stats(&self) -> SlabStats {
+        let (slabs, objects) = {
+            let depot = self.depot.lock();
+            (depot.slabs, depot.objects)
+        };
+        let cached: usize = self.magazines.iter().map(|m| m.lock().len).sum();
+        self.make_stats(slabs, objects, cached)
+    }
+
+    fn try_stats(&self) -> Option<SlabStats> {
+        let (slabs, objects) = {
+            let depot = self.depot.try_lock()?;
+            (depot.slabs, depot.objects)
+        };
+        let cached: usize = self
+            .magazines
+            .iter()
+            .map(|m| m.try_lock().map_or(0, |m| m.len))
+            .sum();
+        Some(self.make_stats(slabs, objects, cached))
+    }
+
+    fn make_stats(&self, slabs: usize, objects: usize, cached: usize) -> SlabStats {
+        SlabStats {
+            name: self.name,
+            object_size: self.layout.size(),
+            slabs,
+            objects,
+            in_use: objects.saturating_sub(cached),
+            allocs: self.allocs.load(Ordering::Relaxed),
+            frees: self.frees.load(Ordering::Relaxed),
+            magazine_hits: self.magazine_hits.load(Ordering::Relaxed),
+        }
+    }
+
+    /// 清空各 CPU 的 magazine 并释放空 slab，返回归还给伙伴分配器的字节数
+    ///
+    /// 可能在本缓存分配的内存不足路径中被调用，所有锁都只尝试获取
+    pub fn shrink(&self) -> usize {
+        let Some(mut depot) = self.depot.try_lock() else {
+            return 0;
+        };
+        let direct = Self::is_direct(self.layout);
+        let slabs = depot.slabs;
+        let mut freed = 0;
+
+        for mag in &self.magazines {
+            let Some(mut mag) = mag.try_lock() else {
+                continue;
+            };
+            for &obj in &mag.objs[..mag.len] {
+                unsafe { depot.free(obj, self.layout) };
+                if direct {
+                    freed += self.layout.size();
+                }
+            }
+            mag.len = 0;
+        }
+
+        if !direct {
+            depot.release_empty();
+            freed += (slabs - depot.slabs) * SLAB_SIZE;
+        }
+        freed
+    }
+
+    /// 当前 CPU 的 magazine，重入（如中断中分配）时返回 `None` 直接走 slab
+    fn magazine(&self) -> Option<spin::MutexGuard<'_, Magazine>> {
+        let idx = if cpu_inited() {
+            usize::from(CPUId::from(cpu_hard_id()))
+        } else {
+            0
+        };
+        self.magazines.get(idx)?.try_lock()
+    }
+
+    fn register(&'static self) {
+        if self.registered.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let this = self as *const _ as *mut KmemCache;
+        let mut head = CACHES.load(Ordering::Acquire);
+        loop {
+            self.next.store(head, Ordering::Release);
+            match CACHES.compare_exchange(head, this, Ordering::AcqRel, Ordering::Acquire) {
+                Ok(_) => break,
+                Err(h) => head = h,
+            }
+        }
+    }
+}
+
+fn buddy_alloc(layout: Layout) -> Option<NonNull<u8>> {
+    super::heap_alloc(layout)
+}
+
+unsafe fn buddy_free(ptr: *mut u8, layout: Layout) {
+    ALLOCATOR
+        .inner
+        .lock()
+        .dealloc(unsafe { NonNull::new_unchecked(ptr) }, layout);
+}
+
+impl Depot {
+    fn take(&mut self) -> Option<NonNull<u8>> {
+        let slab = unsafe { self.partial.as_mut()? };
+        let obj = slab.free;
+        unsafe {
+            slab.free = (*obj).next;
+            if slab.inuse == 0 {
+                self.empty -= 1;
+            }
+            slab.inuse += 1;
+            if slab.free.is_null() {
+                self.unlink(slab);
+            }
+        }
+        self.objects += 1;
+        NonNull::new(obj as *mut u8)
+    }
+
+    unsafe fn free(&mut self, ptr: *mut u8, layout: Layout) {
+        self.objects -= 1;
+        if KmemCache::is_direct(layout) {
+            unsafe { buddy_free(ptr, layout) };
+            return;
+        }
+
+        unsafe {
+            let slab = &mut *((ptr as usize & !(SLAB_SIZE - 1)) as *mut SlabHeader);
+            let was_full = slab.free.is_null();
+            let obj = ptr as *mut FreeObj;
+            (*obj).next = slab.free;
+            slab.free = obj;
+            slab.inuse -= 1;
+            if was_full {
+                self.push(slab);
+            }
+            if slab.inuse == 0 {
+                // 保留一个空 slab 避免反复向伙伴分配器申请
+                if self.empty > 0 {
+                    self.unlink(slab);
+                    self.slabs -= 1;
+                    buddy_free(slab as *mut _ as *mut u8, slab_layout());
+                } else {
+                    self.empty += 1;
+                }
+            }
+        }
+    }
+
+    fn release_empty(&mut self) {
+        let mut cur = self.partial;
+        while let Some(slab) = unsafe { cur.as_mut() } {
+            cur = slab.next;
+            if slab.inuse == 0 {
+                unsafe {
+                    self.unlink(slab);
+                    buddy_free(slab as *mut _ as *mut u8, slab_layout());
+                }
+                self.slabs -= 1;
+                self.empty -= 1;
+            }
+        }
+    }
+
+    unsafe fn add_slab(&mut self, base: *mut u8, layout: Layout) {
+        let first = size_of::<SlabHeader>().next_multiple_of(layout.align());
+
+        unsafe {
+            let slab = &mut *(base as *mut SlabHeader);
+            slab.prev = null_mut();
+            slab.next = null_mut();
+            slab.free = null_mut();
+            slab.inuse = 0;
+
+            let mut off = first;
+            while off + layout.size() <= SLAB_SIZE {
+                let obj = base.add(off) as *mut FreeObj;
+                (*obj).next = slab.free;
+                slab.free = obj;
+                off += layout.size();
+            }
+            self.push(slab);
+        }
+        self.slabs += 1;
+        self.empty += 1;
+    }
+
+    unsafe fn push(&mut self, slab: &mut SlabHeader) {
+        slab.prev = null_mut();
+        slab.next = self.partial;
+        if let Some(head) = unsafe { self.partial.as_mut() } {
+            head.prev = slab;
+        }
+        self.partial = slab;
+    }
+
+    unsafe fn unlink(&mut self, slab: &mut SlabHeader) {
+        if let Some(prev) = unsafe { slab.prev.as_mut() } {
+            prev.next = slab.next;
+        } else {
+            self.partial = slab.next;
+        }
+        if let Some(next) = unsafe { slab.next.as_mut() } {
+            next.prev = slab.prev;
+        }
+        slab.prev = null_mut();
+        slab.next = null_mut();
+    }
+}
+
+fn slab_layout() -> Layout {
+    unsafe { Layout::from_size_align_unchecked(SLAB_SIZE, SLAB_SIZE) }
+}
@@ -0,0 +1,306 @@
+use core::{
+    alloc::Layout,
+    sync::atomic::{AtomicU64, AtomicUsize, Ordering},
+};
+
+#[cfg(feature = "heap-trace")]
+pub use record::*;
+
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static LIVE_COUNT: AtomicUsize = AtomicUsize::new(0);
+static SEQ: AtomicU64 = AtomicU64::new(0);
+
+pub(crate) fn on_alloc(ptr: *mut u8, layout: Layout) {
+    LIVE_BYTES.fetch_add(layout.size(), Ordering::Relaxed);
+    LIVE_COUNT.fetch_add(1, Ordering::Relaxed);
+    let seq = SEQ.fetch_add(1, Ordering::Relaxed);
+
+    #[cfg(feature = "heap-trace")]
+    record::insert(ptr, layout, seq);
+    #[cfg(not(feature = "heap-trace"))]
+    let _ = (ptr, seq);
+}
+
+pub(crate) fn on_dealloc(ptr: *mut u8, layout: Layout) {
+    LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    LIVE_COUNT.fetch_sub(1, Ordering::Relaxed);
+
+    #[cfg(feature = "heap-trace")]
+    record::remove(ptr);
+    #[cfg(not(feature = "heap-trace"))]
+    let _ = ptr;
+}
+
+/// 某一时刻的堆状态，之后用 [`HeapMark::diff`] 比较
+#[derive(Debug, Clone, Copy)]
+pub struct HeapMark {
+    bytes: usize,
+    count: usize,
+    seq: u64,
+}
+
+/// 相对 [`HeapMark`] 的变化，正数表示新增未释放
+#[derive(Debug)]
+pub struct HeapDiff {
+    pub bytes: isize,
+    pub allocations: isize,
+    /// mark 之后分配且仍存活的记录
+    #[cfg(feature = "heap-trace")]
+    pub live: alloc::vec::Vec<AllocRecord>,
+}
+
+impl HeapDiff {
+    pub fn is_empty(&self) -> bool {
+        self.bytes == 0 && self.allocations == 0
+    }
+}
+
+pub fn heap_mark() -> HeapMark {
+    HeapMark {
+        bytes: LIVE_BYTES.load(Ordering::Relaxed),
+        count: LIVE_COUNT.load(Ordering::Relaxed),
+        seq: SEQ.load(Ordering::Relaxed),
+    }
+}
+
+impl HeapMark {
+    pub fn diff(&self) -> HeapDiff {
+        let now = heap_mark();
+        HeapDiff {
+            bytes: now.bytes as isize - self.bytes as isize,
+            allocations: now.count as isize - self.count as isize,
+            #[cfg(feature = "heap-trace")]
+            live: record::live_since(self.seq),
+        }
+    }
+}
+
+#[cfg(feature = "heap-trace")]
+mod record {
+    use core::{
+        alloc::Layout,
+        panic::Location,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use alloc::vec::Vec;
+    use spin::Mutex;
+
+    use crate::{
+        platform::kstack_size,
+        platform_if::PlatformImpl,
+        task::{Pid, TaskControlBlock, current_pid, try_current},
+    };
+
+    const SLOTS: usize = 8192;
+    const BACKTRACE_DEPTH: usize = 8;
+
+    /// 开放寻址表，分配器内部不能再分配
+    static TABLE: Mutex<[Option<AllocRecord>; SLOTS]> = Mutex::new([None; SLOTS]);
+    static DROPPED: AtomicUsize = AtomicUsize::new(0);
+    /// 调度器初始化前只有主 CPU 在运行，标签记在这里
+    static BOOT_TAG: Mutex<Option<AllocTag>> = Mutex::new(None);
+
+    #[derive(Debug, Clone, Copy)]
+    pub struct AllocRecord {
+        pub ptr: usize,
+        pub size: usize,
+        pub align: usize,
+        pub seq: u64,
+        pub tag: Option<&'static str>,
+        /// 打标签的位置
+        pub site: Option<&'static Location<'static>>,
+        pub owner: Option<Pid>,
+        /// 分配时的返回地址，由内向外，0 表示结束
+        pub backtrace: [usize; BACKTRACE_DEPTH],
+    }
+
+    impl AllocRecord {
+        pub fn backtrace(&self) -> impl Iterator<Item = usize> + '_ {
+            self.backtrace.iter().copied().take_while(|&a| a != 0)
+        }
+    }
+
+    /// 当前分配标签，随任务保存，任务被抢占或迁移到其他 CPU 后仍然有效
+    #[derive(Debug, Clone, Copy)]
+    pub(crate) struct AllocTag {
+        name: &'static str,
+        site: &'static Location<'static>,
+    }
+
+    /// 离开作用域时恢复之前的标签，`f` panic 时也会执行
+    struct TagGuard {
+        owner: Option<TaskControlBlock>,
+        prev: Option<AllocTag>,
+    }
+
+    impl Drop for TagGuard {
+        fn drop(&mut self) {
+            match &mut self.owner {
+                Some(task) => task.alloc_tag = self.prev,
+                None => *BOOT_TAG.lock() = self.prev,
+            }
+        }
+    }
+
+    /// `f` 执行期间当前任务的分配都记为 `name`，可嵌套
+    #[track_caller]
+    pub fn with_alloc_tag<R>(name: &'static str, f: impl FnOnce() -> R) -> R {
+        let tag = Some(AllocTag {
+            name,
+            site: Location::caller(),
+        });
+        let owner = try_current();
+        let prev = match owner {
+            Some(mut task) => core::mem::replace(&mut task.alloc_tag, tag),
+            None => core::mem::replace(&mut *BOOT_TAG.lock(), tag),
+        };
+        let _guard = TagGuard { owner, prev };
+        f()
+    }
+
+    fn current_tag() -> Option<AllocTag> {
+        match try_current() {
+            Some(task) => task.alloc_tag,
+            None => *BOOT_TAG.lock(),
+        }
+    }
+
+    /// 因记录表满而未被跟踪的分配数
+    pub fn untracked_allocations() -> usize {
+        DROPPED.load(Ordering::Relaxed)
+    }
+
+    /// 沿帧指针链回溯，帧指针须递增且不超出一个内核栈
+    fn backtrace() -> [usize; BACKTRACE_DEPTH] {
+        let mut out = [0; BACKTRACE_DEPTH];
+        let mut fp = PlatformImpl::frame_pointer();
+        let limit = fp.saturating_add(kstack_size());
+        for slot in &mut out {
+            if fp == 0 || fp % 16 != 0 || fp >= limit {
+                break;
+            }
+            let frame = fp as *const usize;
+            let (prev, lr) = unsafe { (frame.read(), frame.add(1).read()) };
+            if lr == 0 {
+                break;
+            }
+            *slot = lr;
+            if prev <= fp {
+                break;
+            }
+            fp = prev;
+        }
+        out
+    }
+
+    fn slot_of(ptr: usize) -> usize {
+        (ptr >> 4).wrapping_mul(0x9e37_79b9_7f4a_7c15) % SLOTS
+    }
+
+    pub(super) fn insert(ptr: *mut u8, layout: Layout, seq: u64) {
+        let tag = current_tag();
+        let record = AllocRecord {
+            ptr: ptr as usize,
+            size: layout.size(),
+            align: layout.align(),
+            seq,
+            tag: tag.map(|t| t.name),
+            site: tag.map(|t| t.site),
+            owner: current_pid(),
+            backtrace: backtrace(),
+        };
+
+        let mut table = TABLE.lock();
+        let start = slot_of(record.ptr);
+        for i in 0..SLOTS {
+            let slot = &mut table[(start + i) % SLOTS];
+            if slot.is_none() {
+                *slot = Some(record);
+                return;
+            }
+        }
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn remove(ptr: *mut u8) {
+        let ptr = ptr as usize;
+        let mut table = TABLE.lock();
+        let start = slot_of(ptr);
+        let mut i = 0;
+        let mut hole = loop {
+            if i == SLOTS {
+                return;
+            }
+            let idx = (start + i) % SLOTS;
+            match &table[idx] {
+                Some(r) if r.ptr == ptr => break idx,
+                None => return,
+                _ => i += 1,
+            }
+        };
+        table[hole] = None;
+
+        // 后移删除，保持探测链连续
+        let mut idx = (hole + 1) % SLOTS;
+        while let Some(r) = table[idx] {
+            let home = slot_of(r.ptr);
+            let dist_hole = (hole + SLOTS - home) % SLOTS;
+            let dist_idx = (idx + SLOTS - home) % SLOTS;
+            if dist_hole < dist_idx {
+                table[hole] = Some(r);
+                table[idx] = None;
+                hole = idx;
+            }
+            idx = (idx + 1) % SLOTS;
+        }
+    }
+
+    /// 查找仍存活的分配记录
+    pub fn find_record(ptr: *const u8) -> Option<AllocRecord> {
+        let ptr = ptr as usize;
+        let table = TABLE.lock();
+        let start = slot_of(ptr);
+        for i in 0..SLOTS {
+            match &table[(start + i) % SLOTS] {
+                Some(r) if r.ptr == ptr => return Some(*r),
+                None => return None,
+                _ => {}
+            }
+        }
+        None
+    }
+
+    /// 持记录表锁遍历，`f` 中不能分配
+    pub(crate) fn for_each_live(mut f: impl FnMut(&AllocRecord)) {
+        TABLE.lock().iter().flatten().for_each(f);
+    }
+
+    pub(super) fn live_since(seq: u64) -> Vec<AllocRecord> {
+        // 记录表持锁期间不能分配，先按数量预留
+        let n = TABLE
+            .lock()
+            .iter()
+            .flatten()
+            .filter(|r| r.seq >= seq)
+            .count();
+        let mut out = Vec::with_capacity(n);
+        let own = out.as_ptr() as usize;
+
+        let table = TABLE.lock();
+        let live = table
+            .iter()
+            .flatten()
+            .filter(|r| r.seq >= seq && r.ptr != own);
+        for r in live {
+            if out.len() == out.capacity() {
+                break;
+            }
+            out.push(*r);
+        }
+        drop(table);
+
+        out.sort_unstable_by_key(|r| r.seq);
+        out
+    }
+}
@@ -0,0 +1,132 @@
+//! 生成扁平设备树（DTB），版本 17
+
+use alloc::vec::Vec;
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_END: u32 = 9;
+const HEADER_SIZE: usize = 40;
+/// 只有结束项的内存保留表
+const RSVMAP_SIZE: usize = 16;
+
+#[derive(Default)]
+pub struct DtbBuilder {
+    structure: Vec<u8>,
+    strings: Vec<u8>,
+    depth: usize,
+}
+
+impl DtbBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn token(&mut self, token: u32) {
+        self.structure.extend_from_slice(&token.to_be_bytes());
+    }
+
+    fn pad(&mut self) {
+        let len = self.structure.len().next_multiple_of(4);
+        self.structure.resize(len, 0);
+    }
+
+    fn string_offset(&mut self, name: &str) -> u32 {
+        let mut offset = 0;
+        for s in self.strings.split(|b| *b == 0) {
+            if s == name.as_bytes() && offset < self.strings.len() {
+                return offset as u32;
+            }
+            offset += s.len() + 1;
+        }
+        let offset = self.strings.len();
+        self.strings.extend_from_slice(name.as_bytes());
+        self.strings.push(0);
+        offset as u32
+    }
+
+    /// 根节点名为空串
+    pub fn begin_node(&mut self, name: &str) {
+        self.token(FDT_BEGIN_NODE);
+        self.structure.extend_from_slice(name.as_bytes());
+        self.structure.push(0);
+        self.pad();
+        self.depth += 1;
+    }
+
+    pub fn end_node(&mut self) {
+        self.token(FDT_END_NODE);
+        self.depth -= 1;
+    }
+
+    pub fn prop(&mut self, name: &str, value: &[u8]) {
+        let name = self.string_offset(name);
+        self.token(FDT_PROP);
+        self.token(value.len() as u32);
+        self.token(name);
+        self.structure.extend_from_slice(value);
+        self.pad();
+    }
+
+    pub fn prop_empty(&mut self, name: &str) {
+        self.prop(name, &[]);
+    }
+
+    pub fn prop_u32(&mut self, name: &str, value: u32) {
+        self.prop_cells(name, &[value]);
+    }
+
+    pub fn prop_cells(&mut self, name: &str, cells: &[u32]) {
+        let value: Vec<u8> = cells.iter().flat_map(|c| c.to_be_bytes()).collect();
+        self.prop(name, &value);
+    }
+
+    /// 64 位值按两个 cell 写入
+    pub fn prop_u64s(&mut self, name: &str, values: &[u64]) {
+        let value: Vec<u8> = values.iter().flat_map(|v| v.to_be_bytes()).collect();
+        self.prop(name, &value);
+    }
+
+    pub fn prop_str(&mut self, name: &str, value: &str) {
+        self.prop_strs(name, &[value]);
+    }
+
+    pub fn prop_strs(&mut self, name: &str, values: &[&str]) {
+        let mut value = Vec::new();
+        for s in values {
+            value.extend_from_slice(s.as_bytes());
+            value.push(0);
+        }
+        self.prop(name, &value);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        assert_eq!(self.depth, 0, "unclosed dtb node");
+        self.token(FDT_END);
+
+        let off_struct = HEADER_SIZE + RSVMAP_SIZE;
+        let off_strings = off_struct + self.structure.len();
+        let total = off_strings + self.strings.len();
+
+        let mut out = Vec::with_capacity(total);
+        for v in [
+            FDT_MAGIC,
+            total as u32,
+            off_struct as u32,
+            off_strings as u32,
+            HEADER_SIZE as u32,
+            17,
+            16,
+            0,
+            self.strings.len() as u32,
+            self.structure.len() as u32,
+        ] {
+            out.extend_from_slice(&v.to_be_bytes());
+        }
+        out.resize(off_struct, 0);
+        out.extend_from_slice(&self.structure);
+        out.extend_from_slice(&self.strings);
+        out
+    }
+}
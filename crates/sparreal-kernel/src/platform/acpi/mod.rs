@@ -0,0 +1,275 @@
+//! 无设备树的 ACPI 平台：从 ACPI 表和 UEFI 内存表获取平台信息
+
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use arrayvec::ArrayVec;
+use core::ops::Range;
+
+use crate::mem::PhysAddr;
+use crate::platform_if::{RegionKind, is_mmu_enabled};
+
+use super::{
+    CPUHardId, CPUInfo, EFI_ACPI_MEMORY_NVS, EFI_ACPI_RECLAIM_MEMORY, EFI_PAGE_SIZE, EnableMethod,
+    SerialPort, efi_memory_descs, efi_usable, push_merged,
+};
+
+mod dtb;
+mod tables;
+
+pub use dtb::DtbBuilder;
+pub use tables::{Gicc, Gtdt, Madt, PsciMethod, Sdt, Spcr, TimerIrq};
+
+const GIC_PHANDLE: u32 = 1;
+
+fn phys_to_virt(addr: PhysAddr) -> *const u8 {
+    if is_mmu_enabled() {
+        (addr + RegionKind::Other.va_offset()).raw() as _
+    } else {
+        addr.raw() as _
+    }
+}
+
+#[derive(Clone)]
+pub struct Acpi {
+    rsdp: PhysAddr,
+    memorys: ArrayVec<Range<PhysAddr>, 12>,
+    firmware: ArrayVec<Range<PhysAddr>, 12>,
+}
+
+impl Acpi {
+    /// `memory_map` 为退出启动服务时的 UEFI 内存描述符数组，每项 `desc_size` 字节
+    ///
+    /// 内存表所在的页之后会交给堆，这里复制出需要的部分。
+    pub fn new(rsdp: PhysAddr, memory_map: &[u8], desc_size: usize) -> Self {
+        let mut memorys = ArrayVec::new();
+        let mut firmware = ArrayVec::new();
+
+        let descs = || efi_memory_descs(memory_map, desc_size);
+
+        // 此时还没有堆，不能排序，按地址从小到大逐个取出
+        let mut last = None;
+        while let Some((ty, range)) = descs()
+            .filter(|(_, r)| last.is_none_or(|l| r.start.raw() > l))
+            .min_by_key(|(_, r)| r.start.raw())
+        {
+            last = Some(range.start.raw());
+            match ty {
+                _ if efi_usable(ty) => push_merged(&mut memorys, range),
+                EFI_ACPI_RECLAIM_MEMORY | EFI_ACPI_MEMORY_NVS => push_merged(&mut firmware, range),
+                _ => {}
+            }
+        }
+
+        // RSDP 可能不在 ACPI 内存中
+        let rsdp_page = rsdp.align_down(EFI_PAGE_SIZE);
+        if !firmware.iter().any(|r| r.contains(&rsdp)) {
+            push_merged(&mut firmware, rsdp_page..rsdp_page + EFI_PAGE_SIZE);
+        }
+
+        Self {
+            rsdp,
+            memorys,
+            firmware,
+        }
+    }
+
+    pub fn rsdp(&self) -> PhysAddr {
+        self.rsdp
+    }
+
+    fn root(&self) -> Option<Sdt> {
+        unsafe { tables::root_table(self.rsdp) }
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = Sdt> {
+        self.root().into_iter().flat_map(tables::tables)
+    }
+
+    pub fn find_table(&self, signature: &[u8; 4]) -> Option<Sdt> {
+        self.tables().find(|t| t.signature() == signature)
+    }
+
+    /// 可用的内存，相邻区间已合并
+    pub fn memorys(&self) -> ArrayVec<Range<PhysAddr>, 12> {
+        self.memorys.clone()
+    }
+
+    /// ACPI 表所在的内存，需映射后才能在开启 MMU 后访问
+    pub fn firmware_regions(&self) -> &[Range<PhysAddr>] {
+        &self.firmware
+    }
+
+    /// 根表的 OEM ID 和表 ID
+    pub fn model_name(&self) -> Option<String> {
+        let root = self.root()?;
+        let (oem, table) = root.oem();
+        let oem = core::str::from_utf8(oem).ok()?.trim();
+        let table = core::str::from_utf8(table).ok()?.trim();
+        Some(format!("{oem} {table}").trim().to_string())
+    }
+
+    pub fn madt(&self) -> Option<Madt> {
+        self.find_table(b"APIC").map(|t| Madt::parse(&t))
+    }
+
+    pub fn gtdt(&self) -> Option<Gtdt> {
+        Gtdt::parse(&self.find_table(b"GTDT")?)
+    }
+
+    pub fn spcr(&self) -> Option<Spcr> {
+        Spcr::parse(&self.find_table(b"SPCR")?)
+    }
+
+    pub fn psci(&self) -> Option<PsciMethod> {
+        tables::fadt_psci(&self.find_table(b"FACP")?)
+    }
+
+    /// 拓扑按 MPIDR 推断
+    pub fn cpus(&self) -> Vec<CPUInfo> {
+        let enable_method = match self.psci() {
+            Some(_) => EnableMethod::Psci,
+            None => EnableMethod::Unknown,
+        };
+        self.madt()
+            .map(|m| m.gicc)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|c| c.enabled)
+            .map(|c| CPUInfo {
+                enable_method,
+                ..CPUInfo::new(CPUHardId(c.mpidr as usize & 0xff00ffffff))
+            })
+            .collect()
+    }
+
+    pub fn debugcon(&self) -> Option<SerialPort> {
+        let spcr = self.spcr()?;
+        Some(SerialPort::new(
+            (spcr.addr as usize).into(),
+            Some(0x1000),
+            spcr.compatibles().iter().copied(),
+        ))
+    }
+
+    /// 按 ACPI 表生成等价的设备树，供只能从设备树探测的驱动使用
+    pub fn to_fdt(&self) -> Vec<u8> {
+        let mut b = DtbBuilder::new();
+        b.begin_node("");
+        b.prop_u32("#address-cells", 2);
+        b.prop_u32("#size-cells", 2);
+        b.prop_str("model", &self.model_name().unwrap_or_default());
+        b.prop_u32("interrupt-parent", GIC_PHANDLE);
+
+        let spcr = self.spcr().filter(|s| !s.compatibles().is_empty());
+        b.begin_node("chosen");
+        if let Some(spcr) = &spcr {
+            b.prop_str("stdout-path", &format!("/serial@{:x}", spcr.addr));
+        }
+        b.end_node();
+
+        for m in &self.memorys {
+            b.begin_node(&format!("memory@{:x}", m.start.raw()));
+            b.prop_str("device_type", "memory");
+            b.prop_u64s("reg", &[m.start.raw() as _, (m.end - m.start) as _]);
+            b.end_node();
+        }
+
+        let madt = self.madt().unwrap_or_default();
+        b.begin_node("cpus");
+        b.prop_u32("#address-cells", 2);
+        b.prop_u32("#size-cells", 0);
+        for c in madt.gicc.iter().filter(|c| c.enabled) {
+            let mpidr = c.mpidr & 0xff00ffffff;
+            b.begin_node(&format!("cpu@{mpidr:x}"));
+            b.prop_str("device_type", "cpu");
+            b.prop_str("compatible", "arm,armv8");
+            b.prop_u64s("reg", &[mpidr]);
+            b.prop_str("enable-method", "psci");
+            b.end_node();
+        }
+        b.end_node();
+
+        if let Some(gicd) = madt.gicd {
+            b.begin_node(&format!("interrupt-controller@{gicd:x}"));
+            if madt.gic_version() >= 3 {
+                b.prop_str("compatible", "arm,gic-v3");
+                let gicr = madt.gicr.first().map(|&(base, len)| (base, len as u64));
+                let gicr = gicr.or_else(|| {
+                    // 没有发现区域时用各 CPU 的 redistributor 覆盖的范围
+                    let bases = madt.gicc.iter().map(|c| c.gicr).filter(|b| *b != 0);
+                    let start = bases.clone().min()?;
+                    Some((start, bases.max()? + 0x20000 - start))
+                });
+                let (gicr, len) = gicr.unwrap_or_default();
+                b.prop_u64s("reg", &[gicd, 0x10000, gicr, len]);
+            } else {
+                b.prop_strs("compatible", &["arm,cortex-a15-gic", "arm,gic-400"]);
+                let gicc = madt.gicc.first().map(|c| c.base).unwrap_or_default();
+                b.prop_u64s("reg", &[gicd, 0x1000, gicc, 0x2000]);
+            }
+            b.prop_empty("interrupt-controller");
+            b.prop_u32("#interrupt-cells", 3);
+            b.prop_u32("phandle", GIC_PHANDLE);
+            b.end_node();
+        }
+
+        if let Some(gtdt) = self.gtdt() {
+            b.begin_node("timer");
+            b.prop_str("compatible", "arm,armv8-timer");
+            b.prop_u32("interrupt-parent", GIC_PHANDLE);
+            let cells: Vec<u32> = [gtdt.secure_el1, gtdt.el1, gtdt.virtual_el1, gtdt.el2]
+                .iter()
+                .flat_map(|t| {
+                    [
+                        1,
+                        t.gsiv.saturating_sub(16),
+                        irq_flags(t.edge, t.active_low),
+                    ]
+                })
+                .collect();
+            b.prop_cells("interrupts", &cells);
+            b.prop_empty("always-on");
+            b.end_node();
+        }
+
+        if let Some(method) = self.psci() {
+            b.begin_node("psci");
+            b.prop_strs("compatible", &["arm,psci-1.0", "arm,psci-0.2", "arm,psci"]);
+            b.prop_str(
+                "method",
+                match method {
+                    PsciMethod::Smc => "smc",
+                    PsciMethod::Hvc => "hvc",
+                },
+            );
+            b.end_node();
+        }
+
+        if let Some(spcr) = spcr {
+            b.begin_node(&format!("serial@{:x}", spcr.addr));
+            b.prop_strs("compatible", spcr.compatibles());
+            b.prop_u64s("reg", &[spcr.addr, 0x1000]);
+            if let Some(gsiv) = spcr.gsiv {
+                b.prop_u32("interrupt-parent", GIC_PHANDLE);
+                b.prop_cells("interrupts", &[0, gsiv.saturating_sub(32), 4]);
+            }
+            b.end_node();
+        }
+
+        b.end_node();
+        b.finish()
+    }
+}
+
+/// GIC 设备树绑定中第三个 cell 的触发方式
+fn irq_flags(edge: bool, active_low: bool) -> u32 {
+    match (edge, active_low) {
+        (true, false) => 1,
+        (true, true) => 2,
+        (false, false) => 4,
+        (false, true) => 8,
+    }
+}
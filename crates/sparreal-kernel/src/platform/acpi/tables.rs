@@ -0,0 +1,272 @@
+//! 启动所需的 ACPI 表：RSDP/XSDT、MADT、GTDT、SPCR 和 FADT
+
+use alloc::vec::Vec;
+
+use crate::mem::PhysAddr;
+
+use super::phys_to_virt;
+
+const SDT_HEADER_SIZE: usize = 36;
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Option<[u8; N]> {
+    data.get(offset..offset + N)?.try_into().ok()
+}
+
+pub(super) fn u8_at(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+pub(super) fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+pub(super) fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+pub(super) fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    read(data, offset).map(u64::from_le_bytes)
+}
+
+fn checksum_ok(data: &[u8]) -> bool {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) == 0
+}
+
+/// 带标准表头的系统描述表
+#[derive(Clone, Copy)]
+pub struct Sdt {
+    data: &'static [u8],
+}
+
+impl Sdt {
+    /// # Safety
+    ///
+    /// `addr` 处是固件提供的 ACPI 表，且已可访问
+    unsafe fn from_phys(addr: PhysAddr) -> Option<Self> {
+        let ptr = phys_to_virt(addr);
+        let header = unsafe { core::slice::from_raw_parts(ptr, SDT_HEADER_SIZE) };
+        let len = u32_at(header, 4)? as usize;
+        if len < SDT_HEADER_SIZE {
+            return None;
+        }
+        let data = unsafe { core::slice::from_raw_parts(ptr, len) };
+        checksum_ok(data).then_some(Self { data })
+    }
+
+    pub fn signature(&self) -> &[u8] {
+        &self.data[..4]
+    }
+
+    pub fn revision(&self) -> u8 {
+        self.data[8]
+    }
+
+    /// 六字节 OEM ID 和八字节 OEM 表 ID
+    pub fn oem(&self) -> (&[u8], &[u8]) {
+        (&self.data[10..16], &self.data[16..24])
+    }
+
+    /// 表头之后的内容
+    pub fn body(&self) -> &'static [u8] {
+        &self.data[SDT_HEADER_SIZE..]
+    }
+}
+
+/// 校验 RSDP，返回 XSDT（ACPI 2.0 以上）或 RSDT
+///
+/// # Safety
+///
+/// `rsdp` 为固件提供的 RSDP 地址
+pub(super) unsafe fn root_table(rsdp: PhysAddr) -> Option<Sdt> {
+    let data = unsafe { core::slice::from_raw_parts(phys_to_virt(rsdp), 36) };
+    if &data[..8] != b"RSD PTR " || !checksum_ok(&data[..20]) {
+        return None;
+    }
+    let root = if data[15] >= 2 && checksum_ok(data) {
+        u64_at(data, 24)? as usize
+    } else {
+        u32_at(data, 16)? as usize
+    };
+    let sdt = unsafe { Sdt::from_phys(root.into())? };
+    matches!(sdt.signature(), b"XSDT" | b"RSDT").then_some(sdt)
+}
+
+/// 根表列出的各个表
+pub(super) fn tables(root: Sdt) -> impl Iterator<Item = Sdt> {
+    let wide = root.signature() == b"XSDT";
+    let size = if wide { 8 } else { 4 };
+    root.body().chunks_exact(size).filter_map(move |e| {
+        let addr = if wide {
+            u64_at(e, 0)? as usize
+        } else {
+            u32_at(e, 0)? as usize
+        };
+        unsafe { Sdt::from_phys(addr.into()) }
+    })
+}
+
+/// MADT 中 GIC 的 CPU 接口，每个 CPU 一项
+#[derive(Debug, Clone, Copy)]
+pub struct Gicc {
+    pub uid: u32,
+    pub mpidr: u64,
+    pub enabled: bool,
+    /// GICv2 CPU 接口
+    pub base: u64,
+    /// GICv3 本 CPU 的 redistributor
+    pub gicr: u64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Madt {
+    pub gicd: Option<u64>,
+    /// 1..4，0 表示由 GICR 是否存在推断
+    pub gic_version: u8,
+    pub gicc: Vec<Gicc>,
+    /// redistributor 发现区域（基址，长度）
+    pub gicr: Vec<(u64, u32)>,
+    pub its: Vec<u64>,
+}
+
+impl Madt {
+    pub fn parse(sdt: &Sdt) -> Self {
+        let mut out = Self::default();
+        // 本地中断控制器地址与标志之后是变长条目
+        let mut entries = sdt.body().get(8..).unwrap_or_default();
+        while let (Some(ty), Some(len)) = (u8_at(entries, 0), u8_at(entries, 1)) {
+            let len = len as usize;
+            if len < 2 || len > entries.len() {
+                break;
+            }
+            let e = &entries[..len];
+            match ty {
+                0x0b => {
+                    if let (Some(uid), Some(flags), Some(base), Some(gicr), Some(mpidr)) = (
+                        u32_at(e, 8),
+                        u32_at(e, 12),
+                        u64_at(e, 32),
+                        u64_at(e, 60),
+                        u64_at(e, 68),
+                    ) {
+                        out.gicc.push(Gicc {
+                            uid,
+                            mpidr,
+                            enabled: flags & 1 != 0,
+                            base,
+                            gicr,
+                        });
+                    }
+                }
+                0x0c => {
+                    out.gicd = u64_at(e, 8);
+                    out.gic_version = u8_at(e, 20).unwrap_or_default();
+                }
+                0x0e => {
+                    if let (Some(base), Some(len)) = (u64_at(e, 4), u32_at(e, 12)) {
+                        out.gicr.push((base, len));
+                    }
+                }
+                0x0f => out.its.extend(u64_at(e, 8)),
+                _ => {}
+            }
+            entries = &entries[len..];
+        }
+        out
+    }
+
+    pub fn gic_version(&self) -> u8 {
+        match self.gic_version {
+            0 if !self.gicr.is_empty() || self.gicc.iter().any(|c| c.gicr != 0) => 3,
+            0 => 2,
+            v => v,
+        }
+    }
+}
+
+/// GTDT 中的中断号（GSIV）和触发方式
+#[derive(Debug, Clone, Copy)]
+pub struct TimerIrq {
+    pub gsiv: u32,
+    pub edge: bool,
+    pub active_low: bool,
+}
+
+/// 通用定时器的各个 PPI
+#[derive(Debug, Clone, Copy)]
+pub struct Gtdt {
+    pub secure_el1: TimerIrq,
+    pub el1: TimerIrq,
+    pub virtual_el1: TimerIrq,
+    pub el2: TimerIrq,
+}
+
+impl Gtdt {
+    pub fn parse(sdt: &Sdt) -> Option<Self> {
+        let body = sdt.body();
+        let irq = |offset| {
+            let flags = u32_at(body, offset + 4)?;
+            Some(TimerIrq {
+                gsiv: u32_at(body, offset)?,
+                edge: flags & 1 != 0,
+                active_low: flags & 2 != 0,
+            })
+        };
+        Some(Self {
+            secure_el1: irq(12)?,
+            el1: irq(20)?,
+            virtual_el1: irq(28)?,
+            el2: irq(36)?,
+        })
+    }
+}
+
+/// SPCR 描述的串口控制台
+#[derive(Debug, Clone, Copy)]
+pub struct Spcr {
+    pub interface: u8,
+    pub addr: u64,
+    pub gsiv: Option<u32>,
+}
+
+impl Spcr {
+    pub fn parse(sdt: &Sdt) -> Option<Self> {
+        let body = sdt.body();
+        // 基址为 GAS，只支持系统内存空间
+        if u8_at(body, 4)? != 0 {
+            return None;
+        }
+        // 中断类型 bit3 为 GIC
+        let gsiv = (u8_at(body, 16)? & 0b1000 != 0).then(|| u32_at(body, 18));
+        Some(Self {
+            interface: u8_at(body, 0)?,
+            addr: u64_at(body, 8)?,
+            gsiv: gsiv.flatten(),
+        })
+    }
+
+    /// 接口类型对应的设备树 compatible
+    pub fn compatibles(&self) -> &'static [&'static str] {
+        match self.interface {
+            0x00 | 0x01 | 0x12 => &["ns16550a"],
+            0x03 => &["arm,pl011", "arm,primecell"],
+            0x0d | 0x0e => &["arm,sbsa-uart"],
+            _ => &[],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PsciMethod {
+    Smc,
+    Hvc,
+}
+
+/// FADT 中 ARM 启动架构标志给出的 PSCI 调用方式
+pub fn fadt_psci(sdt: &Sdt) -> Option<PsciMethod> {
+    let flags = u16_at(sdt.data, 129)?;
+    match flags & 0b11 {
+        0b01 => Some(PsciMethod::Smc),
+        0b11 => Some(PsciMethod::Hvc),
+        _ => None,
+    }
+}
@@ -0,0 +1,125 @@
+//! CPU 的启动方式与拓扑
+
+use alloc::vec::Vec;
+
+use crate::mem::PhysAddr;
+
+use super::{CPUHardId, cpu_list};
+
+/// 启动从核的方式，设备树 `enable-method`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EnableMethod {
+    /// 没有给出，启动核通常如此
+    None,
+    Psci,
+    /// 把入口地址写入 `cpu-release-addr` 后 `sev`
+    SpinTable {
+        release_addr: PhysAddr,
+    },
+    Unknown,
+}
+
+/// CPU 在系统中的位置
+///
+/// `cluster` 在整个系统中唯一，`core` 是在所属簇中的序号，`thread` 是在所属核中的序号。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuTopology {
+    pub package: usize,
+    pub cluster: usize,
+    pub core: usize,
+    pub thread: usize,
+}
+
+impl CpuTopology {
+    /// 没有 `cpu-map` 时按 MPIDR 亲和性推断：Aff0 为核，Aff1 为簇，更高的为封装
+    pub fn from_mpidr(mpidr: usize) -> Self {
+        Self {
+            package: (mpidr >> 16) & 0xff | ((mpidr >> 32) & 0xff) << 8,
+            cluster: (mpidr >> 8) & 0xffffff,
+            core: mpidr & 0xff,
+            thread: 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CPUInfo {
+    pub cpu_id: CPUHardId,
+    pub enable_method: EnableMethod,
+    pub topology: CpuTopology,
+    /// 相对性能，`capacity-dmips-mhz`
+    pub capacity: Option<u32>,
+    pub numa_node: Option<u32>,
+}
+
+impl CPUInfo {
+    pub fn new(cpu_id: CPUHardId) -> Self {
+        Self {
+            cpu_id,
+            enable_method: EnableMethod::None,
+            topology: CpuTopology::from_mpidr(cpu_id.into()),
+            capacity: None,
+            numa_node: None,
+        }
+    }
+}
+
+/// 可用 CPU 的拓扑
+#[derive(Debug, Clone)]
+pub struct Topology {
+    pub cpus: Vec<CPUInfo>,
+}
+
+impl Topology {
+    pub fn new(cpus: Vec<CPUInfo>) -> Self {
+        Self { cpus }
+    }
+
+    pub fn get(&self, cpu_id: CPUHardId) -> Option<&CPUInfo> {
+        self.cpus.iter().find(|c| c.cpu_id == cpu_id)
+    }
+
+    fn distinct(&self, key: impl Fn(&CPUInfo) -> (usize, usize)) -> usize {
+        let mut keys: Vec<_> = self.cpus.iter().map(key).collect();
+        keys.sort_unstable();
+        keys.dedup();
+        keys.len()
+    }
+
+    pub fn packages(&self) -> usize {
+        self.distinct(|c| (c.topology.package, 0))
+    }
+
+    pub fn clusters(&self) -> usize {
+        self.distinct(|c| (c.topology.package, c.topology.cluster))
+    }
+
+    /// 有 `numa-node-id` 时为不同节点的数量，否则为 1
+    pub fn numa_nodes(&self) -> usize {
+        self.distinct(|c| (c.numa_node.unwrap_or_default() as usize, 0))
+    }
+
+    /// 同一簇中的 CPU，包括自身
+    pub fn cluster_siblings(&self, cpu_id: CPUHardId) -> impl Iterator<Item = &CPUInfo> {
+        let t = self.get(cpu_id).map(|c| c.topology);
+        self.cpus.iter().filter(move |c| {
+            t.is_some_and(|t| c.topology.package == t.package && c.topology.cluster == t.cluster)
+        })
+    }
+
+    /// 同一物理核上的硬件线程，包括自身
+    pub fn thread_siblings(&self, cpu_id: CPUHardId) -> impl Iterator<Item = &CPUInfo> {
+        let t = self.get(cpu_id).map(|c| c.topology);
+        self.cpus.iter().filter(move |c| {
+            t.is_some_and(|t| {
+                let c = c.topology;
+                c.package == t.package && c.cluster == t.cluster && c.core == t.core
+            })
+        })
+    }
+}
+
+/// 经 `maxcpus` 限制后可用 CPU 的拓扑
+pub fn topology() -> Topology {
+    Topology::new(cpu_list())
+}
@@ -1,15 +1,18 @@
 use alloc::string::{String, ToString};
-use alloc::vec::Vec;
+use alloc::{vec, vec::Vec};
 use arrayvec::ArrayVec;
-use core::{ops::Range, ptr::NonNull};
+use core::{ffi::CStr, ops::Range, ptr::NonNull};
 use fdt_parser::{Node, Pci};
-use rdrive::{Phandle, probe::ProbeData};
+use log::{debug, warn};
+use rdrive::{Phandle, probe::ProbeData, register::FdtInfo};
 
+use crate::globals::global_val;
 use crate::irq::IrqInfo;
 use crate::mem::PhysAddr;
+use crate::mem::dma::{DmaRange, DmaRanges};
 use crate::platform_if::{RegionKind, is_mmu_enabled};
 
-use super::{CPUInfo, SerialPort};
+use super::{CPUHardId, CPUInfo, CpuTopology, EnableMethod, PlatformInfoKind, SerialPort};
 
 #[derive(Clone)]
 pub struct Fdt(PhysAddr);
@@ -29,16 +32,7 @@ impl Fdt {
     }
 
     pub fn cpus(&self) -> Vec<CPUInfo> {
-        let fdt = self.get();
-
-        fdt.find_nodes("/cpus/cpu")
-            .map(|cpu| {
-                let reg = cpu.reg().unwrap().next().unwrap();
-                CPUInfo {
-                    cpu_id: super::CPUHardId(reg.address as usize),
-                }
-            })
-            .collect()
+        parse_cpus(&self.get())
     }
 
     pub fn get(&self) -> fdt_parser::Fdt<'static> {
@@ -81,6 +75,59 @@ impl Fdt {
         addr..addr + region.size
     }
 
+    /// `/chosen` 下 `linux,initrd-start` 和 `linux,initrd-end` 给出的 initrd
+    pub fn initrd(&self) -> Option<Range<PhysAddr>> {
+        let fdt = self.get();
+        let chosen = fdt.find_nodes("/chosen").next()?;
+        // 一个或两个 cell，与 `#address-cells` 无关
+        let read = |name| {
+            let raw = chosen.find_property(name)?.raw_value();
+            Some(read_cells(raw, raw.len() / 4) as usize)
+        };
+        let start = read("linux,initrd-start")?;
+        let end = read("linux,initrd-end")?;
+        (end > start).then(|| start.into()..end.into())
+    }
+
+    /// 不能交给堆的内存：设备树自身、`/memreserve/` 和 `/reserved-memory` 的子节点
+    pub fn reserved_regions(&self) -> Vec<Range<PhysAddr>> {
+        let fdt = self.get();
+        let mut out = vec![self.0..self.0 + fdt.total_size()];
+
+        out.extend(fdt.memory_reservation_block().map(|r| {
+            let start = PhysAddr::from(r.address as usize);
+            start..start + r.size
+        }));
+
+        let mut in_rsv = false;
+        for node in fdt.all_nodes() {
+            if node.level <= 2 {
+                in_rsv = node.level == 2 && node.name() == "reserved-memory";
+                continue;
+            }
+            if node.level != 3 || !in_rsv {
+                continue;
+            }
+            for reg in node.reg().into_iter().flatten() {
+                let start = PhysAddr::from(reg.address as usize);
+                out.push(start..start + reg.size.unwrap_or_default());
+            }
+        }
+        out
+    }
+
+    /// `/chosen` 下的 `bootargs`
+    pub fn bootargs(&self) -> Option<&'static str> {
+        let fdt = self.get();
+        let args = fdt
+            .find_nodes("/chosen")
+            .next()?
+            .find_property("bootargs")?
+            .str();
+        // 设备树所在内存一直保留映射
+        Some(unsafe { &*(args as *const str) })
+    }
+
     pub fn debugcon(&self) -> Option<SerialPort> {
         let fdt = self.get();
         let stdout = fdt.chosen()?.stdout()?;
@@ -94,6 +141,156 @@ impl Fdt {
     }
 }
 
+/// `/cpus` 下可用的 CPU，拓扑取自 `cpu-map`，没有时按 MPIDR 推断
+///
+/// 格式错误的节点打印警告后跳过。
+pub fn parse_cpus(fdt: &fdt_parser::Fdt<'_>) -> Vec<CPUInfo> {
+    let mut nodes = fdt
+        .all_nodes()
+        .skip_while(|n| !(n.level == 2 && n.name == "cpus"));
+    let Some(cpus) = nodes.next() else {
+        warn!("no /cpus node");
+        return Vec::new();
+    };
+    let address_cells = prop_u32(&cpus, "#address-cells").map_or(2, |c| c as usize);
+
+    let mut out = Vec::new();
+    let mut phandles = Vec::new();
+    let mut map = CpuMap::default();
+    for node in nodes.take_while(|n| n.level > cpus.level) {
+        if map.level.is_some_and(|l| node.level > l) {
+            map.visit(&node);
+            continue;
+        }
+        map.level = None;
+
+        if node.name == "cpu-map" {
+            map.level = Some(node.level);
+            continue;
+        }
+        if node.level != cpus.level + 1 || prop_str(&node, "device_type") != Some("cpu") {
+            continue;
+        }
+        if prop_str(&node, "status").is_some_and(|s| s != "okay" && s != "ok") {
+            debug!("cpu node {} disabled", node.name);
+            continue;
+        }
+        if let Some(cpu) = parse_cpu(&node, address_cells) {
+            phandles.push(prop_u32(&node, "phandle").map(Phandle::from));
+            out.push(cpu);
+        }
+    }
+
+    if !map.leaves.is_empty() {
+        for (cpu, phandle) in out.iter_mut().zip(phandles) {
+            match map.leaves.iter().find(|(p, _)| Some(*p) == phandle) {
+                Some((_, topology)) => cpu.topology = *topology,
+                None => warn!("cpu {} not in cpu-map", cpu.cpu_id),
+            }
+        }
+    }
+    out
+}
+
+fn parse_cpu(node: &Node<'_>, address_cells: usize) -> Option<CPUInfo> {
+    let Some(reg) = node.find_property("reg").map(|p| p.raw_value()) else {
+        warn!("cpu node {}: no reg", node.name);
+        return None;
+    };
+    if address_cells == 0 || reg.len() < address_cells * 4 {
+        warn!("cpu node {}: bad reg {:02x?}", node.name, reg);
+        return None;
+    }
+    let mut cpu = CPUInfo::new(CPUHardId(read_cells(reg, address_cells) as usize));
+
+    let method = node
+        .find_property("enable-method")
+        .map(|p| cstr(p.raw_value()));
+    cpu.enable_method = match method {
+        None => EnableMethod::None,
+        Some(Some("psci")) => EnableMethod::Psci,
+        Some(Some("spin-table")) => match node
+            .find_property("cpu-release-addr")
+            .map(|p| p.raw_value())
+            .filter(|raw| raw.len() >= 4)
+        {
+            Some(raw) => EnableMethod::SpinTable {
+                release_addr: (read_cells(raw, 2) as usize).into(),
+            },
+            None => {
+                warn!(
+                    "cpu node {}: spin-table without cpu-release-addr",
+                    node.name
+                );
+                EnableMethod::Unknown
+            }
+        },
+        Some(other) => {
+            warn!("cpu node {}: unknown enable-method {other:?}", node.name);
+            EnableMethod::Unknown
+        }
+    };
+    cpu.capacity = prop_u32(node, "capacity-dmips-mhz");
+    cpu.numa_node = prop_u32(node, "numa-node-id");
+    Some(cpu)
+}
+
+/// `/cpus/cpu-map` 的遍历状态
+#[derive(Default)]
+struct CpuMap {
+    /// `cpu-map` 节点的层级，在其子树中时为 `Some`
+    level: Option<usize>,
+    /// 从 `cpu-map` 到当前节点路径上的名称
+    path: Vec<String>,
+    /// 已编号的簇，以路径区分
+    clusters: Vec<String>,
+    leaves: Vec<(Phandle, CpuTopology)>,
+}
+
+impl CpuMap {
+    fn visit(&mut self, node: &Node<'_>) {
+        let depth = node.level - self.level.unwrap_or_default();
+        self.path.truncate(depth - 1);
+        self.path.push(node.name.to_string());
+
+        let Some(cpu) = prop_u32(node, "cpu") else {
+            return;
+        };
+        let index = |prefix: &str| {
+            self.path
+                .iter()
+                .rev()
+                .find_map(|n| n.strip_prefix(prefix)?.parse::<usize>().ok())
+        };
+        let (Some(core), thread) = (index("core"), index("thread")) else {
+            warn!("cpu-map node {} not under a core", node.name);
+            return;
+        };
+        // 簇可以嵌套，取最内层的，按路径在整个系统中编号
+        let cluster_path = match self.path.iter().rposition(|n| n.starts_with("cluster")) {
+            Some(i) => self.path[..=i].join("/"),
+            None => String::new(),
+        };
+        let cluster = match self.clusters.iter().position(|c| *c == cluster_path) {
+            Some(i) => i,
+            None => {
+                self.clusters.push(cluster_path);
+                self.clusters.len() - 1
+            }
+        };
+
+        self.leaves.push((
+            cpu.into(),
+            CpuTopology {
+                package: index("socket").unwrap_or_default(),
+                cluster,
+                core,
+                thread: thread.unwrap_or_default(),
+            },
+        ));
+    }
+}
+
 pub trait GetIrqConfig {
     fn irq_info(&self) -> Option<IrqInfo>;
 }
@@ -145,3 +342,141 @@ impl GetPciIrqConfig for Pci<'_> {
         parse_irq_config(irq.parent, &[raw])
     }
 }
+
+pub trait GetDmaRanges {
+    /// 沿总线层级合成的 CPU 物理地址与设备总线地址的转换
+    fn dma_ranges(&self) -> DmaRanges;
+}
+
+impl GetDmaRanges for Node<'_> {
+    fn dma_ranges(&self) -> DmaRanges {
+        // ACPI 平台生成的设备树不含 dma-ranges
+        let PlatformInfoKind::DeviceTree(fdt) = &global_val().platform_info else {
+            return DmaRanges::identity();
+        };
+        parse_dma_ranges(&fdt.get(), self)
+    }
+}
+
+/// `node` 所在各级总线的 `dma-ranges` 合成的转换，`node` 须属于 `fdt`
+pub fn parse_dma_ranges(fdt: &fdt_parser::Fdt<'_>, node: &Node<'_>) -> DmaRanges {
+    // Node 不提供父节点访问，按先序遍历重建祖先链，节点名指针在 fdt 内唯一
+    let mut stack: Vec<Node> = Vec::new();
+    for n in fdt.all_nodes() {
+        stack.truncate(n.level - 1);
+        if n.name.as_ptr() == node.name.as_ptr() {
+            return translate(&stack);
+        }
+        stack.push(n);
+    }
+    DmaRanges::identity()
+}
+
+impl GetDmaRanges for FdtInfo<'_> {
+    fn dma_ranges(&self) -> DmaRanges {
+        self.node.dma_ranges()
+    }
+}
+
+fn cells(node: &Node, name: &str, default: usize) -> usize {
+    node.find_property(name)
+        .map(|p| p.u32() as usize)
+        .unwrap_or(default)
+}
+
+fn cstr(raw: &[u8]) -> Option<&str> {
+    CStr::from_bytes_until_nul(raw).ok()?.to_str().ok()
+}
+
+/// 不像 `Property::str` 那样在格式错误时 panic
+fn prop_str<'a>(node: &Node<'a>, name: &str) -> Option<&'a str> {
+    cstr(node.find_property(name)?.raw_value())
+}
+
+fn prop_u32(node: &Node<'_>, name: &str) -> Option<u32> {
+    let raw = node.find_property(name)?.raw_value();
+    Some(u32::from_be_bytes(*raw.first_chunk()?))
+}
+
+fn read_cells(raw: &[u8], cells: usize) -> u64 {
+    raw.as_chunks::<4>()
+        .0
+        .iter()
+        .take(cells)
+        .fold(0, |acc, c| (acc << 32) | u32::from_be_bytes(*c) as u64)
+}
+
+/// `ancestors` 从根到父节点，自下而上逐级应用 `dma-ranges`，父节点自身的也包括在内
+///
+/// 某一级的窗口与下一级不相交，或 `dma-ranges` 无法解析时，设备不能做 DMA。
+fn translate(ancestors: &[Node]) -> DmaRanges {
+    // (bus, 当前层地址, size)，None 表示尚未遇到限制
+    let mut windows: Option<Vec<(u64, u64, u64)>> = None;
+
+    for i in (0..ancestors.len()).rev() {
+        let bus = &ancestors[i];
+        // 根节点的 dma-ranges 直接转换到 CPU 地址，按根节点自身的 cell 数解析
+        let parent = &ancestors[i.saturating_sub(1)];
+        let Some(prop) = bus.find_property("dma-ranges") else {
+            continue;
+        };
+        let raw = prop.raw_value();
+        if raw.is_empty() {
+            continue;
+        }
+
+        let child_cells = cells(bus, "#address-cells", 2);
+        let size_cells = cells(bus, "#size-cells", 1);
+        let parent_cells = cells(parent, "#address-cells", 2);
+        let entry = (child_cells + parent_cells + size_cells) * 4;
+        if entry == 0 {
+            warn!("dma-ranges of {}: zero-sized entries", bus.name);
+            return DmaRanges::unreachable();
+        }
+
+        let level: Vec<(u64, u64, u64)> = raw
+            .chunks_exact(entry)
+            .map(|e| {
+                let child = read_cells(e, child_cells);
+                let up = read_cells(&e[child_cells * 4..], parent_cells);
+                let size = read_cells(&e[(child_cells + parent_cells) * 4..], size_cells);
+                (child, up, size)
+            })
+            .collect();
+
+        windows = Some(match windows {
+            None => level,
+            Some(prev) => prev
+                .into_iter()
+                .flat_map(|(bus, addr, size)| {
+                    level.iter().filter_map(move |&(child, up, len)| {
+                        let start = addr.max(child);
+                        let end = (addr + size).min(child + len);
+                        (start < end)
+                            .then(|| (bus + (start - addr), up + (start - child), end - start))
+                    })
+                })
+                .collect(),
+        });
+    }
+
+    match windows {
+        None => DmaRanges::identity(),
+        Some(w) if w.is_empty() => {
+            warn!(
+                "dma-ranges of {} do not overlap",
+                ancestors[ancestors.len() - 1].name
+            );
+            DmaRanges::unreachable()
+        }
+        Some(w) => DmaRanges::new(
+            w.into_iter()
+                .map(|(bus, cpu, size)| DmaRange {
+                    cpu: cpu as usize,
+                    bus,
+                    size,
+                })
+                .collect(),
+        ),
+    }
+}
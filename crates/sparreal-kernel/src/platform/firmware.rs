@@ -0,0 +1,126 @@
+use core::ops::Range;
+
+use arrayvec::{ArrayString, ArrayVec};
+use log::warn;
+
+use crate::mem::PhysAddr;
+
+/// UEFI 内存类型
+const EFI_LOADER_CODE: u32 = 1;
+const EFI_LOADER_DATA: u32 = 2;
+const EFI_BOOT_SERVICES_CODE: u32 = 3;
+const EFI_BOOT_SERVICES_DATA: u32 = 4;
+const EFI_CONVENTIONAL_MEMORY: u32 = 7;
+pub(crate) const EFI_ACPI_RECLAIM_MEMORY: u32 = 9;
+pub(crate) const EFI_ACPI_MEMORY_NVS: u32 = 10;
+pub(crate) const EFI_PAGE_SIZE: usize = 0x1000;
+
+/// 命令行最大长度，超出部分丢弃
+pub const FIRMWARE_BOOTARGS_MAX: usize = 512;
+
+/// 引导固件在设备树和 ACPI 之外提供的信息，目前由 EFI 桩填写
+#[derive(Debug, Clone, Default)]
+pub struct FirmwareInfo {
+    /// 镜像的 `LoadOptions`，优先于 `/chosen` 下的 `bootargs`
+    pub bootargs: Option<ArrayString<FIRMWARE_BOOTARGS_MAX>>,
+    pub framebuffer: Option<Framebuffer>,
+    pub console: Option<FirmwareConsole>,
+    /// UEFI 内存表中运行时服务、保留和 ACPI 等内核不能使用的内存
+    pub reserved: ArrayVec<Range<PhysAddr>, 32>,
+}
+
+impl FirmwareInfo {
+    /// 记录退出启动服务时内存表中不可用的区间，设备树的 `/memory` 可能覆盖它们
+    pub fn add_efi_memory_map(&mut self, memory_map: &[u8], desc_size: usize) {
+        for (ty, range) in efi_memory_descs(memory_map, desc_size) {
+            if !efi_usable(ty) {
+                push_merged(&mut self.reserved, range);
+            }
+        }
+    }
+}
+
+/// 逐项解析 UEFI 内存描述符，返回类型和物理区间
+pub(crate) fn efi_memory_descs(
+    memory_map: &[u8],
+    desc_size: usize,
+) -> impl Iterator<Item = (u32, Range<PhysAddr>)> + '_ {
+    let field = |d: &[u8], offset: usize, len: usize| {
+        let mut buf = [0u8; 8];
+        buf[..len].copy_from_slice(d.get(offset..offset + len)?);
+        Some(u64::from_le_bytes(buf))
+    };
+    memory_map
+        .chunks_exact(desc_size.max(1))
+        .filter_map(move |d| {
+            let ty = field(d, 0, 4)? as u32;
+            let start = field(d, 8, 8)? as usize;
+            let pages = field(d, 24, 8)? as usize;
+            Some((
+                ty,
+                PhysAddr::from(start)..(start + pages * EFI_PAGE_SIZE).into(),
+            ))
+        })
+}
+
+/// 退出启动服务后可交给内核的内存类型
+pub(crate) fn efi_usable(ty: u32) -> bool {
+    matches!(
+        ty,
+        EFI_LOADER_CODE
+            | EFI_LOADER_DATA
+            | EFI_BOOT_SERVICES_CODE
+            | EFI_BOOT_SERVICES_DATA
+            | EFI_CONVENTIONAL_MEMORY
+    )
+}
+
+/// 把相邻的区间合并后加入 `out`，放不下的丢弃
+pub(crate) fn push_merged<const N: usize>(
+    out: &mut ArrayVec<Range<PhysAddr>, N>,
+    range: Range<PhysAddr>,
+) {
+    if let Some(last) = out.last_mut()
+        && last.end == range.start
+    {
+        last.end = range.end;
+        return;
+    }
+    if out.try_push(range.clone()).is_err() {
+        warn!(
+            "too many memory regions, drop [{}, {})",
+            range.start, range.end
+        );
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+    /// 每像素 32 位，依次为 R、G、B 和保留字节
+    Rgb,
+    /// 每像素 32 位，依次为 B、G、R 和保留字节
+    Bgr,
+    /// 每像素 32 位，各分量由掩码给出
+    Bitmask { red: u32, green: u32, blue: u32 },
+}
+
+/// 固件已设置好模式的线性帧缓冲
+#[derive(Debug, Clone, Copy)]
+pub struct Framebuffer {
+    pub addr: PhysAddr,
+    pub size: usize,
+    pub width: u32,
+    pub height: u32,
+    /// 每行的像素数，可能大于 `width`
+    pub stride: u32,
+    pub format: PixelFormat,
+}
+
+/// 固件控制台的参数，串口地址仍由 FDT 或 SPCR 给出
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FirmwareConsole {
+    /// Serial I/O 协议的波特率，没有串口时为 `None`
+    pub baud_rate: Option<u64>,
+    /// 文本控制台的列数和行数
+    pub text_size: Option<(usize, usize)>,
+}
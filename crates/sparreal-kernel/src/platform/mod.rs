@@ -3,19 +3,30 @@ use core::hint::spin_loop;
 use core::{ffi::CStr, fmt::Display, ops::Range};
 use log::error;
 
+use acpi::Acpi;
 use fdt::Fdt;
 use rdrive::register::DriverRegister;
 
+use crate::cmdline;
 use crate::globals::global_val;
 use crate::mem::PhysAddr;
 use crate::mem::region::boot_regions;
 use crate::platform_if::*;
 
+pub mod acpi;
+mod cpu;
 pub mod fdt;
+mod firmware;
 
+pub use cpu::{CPUInfo, CpuTopology, EnableMethod, Topology, topology};
+pub use firmware::*;
+
+// 开启 MMU 前还没有堆，不能装箱
+#[allow(clippy::large_enum_variant)]
 #[derive(Clone)]
 pub enum PlatformInfoKind {
     DeviceTree(Fdt),
+    Acpi(Acpi),
 }
 
 unsafe impl Send for PlatformInfoKind {}
@@ -25,6 +36,11 @@ impl PlatformInfoKind {
         PlatformInfoKind::DeviceTree(Fdt::new(addr))
     }
 
+    /// `memory_map` 为 UEFI 内存描述符数组，每项 `desc_size` 字节
+    pub fn new_acpi(rsdp: PhysAddr, memory_map: &[u8], desc_size: usize) -> Self {
+        PlatformInfoKind::Acpi(Acpi::new(rsdp, memory_map, desc_size))
+    }
+
     pub fn memorys(&self) -> impl Iterator<Item = Range<PhysAddr>> {
         let mut out: [Option<Range<PhysAddr>>; 24] =
             unsafe { core::mem::MaybeUninit::zeroed().assume_init() };
@@ -49,6 +65,12 @@ impl PlatformInfoKind {
                     len += 1;
                 }
             }
+            PlatformInfoKind::Acpi(acpi) => {
+                for m in acpi.memorys() {
+                    out[len] = Some(m);
+                    len += 1;
+                }
+            }
         }
 
         let mut iter = 0;
@@ -66,14 +88,58 @@ impl PlatformInfoKind {
     pub fn debugcon(&self) -> Option<SerialPort> {
         match self {
             Self::DeviceTree(fdt) => fdt.debugcon(),
+            Self::Acpi(acpi) => acpi.debugcon(),
+        }
+    }
+
+    /// 引导程序加载的 initrd
+    pub fn initrd(&self) -> Option<Range<PhysAddr>> {
+        match self {
+            Self::DeviceTree(fdt) => fdt.initrd(),
+            Self::Acpi(_) => None,
+        }
+    }
+
+    /// 固件占用、不能交给堆的内存
+    pub fn reserved_regions(&self) -> Vec<Range<PhysAddr>> {
+        let mut out = match self {
+            Self::DeviceTree(fdt) => fdt.reserved_regions(),
+            Self::Acpi(acpi) => acpi.firmware_regions().to_vec(),
+        };
+        out.extend(self.initrd());
+        out
+    }
+
+    /// 内核命令行
+    pub fn bootargs(&self) -> Option<&'static str> {
+        match self {
+            Self::DeviceTree(fdt) => fdt.bootargs(),
+            Self::Acpi(_) => None,
         }
     }
 }
 
+/// 可用的 CPU，按 `maxcpus` 参数限制数量，启动 CPU 总会保留
 pub fn cpu_list() -> Vec<CPUInfo> {
-    match &global_val().platform_info {
+    let mut cpus = match &global_val().platform_info {
         PlatformInfoKind::DeviceTree(fdt) => fdt.cpus(),
+        PlatformInfoKind::Acpi(acpi) => acpi.cpus(),
+    };
+
+    let max = cmdline::MAXCPUS.get();
+    if max > 0 && cpus.len() > max {
+        let cpu0 = cpu_hard_id();
+        let mut others = max - cpus.iter().any(|c| c.cpu_id == cpu0) as usize;
+        cpus.retain(|c| {
+            if c.cpu_id == cpu0 {
+                return true;
+            }
+            let keep = others > 0;
+            others = others.saturating_sub(1);
+            keep
+        });
     }
+    cpus
 }
 
 pub fn cpu_hard_id() -> CPUHardId {
@@ -83,6 +149,7 @@ pub fn cpu_hard_id() -> CPUHardId {
 pub fn platform_name() -> String {
     match &global_val().platform_info {
         PlatformInfoKind::DeviceTree(fdt) => fdt.model_name().unwrap_or_default(),
+        PlatformInfoKind::Acpi(acpi) => acpi.model_name().unwrap_or_default(),
     }
 }
 
@@ -100,24 +167,39 @@ pub fn memory_main_available(
         .find(|m| m.contains(&text_end))
         .ok_or("can not find main memory")?;
 
-    let mut start = PhysAddr::new(0);
-    for rsv in boot_regions() {
-        if main_memory.contains(&rsv.range.end) && rsv.range.end > start {
-            start = rsv.range.end;
+    // 内核之后还可能有 initrd 等保留区，取它们之间最大的空闲区间
+    let mut cursor = text_end;
+    let mut best = cursor..cursor;
+    loop {
+        let next = boot_regions()
+            .iter()
+            .map(|rsv| rsv.range)
+            .filter(|r| main_memory.contains(&r.start) && r.end > cursor)
+            .min_by_key(|r| r.start.raw());
+
+        let start = cursor.align_up(page_size());
+        let end = next.as_ref().map_or(main_memory.end, |r| r.start);
+        if end.raw() > start.raw() && end - start > best.end - best.start {
+            best = start..end;
+        }
+
+        match next {
+            Some(r) => cursor = r.end,
+            None => break,
         }
     }
-    start = start.align_up(0x1000);
-    Ok(start..main_memory.end)
+    Ok(best)
 }
 
 pub fn regsions() -> Vec<BootRegion> {
     let mut ret = boot_regions().to_vec();
 
-    let main_available = memory_main_available(&global_val().platform_info).unwrap();
+    // DMA 池已从主内存中划出，不在线性区中映射
+    let main_available = global_val().main_memory.clone();
     ret.push(BootRegion::new(
         main_available.clone(),
         c"main mem",
-        AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute,
+        AccessSetting::Read | AccessSetting::Write,
         CacheSetting::Normal,
         RegionKind::Other,
     ));
@@ -130,18 +212,40 @@ pub fn regsions() -> Vec<BootRegion> {
         ret.push(BootRegion::new(
             memory,
             c"memory",
-            AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute,
+            AccessSetting::Read | AccessSetting::Write,
             CacheSetting::Normal,
             RegionKind::Other,
         ));
     }
 
+    if let PlatformInfoKind::Acpi(acpi) = &global_val().platform_info {
+        for table in acpi.firmware_regions() {
+            ret.push(BootRegion::new(
+                table.clone(),
+                c"acpi",
+                AccessSetting::Read,
+                CacheSetting::Normal,
+                RegionKind::Other,
+            ));
+        }
+    }
+
     ret
 }
 
+/// 不能交给堆的内存：平台信息给出的保留区、UEFI 内存表中的非常规内存和帧缓冲
+pub fn reserved_regions() -> Vec<Range<PhysAddr>> {
+    let g = global_val();
+    let mut out = g.platform_info.reserved_regions();
+    out.extend(g.firmware.reserved.iter().cloned());
+    out.extend(g.firmware.framebuffer.map(|fb| fb.addr..fb.addr + fb.size));
+    out
+}
+
 pub fn phys_memorys() -> ArrayVec<Range<PhysAddr>, 12> {
     match &global_val().platform_info {
         PlatformInfoKind::DeviceTree(fdt) => fdt.memorys(),
+        PlatformInfoKind::Acpi(acpi) => acpi.memorys(),
     }
 }
 
@@ -193,11 +297,6 @@ pub fn app_main() {
     unsafe { __sparreal_rt_main() }
 }
 
-#[derive(Debug)]
-pub struct CPUInfo {
-    pub cpu_id: CPUHardId,
-}
-
 #[derive(Debug, Clone, Copy)]
 pub struct SerialPort {
     pub addr: PhysAddr,
@@ -272,6 +371,12 @@ impl From<usize> for CPUId {
 #[repr(transparent)]
 pub struct CPUHardId(usize);
 
+impl From<CPUHardId> for usize {
+    fn from(value: CPUHardId) -> Self {
+        value.0
+    }
+}
+
 // impl CPUHardId {
 //     pub(crate) unsafe fn new(id: usize) -> Self {
 //         Self(id)
@@ -3,6 +3,7 @@ pub use rdrive::register::DriverRegisterSlice;
 pub use sparreal_macros::api_impl;
 use sparreal_macros::api_trait;
 
+pub use crate::cmdline::KernelParamSlice;
 pub use crate::mem::region::BootRsvRegionVec;
 
 #[api_trait]
@@ -41,6 +42,16 @@ pub trait Platform {
     ///
     unsafe fn cpu_context_switch(prev_tcb: *mut u8, next_tcb: *mut u8);
 
+    /// 以 `sp` 为栈从 `pc` 开始执行用户态代码，不再返回
+    ///
+    /// # Safety
+    ///
+    /// 当前用户页表已映射 `pc` 和 `sp`
+    unsafe fn enter_user(pc: usize, sp: usize) -> !;
+
+    /// 当前帧指针，帧记录为 `[上一帧指针, 返回地址]`，用于回溯调用栈
+    fn frame_pointer() -> usize;
+
     fn wait_for_interrupt();
 
     fn irq_all_enable();
@@ -53,6 +64,30 @@ pub trait Platform {
     fn dcache_range(op: CacheOp, addr: usize, size: usize);
 
     fn driver_registers() -> DriverRegisterSlice;
+
+    fn kernel_params() -> KernelParamSlice;
+
+    fn syscalls() -> SyscallSlice;
+}
+
+/// `.kernel.syscall` 段，内容为 `syscall::KernelSyscall` 数组
+#[repr(C)]
+pub struct SyscallSlice {
+    data: *const u8,
+    len: usize,
+}
+
+impl SyscallSlice {
+    pub fn from_raw(data: &'static [u8]) -> Self {
+        Self {
+            data: data.as_ptr(),
+            len: data.len(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &'static [u8] {
+        unsafe { core::slice::from_raw_parts(self.data, self.len) }
+    }
 }
 
 #[cfg(feature = "mmu")]
@@ -81,6 +116,13 @@ pub trait MMU {
     fn table_level() -> usize;
     fn new_pte(config: PTEGeneric) -> usize;
     fn read_pte(pte: usize) -> PTEGeneric;
+    /// 硬件维护的页状态
+    fn page_state_tracked() -> PageState;
+    fn read_page_state(pte: usize) -> PageState;
+    /// 返回清除 `clear` 状态后的表项
+    fn clear_page_state(pte: usize, clear: PageState) -> usize;
+    /// 返回由硬件维护脏状态的末级项，不维护时原样返回；表描述符不能使用
+    fn track_dirty(pte: usize) -> usize;
     fn enable_mmu(stack_top: usize, jump_to: usize) -> !;
 }
 
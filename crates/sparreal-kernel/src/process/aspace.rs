@@ -0,0 +1,167 @@
+use core::{alloc::Layout, ops::Range, ptr::NonNull};
+
+use alloc::collections::btree_map::BTreeMap;
+use page_table_generic::{PageTableRef, err::PagingError};
+use spin::Once;
+
+use crate::mem::{
+    heap_alloc, heap_dealloc,
+    mmu::{
+        AccessSetting, CacheSetting, KernelTableWalker, PTEImpl, PTESetting, RegionKind,
+        TableWalker, flush_tlb_all, get_user_table, map_table, new_table, page_size, release_table,
+        set_user_table,
+    },
+};
+
+/// 用户地址空间上界，16K 粒度下只有 47 位
+pub const USER_END: usize = 1 << 47;
+
+struct UserPage {
+    /// 物理页的内核线性地址
+    kaddr: NonNull<u8>,
+    access: AccessSetting,
+}
+
+/// 用户地址空间：经 `TTBR0` 使用的页表及其拥有的物理页
+pub struct AddressSpace {
+    table: PageTableRef<'static, PTEImpl>,
+    pages: BTreeMap<usize, UserPage>,
+}
+
+unsafe impl Send for AddressSpace {}
+
+impl AddressSpace {
+    pub fn new() -> Result<Self, PagingError> {
+        Ok(Self {
+            table: new_table()?,
+            pages: BTreeMap::new(),
+        })
+    }
+
+    /// 页表的物理地址
+    pub fn root(&self) -> usize {
+        self.table.paddr()
+    }
+
+    pub fn walker(&self) -> KernelTableWalker {
+        TableWalker::new(self.root(), RegionKind::Other.va_offset())
+    }
+
+    fn page_layout() -> Layout {
+        Layout::from_size_align(page_size(), page_size()).unwrap()
+    }
+
+    /// 为 `range` 分配清零的物理页，以 `access` 映射给用户态
+    ///
+    /// 已映射的页保留内容，权限取并集，用于相邻段共用一页的情况。
+    /// 并集同时可写和可执行时返回 [`PagingError::AlreadyMapped`]。
+    pub fn map_anon(
+        &mut self,
+        range: Range<usize>,
+        access: AccessSetting,
+    ) -> Result<(), PagingError> {
+        let layout = Self::page_layout();
+        if range.end > USER_END {
+            return Err(PagingError::NotAligned("user vaddr"));
+        }
+        let start = range.start & !(layout.size() - 1);
+        let mut remapped = false;
+
+        for va in (start..range.end).step_by(layout.size()) {
+            let (kaddr, access) = match self.pages.get_mut(&va) {
+                Some(page) if page.access.contains(access) => continue,
+                Some(page) if is_wx(page.access | access) => {
+                    return Err(PagingError::AlreadyMapped);
+                }
+                Some(page) => {
+                    page.access |= access;
+                    remapped = true;
+                    (page.kaddr, page.access)
+                }
+                None => {
+                    let kaddr = heap_alloc(layout).ok_or(PagingError::NoMemory)?;
+                    unsafe { kaddr.as_ptr().write_bytes(0, layout.size()) };
+                    self.pages.insert(va, UserPage { kaddr, access });
+                    (kaddr, access)
+                }
+            };
+
+            // 内核经线性映射写用户页；用户只读的页在特权级也必须只读，否则 AP 只能编码为 EL0 可写
+            let mut privilege_access = AccessSetting::Read;
+            if access.writable() {
+                privilege_access |= AccessSetting::Write;
+            }
+            let setting = PTESetting {
+                is_global: false,
+                privilege_access,
+                user_access: access,
+                cache_setting: CacheSetting::Normal,
+            };
+            let paddr = kaddr.as_ptr() as usize - RegionKind::Other.va_offset();
+            map_table(&mut self.table, va, paddr, layout.size(), setting)?;
+        }
+
+        if remapped && self.is_active() {
+            flush_tlb_all();
+        }
+        Ok(())
+    }
+
+    /// 不检查用户权限，把 `data` 写入已映射的用户地址
+    pub fn write(&mut self, vaddr: usize, data: &[u8]) -> Result<(), PagingError> {
+        let page_size = page_size();
+        let mut done = 0;
+        while done < data.len() {
+            let va = vaddr + done;
+            let offset = va & (page_size - 1);
+            let page = self
+                .pages
+                .get(&(va - offset))
+                .ok_or(PagingError::NotMapped)?;
+            let n = (page_size - offset).min(data.len() - done);
+            unsafe {
+                page.kaddr
+                    .as_ptr()
+                    .add(offset)
+                    .copy_from_nonoverlapping(data[done..].as_ptr(), n)
+            };
+            done += n;
+        }
+        Ok(())
+    }
+
+    fn is_active(&self) -> bool {
+        get_user_table() == self.root()
+    }
+
+    /// 把页表装入 `TTBR0`
+    pub fn activate(&self) {
+        if !self.is_active() {
+            set_user_table(self.root());
+        }
+    }
+}
+
+fn is_wx(access: AccessSetting) -> bool {
+    access.writable() && access.executable()
+}
+
+/// 不含任何映射的页表，没有用户地址空间时留在 `TTBR0`
+pub(crate) fn empty_root() -> usize {
+    static EMPTY: Once<usize> = Once::new();
+    *EMPTY.call_once(|| new_table().expect("no memory for empty user table").paddr())
+}
+
+impl Drop for AddressSpace {
+    fn drop(&mut self) {
+        if self.is_active() {
+            set_user_table(empty_root());
+        }
+        release_table(&mut self.table);
+
+        let layout = Self::page_layout();
+        for page in self.pages.values() {
+            heap_dealloc(page.kaddr, layout);
+        }
+    }
+}
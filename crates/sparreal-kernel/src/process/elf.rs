@@ -0,0 +1,187 @@
+//! 静态链接 aarch64 ELF64 可执行文件的解析与装载
+
+use core::ops::Range;
+
+use page_table_generic::{AccessSetting, err::PagingError};
+
+use super::aspace::{AddressSpace, USER_END};
+
+const EM_AARCH64: u16 = 183;
+const ET_EXEC: u16 = 2;
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+const PF_X: u32 = 1;
+const PF_W: u32 = 2;
+const PF_R: u32 = 4;
+
+const EHDR_SIZE: usize = 64;
+const PHDR_SIZE: usize = 56;
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ElfError {
+    #[error("not an ELF file")]
+    BadMagic,
+    #[error("not a little-endian ELF64 file")]
+    NotElf64,
+    #[error("machine {0} is not aarch64")]
+    BadMachine(u16),
+    #[error("type {0} is not a static executable")]
+    NotExecutable(u16),
+    #[error("dynamically linked executables are not supported")]
+    Dynamic,
+    #[error("file truncated")]
+    Truncated,
+    #[error("bad segment {0:#x?}")]
+    BadSegment(Range<usize>),
+    #[error("segment {0:#x?} is both writable and executable")]
+    WriteExec(Range<usize>),
+    #[error("entry {0:#x} is not in an executable segment")]
+    BadEntry(usize),
+    #[error("page table: {0}")]
+    Paging(#[from] PagingError),
+}
+
+/// 一个 `PT_LOAD` 段
+#[derive(Clone, PartialEq)]
+pub struct Segment {
+    pub vaddr: Range<usize>,
+    /// 文件中的内容，其余部分填零
+    pub file: Range<usize>,
+    pub access: AccessSetting,
+}
+
+#[derive(Debug)]
+pub struct Elf<'a> {
+    data: &'a [u8],
+    pub entry: usize,
+    phoff: usize,
+    phnum: usize,
+}
+
+fn read<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ElfError> {
+    data.get(offset..offset + N)
+        .ok_or(ElfError::Truncated)
+        .map(|b| b.try_into().unwrap())
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    read(data, offset).map(u16::from_le_bytes)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    read(data, offset).map(u32::from_le_bytes)
+}
+
+fn usize_at(data: &[u8], offset: usize) -> Result<usize, ElfError> {
+    read(data, offset).map(|b| u64::from_le_bytes(b) as usize)
+}
+
+impl<'a> Elf<'a> {
+    pub fn parse(data: &'a [u8]) -> Result<Self, ElfError> {
+        if !data.starts_with(b"\x7fELF") {
+            return Err(ElfError::BadMagic);
+        }
+        if data.len() < EHDR_SIZE {
+            return Err(ElfError::Truncated);
+        }
+        // ELFCLASS64, ELFDATA2LSB
+        if data[4] != 2 || data[5] != 1 {
+            return Err(ElfError::NotElf64);
+        }
+        let ty = u16_at(data, 16)?;
+        if ty != ET_EXEC {
+            return Err(ElfError::NotExecutable(ty));
+        }
+        let machine = u16_at(data, 18)?;
+        if machine != EM_AARCH64 {
+            return Err(ElfError::BadMachine(machine));
+        }
+        if (u16_at(data, 54)? as usize) != PHDR_SIZE {
+            return Err(ElfError::NotElf64);
+        }
+
+        let elf = Self {
+            data,
+            entry: usize_at(data, 24)?,
+            phoff: usize_at(data, 32)?,
+            phnum: u16_at(data, 56)? as usize,
+        };
+        if elf.phoff.saturating_add(elf.phnum * PHDR_SIZE) > data.len() {
+            return Err(ElfError::Truncated);
+        }
+        Ok(elf)
+    }
+
+    /// 所有 `PT_LOAD` 段，检查其范围是否合法
+    pub fn segments(&self) -> impl Iterator<Item = Result<Segment, ElfError>> + '_ {
+        (0..self.phnum).filter_map(|i| {
+            let ph = self.phoff + i * PHDR_SIZE;
+            match u32_at(self.data, ph) {
+                Ok(PT_LOAD) => Some(self.segment(ph)),
+                Ok(PT_INTERP) => Some(Err(ElfError::Dynamic)),
+                Ok(_) => None,
+                Err(e) => Some(Err(e)),
+            }
+        })
+    }
+
+    fn segment(&self, ph: usize) -> Result<Segment, ElfError> {
+        let flags = u32_at(self.data, ph + 4)?;
+        let offset = usize_at(self.data, ph + 8)?;
+        let vaddr = usize_at(self.data, ph + 16)?;
+        let filesz = usize_at(self.data, ph + 32)?;
+        let memsz = usize_at(self.data, ph + 40)?;
+
+        let vrange = vaddr..vaddr.saturating_add(memsz);
+        if filesz > memsz || vrange.end > USER_END || vaddr == 0 {
+            return Err(ElfError::BadSegment(vrange));
+        }
+        if offset.saturating_add(filesz) > self.data.len() {
+            return Err(ElfError::Truncated);
+        }
+
+        let mut access = AccessSetting::empty();
+        if flags & PF_R != 0 {
+            access |= AccessSetting::Read;
+        }
+        if flags & PF_W != 0 {
+            access |= AccessSetting::Write;
+        }
+        if flags & PF_X != 0 {
+            access |= AccessSetting::Execute;
+        }
+        if access.writable() && access.executable() {
+            return Err(ElfError::WriteExec(vrange));
+        }
+        Ok(Segment {
+            vaddr: vrange,
+            file: offset..offset + filesz,
+            access,
+        })
+    }
+
+    /// 入口须落在可执行段内
+    pub fn check_entry(&self) -> Result<(), ElfError> {
+        for seg in self.segments() {
+            let seg = seg?;
+            if seg.access.executable() && seg.vaddr.contains(&self.entry) {
+                return Ok(());
+            }
+        }
+        Err(ElfError::BadEntry(self.entry))
+    }
+
+    /// 把各段装入 `aspace`，返回入口地址
+    pub fn load(&self, aspace: &mut AddressSpace) -> Result<usize, ElfError> {
+        self.check_entry()?;
+        for seg in self.segments() {
+            let seg = seg?;
+            if seg.vaddr.is_empty() {
+                continue;
+            }
+            aspace.map_anon(seg.vaddr.clone(), seg.access)?;
+            aspace.write(seg.vaddr.start, &self.data[seg.file])?;
+        }
+        Ok(self.entry)
+    }
+}
@@ -0,0 +1,132 @@
+//! 用户态同步用的 futex：按（地址空间，用户地址）排队等待与唤醒
+
+use core::{
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use alloc::{collections::btree_map::BTreeMap, collections::vec_deque::VecDeque, sync::Arc};
+use spin::Mutex;
+
+use crate::{
+    irq::NoIrqGuard,
+    syscall::{Errno, copy_from_user},
+    task::{self, TaskControlBlock},
+    time,
+};
+
+/// 页表物理地址和用户地址
+type Key = (usize, usize);
+
+struct Waiter {
+    id: usize,
+    task: TaskControlBlock,
+    timed_out: Arc<AtomicBool>,
+}
+
+// 超时回调在中断中访问，持锁时须关中断
+static QUEUES: Mutex<BTreeMap<Key, VecDeque<Waiter>>> = Mutex::new(BTreeMap::new());
+
+#[derive(thiserror::Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexError {
+    #[error("value changed")]
+    WouldBlock,
+    #[error("timed out")]
+    TimedOut,
+    #[error("bad address")]
+    Fault,
+}
+
+impl From<FutexError> for Errno {
+    fn from(value: FutexError) -> Self {
+        match value {
+            FutexError::WouldBlock => Errno::EAGAIN,
+            FutexError::TimedOut => Errno::ETIMEDOUT,
+            FutexError::Fault => Errno::EFAULT,
+        }
+    }
+}
+
+fn key(addr: usize) -> Result<Key, FutexError> {
+    if addr % 4 != 0 {
+        return Err(FutexError::Fault);
+    }
+    let process = super::current().ok_or(FutexError::Fault)?;
+    Ok((process.root, addr))
+}
+
+fn remove(key: Key, id: usize) -> Option<Waiter> {
+    let mut queues = QUEUES.lock();
+    let queue = queues.get_mut(&key)?;
+    let waiter = queue
+        .iter()
+        .position(|w| w.id == id)
+        .and_then(|i| queue.remove(i));
+    if queue.is_empty() {
+        queues.remove(&key);
+    }
+    waiter
+}
+
+/// `addr` 处的值仍为 `expected` 时阻塞，直到被 [`wake`] 唤醒或超过 `timeout`
+pub fn wait(addr: usize, expected: u32, timeout: Option<Duration>) -> Result<(), FutexError> {
+    static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+    let key = key(addr)?;
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let timed_out = Arc::new(AtomicBool::new(false));
+    {
+        let _g = NoIrqGuard::new();
+        // 比较与入队在同一把锁内，不会错过其间的唤醒
+        let mut queues = QUEUES.lock();
+        let mut value = [0u8; 4];
+        copy_from_user(&mut value, addr).map_err(|_| FutexError::Fault)?;
+        if u32::from_ne_bytes(value) != expected {
+            return Err(FutexError::WouldBlock);
+        }
+        queues.entry(key).or_default().push_back(Waiter {
+            id,
+            task: task::current(),
+            timed_out: timed_out.clone(),
+        });
+    }
+
+    if let Some(timeout) = timeout {
+        time::after(timeout, move || {
+            let _g = NoIrqGuard::new();
+            if let Some(waiter) = remove(key, id) {
+                waiter.timed_out.store(true, Ordering::Release);
+                task::wake(waiter.task);
+            }
+        });
+    }
+
+    task::block_current();
+
+    if timed_out.load(Ordering::Acquire) {
+        Err(FutexError::TimedOut)
+    } else {
+        Ok(())
+    }
+}
+
+/// 唤醒最多 `n` 个在 `addr` 上等待的任务，返回唤醒的数量
+pub fn wake(addr: usize, n: usize) -> Result<usize, FutexError> {
+    let key = key(addr)?;
+    let mut woken = 0;
+    let _g = NoIrqGuard::new();
+    let mut queues = QUEUES.lock();
+    if let Some(queue) = queues.get_mut(&key) {
+        while woken < n {
+            let Some(waiter) = queue.pop_front() else {
+                break;
+            };
+            task::wake(waiter.task);
+            woken += 1;
+        }
+        if queue.is_empty() {
+            queues.remove(&key);
+        }
+    }
+    Ok(woken)
+}
@@ -0,0 +1,113 @@
+//! EL0 用户进程：独立的地址空间，由一个内核任务 `eret` 进入用户态运行
+
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+};
+use page_table_generic::{AccessSetting, err::PagingError};
+use spin::Mutex;
+
+use crate::{
+    mem::mmu::{get_user_table, set_user_table},
+    platform_if::PlatformImpl,
+    task::{self, Pid, TaskConfig, TaskError},
+};
+
+mod aspace;
+mod elf;
+pub mod futex;
+
+pub(crate) use aspace::empty_root;
+pub use aspace::{AddressSpace, USER_END};
+pub use elf::{Elf, ElfError, Segment};
+
+/// 用户栈顶，栈向下增长
+pub const USER_STACK_TOP: usize = 0x7fff_ffff_0000;
+pub const USER_STACK_SIZE: usize = 256 * 1024;
+
+#[derive(thiserror::Error, Debug)]
+pub enum ProcessError {
+    #[error("elf: {0}")]
+    Elf(#[from] ElfError),
+    #[error("page table: {0}")]
+    Paging(#[from] PagingError),
+    #[error("no memory for task")]
+    Task(TaskError),
+}
+
+impl From<TaskError> for ProcessError {
+    fn from(value: TaskError) -> Self {
+        Self::Task(value)
+    }
+}
+
+pub struct Process {
+    pub pid: Pid,
+    pub name: String,
+    /// 页表物理地址，切换任务时无需加锁即可装载
+    root: usize,
+    aspace: Mutex<AddressSpace>,
+    exit_code: Mutex<Option<i32>>,
+}
+
+impl Process {
+    /// 装载 `elf` 并创建运行它的任务，立即切换过去
+    pub fn spawn(name: impl ToString, elf: &[u8]) -> Result<Arc<Self>, ProcessError> {
+        let name = name.to_string();
+        let elf = Elf::parse(elf)?;
+        let mut aspace = AddressSpace::new()?;
+        let entry = elf.load(&mut aspace)?;
+
+        aspace.map_anon(
+            USER_STACK_TOP - USER_STACK_SIZE..USER_STACK_TOP,
+            AccessSetting::Read | AccessSetting::Write,
+        )?;
+        // 为 argc、argv、envp 和 auxv 各留一个空项
+        let sp = USER_STACK_TOP - 64;
+
+        let process = Arc::new(Self {
+            pid: Pid::new(),
+            name: name.clone(),
+            root: aspace.root(),
+            aspace: Mutex::new(aspace),
+            exit_code: Mutex::new(None),
+        });
+
+        task::spawn_in_process(
+            move || unsafe { PlatformImpl::enter_user(entry, sp) },
+            TaskConfig::new(name),
+            process.clone(),
+        )?;
+
+        Ok(process)
+    }
+
+    pub fn aspace(&self) -> spin::MutexGuard<'_, AddressSpace> {
+        self.aspace.lock()
+    }
+
+    /// 退出码，进程仍在运行时为 `None`
+    pub fn exit_code(&self) -> Option<i32> {
+        *self.exit_code.lock()
+    }
+
+    pub(crate) fn activate(&self) {
+        if get_user_table() != self.root {
+            set_user_table(self.root);
+        }
+    }
+}
+
+/// 当前任务所属的进程
+pub fn current() -> Option<Arc<Process>> {
+    task::current().process.clone()
+}
+
+/// 以 `code` 结束当前进程
+pub fn exit_current(code: i32) -> ! {
+    // 任务不会返回，不能持有 `Arc` 跨过切换
+    if let Some(process) = current() {
+        *process.exit_code.lock() = Some(code);
+    }
+    task::exit_current()
+}
@@ -0,0 +1,91 @@
+use core::time::Duration;
+
+use alloc::{string::String, vec};
+
+use crate::{
+    process::{self, futex},
+    task, time,
+};
+
+use super::{Errno, SyscallResult, UserPtr, copy_from_user};
+
+const STDOUT: usize = 1;
+const STDERR: usize = 2;
+
+/// 单次写入的上限，超出部分由用户态再次调用
+const WRITE_MAX: usize = 4096;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeSpec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+pub(super) fn sys_write(fd: usize, buf: UserPtr<u8>, len: usize) -> SyscallResult {
+    if fd != STDOUT && fd != STDERR {
+        return Err(Errno::EBADF);
+    }
+    let mut data = vec![0u8; len.min(WRITE_MAX)];
+    copy_from_user(&mut data, buf.addr())?;
+    crate::print!("{}", String::from_utf8_lossy(&data));
+    Ok(data.len())
+}
+
+pub(super) fn sys_exit(code: i32) -> SyscallResult {
+    process::exit_current(code)
+}
+
+pub(super) fn sys_yield() -> SyscallResult {
+    task::yield_now();
+    Ok(0)
+}
+
+pub(super) fn sys_getpid() -> SyscallResult {
+    let process = process::current().ok_or(Errno::ESRCH)?;
+    Ok(process.pid.into())
+}
+
+const FUTEX_WAIT: usize = 0;
+const FUTEX_WAKE: usize = 1;
+/// 只有进程内的 futex，忽略 `FUTEX_PRIVATE_FLAG`
+const FUTEX_CMD_MASK: usize = 0x7f;
+
+impl TimeSpec {
+    fn duration(&self) -> Result<Duration, Errno> {
+        if self.tv_sec < 0 || !(0..1_000_000_000).contains(&self.tv_nsec) {
+            return Err(Errno::EINVAL);
+        }
+        Ok(Duration::new(self.tv_sec as u64, self.tv_nsec as u32))
+    }
+}
+
+pub(super) fn sys_futex(
+    addr: usize,
+    op: usize,
+    val: usize,
+    timeout: UserPtr<TimeSpec>,
+) -> SyscallResult {
+    match op & FUTEX_CMD_MASK {
+        FUTEX_WAIT => {
+            let timeout = if timeout.is_null() {
+                None
+            } else {
+                Some(timeout.read()?.duration()?)
+            };
+            futex::wait(addr, val as u32, timeout)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => Ok(futex::wake(addr, val)?),
+        _ => Err(Errno::ENOSYS),
+    }
+}
+
+/// 阻塞到定时器唤醒；不会被打断，`rem` 不写入
+pub(super) fn sys_nanosleep(req: UserPtr<TimeSpec>, _rem: UserPtr<TimeSpec>) -> SyscallResult {
+    let duration = req.read()?.duration()?;
+    let current = task::current();
+    time::after(duration, move || task::wake(current));
+    task::block_current();
+    Ok(0)
+}
@@ -0,0 +1,154 @@
+//! EL0 系统调用分发，调用号与 Linux aarch64 一致
+
+mod calls;
+mod user;
+
+pub use calls::TimeSpec;
+pub use user::{UserPtr, copy_from_user, copy_to_user};
+
+use crate::platform_if::PlatformImpl;
+
+#[allow(clippy::upper_case_acronyms)]
+#[repr(isize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Errno {
+    EPERM = 1,
+    ESRCH = 3,
+    EINTR = 4,
+    EIO = 5,
+    EBADF = 9,
+    EAGAIN = 11,
+    ENOMEM = 12,
+    EFAULT = 14,
+    EINVAL = 22,
+    ENOSYS = 38,
+    ETIMEDOUT = 110,
+}
+
+pub type SyscallResult = Result<usize, Errno>;
+
+/// 从寄存器值转换的参数类型
+pub trait FromArg {
+    fn from_arg(raw: usize) -> Self;
+}
+
+impl FromArg for usize {
+    fn from_arg(raw: usize) -> Self {
+        raw
+    }
+}
+
+impl FromArg for isize {
+    fn from_arg(raw: usize) -> Self {
+        raw as _
+    }
+}
+
+impl FromArg for i32 {
+    fn from_arg(raw: usize) -> Self {
+        raw as _
+    }
+}
+
+impl<T> FromArg for UserPtr<T> {
+    fn from_arg(raw: usize) -> Self {
+        UserPtr::new(raw)
+    }
+}
+
+/// `.kernel.syscall` 段中的一项，由 [`syscall!`](crate::syscall!) 生成
+#[repr(C)]
+pub struct KernelSyscall {
+    pub num: usize,
+    pub name: &'static str,
+    pub call: fn([usize; 6]) -> SyscallResult,
+}
+
+/// 所有已注册的系统调用
+pub fn syscalls() -> &'static [KernelSyscall] {
+    let data = PlatformImpl::syscalls().as_bytes();
+    unsafe {
+        core::slice::from_raw_parts(
+            data.as_ptr() as *const KernelSyscall,
+            data.len() / size_of::<KernelSyscall>(),
+        )
+    }
+}
+
+/// 注册系统调用，生成调用号常量，参数按声明的类型从寄存器值转换
+///
+/// ```ignore
+/// syscall! {
+///     pub GETPID = 172 => sys_getpid();
+///     pub WRITE = 64 => sys_write(fd: usize, buf: UserPtr<u8>, len: usize);
+/// }
+/// ```
+///
+/// 调用号重复时只有其中一个生效。
+#[macro_export]
+macro_rules! syscall {
+    ($(
+        $(#[doc = $doc:literal])*
+        $vis:vis $name:ident = $num:literal => $handler:ident($($arg:ident: $ty:ty),*);
+    )+) => {
+        $(
+            $(#[doc = $doc])*
+            $vis const $name: usize = $num;
+
+            const _: () = {
+                // 处理函数依赖平台实现，宿主机上的测试不保留
+                #[cfg_attr(target_os = "none", used)]
+                #[allow(dead_code)]
+                #[unsafe(link_section = ".kernel.syscall")]
+                static SYSCALL: $crate::syscall::KernelSyscall = $crate::syscall::KernelSyscall {
+                    num: $num,
+                    name: stringify!($handler),
+                    call: |args| {
+                        #[allow(unused_mut, unused_variables)]
+                        let mut args = args.into_iter();
+                        $(let $arg = <$ty as $crate::syscall::FromArg>::from_arg(args.next().unwrap());)*
+                        $handler($($arg),*)
+                    },
+                };
+            };
+        )+
+    };
+}
+
+/// 内核自带的系统调用
+pub mod nr {
+    use super::{TimeSpec, UserPtr, calls::*};
+
+    crate::syscall! {
+        pub WRITE = 64 => sys_write(fd: usize, buf: UserPtr<u8>, len: usize);
+        pub EXIT = 93 => sys_exit(code: i32);
+        pub EXIT_GROUP = 94 => sys_exit(code: i32);
+        pub FUTEX = 98 => sys_futex(addr: usize, op: usize, val: usize, timeout: UserPtr<TimeSpec>);
+        pub NANOSLEEP = 101 => sys_nanosleep(req: UserPtr<TimeSpec>, rem: UserPtr<TimeSpec>);
+        pub SCHED_YIELD = 124 => sys_yield();
+        pub GETPID = 172 => sys_getpid();
+    }
+}
+
+fn find(num: usize) -> Option<&'static KernelSyscall> {
+    syscalls().iter().find(|s| s.num == num)
+}
+
+/// 调用号对应的名称，用于日志
+pub fn name(num: usize) -> Option<&'static str> {
+    find(num).map(|s| s.name)
+}
+
+/// 执行 `num` 号系统调用，`args` 为 x0..x5，返回写回 x0 的值
+///
+/// 出错时返回负的错误码
+pub fn dispatch(num: usize, args: [usize; 6]) -> usize {
+    let ret = find(num).map_or(Err(Errno::ENOSYS), |s| (s.call)(args));
+    if let Err(e) = ret {
+        log::trace!("syscall {}({num}) -> {e:?}", name(num).unwrap_or("?"));
+    }
+    match ret {
+        Ok(v) => v,
+        Err(e) => (-(e as isize)) as usize,
+    }
+}
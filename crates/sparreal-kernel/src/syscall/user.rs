@@ -0,0 +1,100 @@
+//! 经当前进程页表检查权限后访问用户内存
+
+use core::{marker::PhantomData, mem::MaybeUninit};
+
+use crate::{
+    mem::mmu::{RegionKind, page_size},
+    process::{self, USER_END},
+};
+
+use super::Errno;
+
+/// 逐页翻译 `[vaddr, vaddr + len)`，`f` 参数为内核线性地址和本段在整体中的范围
+fn for_each_user_page(
+    vaddr: usize,
+    len: usize,
+    write: bool,
+    mut f: impl FnMut(*mut u8, core::ops::Range<usize>),
+) -> Result<(), Errno> {
+    let end = vaddr.checked_add(len).ok_or(Errno::EFAULT)?;
+    if end > USER_END {
+        return Err(Errno::EFAULT);
+    }
+    let process = process::current().ok_or(Errno::EFAULT)?;
+    // 持锁期间映射不会变化
+    let aspace = process.aspace();
+    let walker = aspace.walker();
+    let page_size = page_size();
+
+    let mut va = vaddr;
+    while va < end {
+        let t = walker.translate(va).ok_or(Errno::EFAULT)?;
+        let access = t.pte.setting.user_access;
+        if !access.readable() || (write && !access.writable()) {
+            return Err(Errno::EFAULT);
+        }
+        let n = (page_size - (va & (page_size - 1))).min(end - va);
+        let kaddr = (t.paddr + RegionKind::Other.va_offset()) as *mut u8;
+        f(kaddr, va - vaddr..va - vaddr + n);
+        va += n;
+    }
+    Ok(())
+}
+
+/// 从用户地址 `src` 读满 `dst`
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), Errno> {
+    for_each_user_page(src, dst.len(), false, |kaddr, range| unsafe {
+        dst[range.clone()]
+            .as_mut_ptr()
+            .copy_from_nonoverlapping(kaddr, range.len())
+    })
+}
+
+/// 把 `src` 写到用户地址 `dst`
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), Errno> {
+    for_each_user_page(dst, src.len(), true, |kaddr, range| unsafe {
+        kaddr.copy_from_nonoverlapping(src[range.clone()].as_ptr(), range.len())
+    })
+}
+
+/// 系统调用参数中的用户指针，每次访问都重新检查
+#[repr(transparent)]
+pub struct UserPtr<T>(usize, PhantomData<*mut T>);
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> UserPtr<T> {
+    pub fn new(addr: usize) -> Self {
+        Self(addr, PhantomData)
+    }
+
+    pub fn addr(&self) -> usize {
+        self.0
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub fn read(&self) -> Result<T, Errno> {
+        let mut v = MaybeUninit::<T>::uninit();
+        let buf =
+            unsafe { core::slice::from_raw_parts_mut(v.as_mut_ptr() as *mut u8, size_of::<T>()) };
+        copy_from_user(buf, self.0)?;
+        Ok(unsafe { v.assume_init() })
+    }
+
+    pub fn write(&self, v: T) -> Result<(), Errno> {
+        let buf =
+            unsafe { core::slice::from_raw_parts(&v as *const T as *const u8, size_of::<T>()) };
+        copy_to_user(self.0, buf)
+    }
+}
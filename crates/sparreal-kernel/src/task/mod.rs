@@ -1,12 +1,21 @@
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use alloc::string::{String, ToString};
-use tcb::set_current;
+#[cfg(feature = "mmu")]
+use alloc::sync::Arc;
+use tcb::{TaskState, set_current};
+
+#[cfg(feature = "mmu")]
+use crate::process::Process;
 
 mod schedule;
 mod tcb;
 
-pub use schedule::suspend;
+pub use schedule::{suspend, yield_now};
 pub use tcb::{Pid, TaskControlBlock, TaskControlBlockData, current};
 
+const DEFAULT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
 #[derive(Debug, Clone)]
 pub enum TaskError {
     NoMemory,
@@ -24,7 +33,7 @@ impl TaskConfig {
         Self {
             name: name.to_string(),
             priority: 0,
-            stack_size: 2 * 1024 * 1024,
+            stack_size: DEFAULT_STACK_SIZE,
         }
     }
 }
@@ -40,9 +49,64 @@ where
     Ok(())
 }
 
+/// 创建属于 `process` 的任务，切换到它时加载该进程的页表
+#[cfg(feature = "mmu")]
+pub(crate) fn spawn_in_process<F>(
+    f: F,
+    config: TaskConfig,
+    process: Arc<Process>,
+) -> Result<Pid, TaskError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    let mut task = TaskControlBlock::new(f, config)?;
+    task.process = Some(process);
+    let pid = task.pid;
+
+    tcb::current().switch_to(&task);
+
+    Ok(pid)
+}
+
+/// 阻塞当前任务，直到持有它的等待队列调用 [`wake`]
+///
+/// 唤醒可能先于阻塞到达，例如另一个 CPU 上的 futex 唤醒，此时直接返回
+pub(crate) fn block_current() {
+    if schedule::try_block(tcb::current()) {
+        schedule::schedule();
+    }
+}
+
+/// 唤醒被阻塞的任务，可在中断中调用
+pub(crate) fn wake(task: TaskControlBlock) {
+    schedule::wake(task);
+}
+
+/// 结束当前任务，由回收流程释放
+pub fn exit_current() -> ! {
+    let mut task = tcb::current();
+    task.state = TaskState::Stopped;
+    schedule::schedule();
+    unreachable!("task exited!");
+}
+
+static INITED: AtomicBool = AtomicBool::new(false);
+
 pub fn init() {
+    crate::mem::register_shrinker("finished tasks", schedule::reap_finished);
+
     let task = TaskControlBlock::new_main();
     set_current(&task);
+    INITED.store(true, Ordering::Release);
+}
+
+/// 调度器初始化前没有当前任务
+pub fn current_pid() -> Option<Pid> {
+    try_current().map(|t| t.pid)
+}
+
+pub(crate) fn try_current() -> Option<TaskControlBlock> {
+    INITED.load(Ordering::Acquire).then(tcb::current)
 }
 
 pub fn wake_up_in_irq(_pid: Pid) {}
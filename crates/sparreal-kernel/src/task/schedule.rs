@@ -2,7 +2,7 @@ use alloc::collections::vec_deque::VecDeque;
 use log::debug;
 use spin::Mutex;
 
-use crate::platform_if::PlatformImpl;
+use crate::{globals::cpu_global_mut, irq::NoIrqGuard, platform_if::PlatformImpl};
 
 use super::tcb::{TaskControlBlock, TaskState, current};
 
@@ -10,28 +10,66 @@ static IDLE: Mutex<VecDeque<TaskControlBlock>> = Mutex::new(VecDeque::new());
 static FINISHED: Mutex<VecDeque<TaskControlBlock>> = Mutex::new(VecDeque::new());
 
 pub fn schedule() {
-    let idle = idle_pop();
-    if let Some(mut idle) = idle {
-        let mut cu = current();
-        if matches!(cu.state, TaskState::Running) {
-            cu.state = TaskState::Suspend;
+    let mut cu = current();
+    if matches!(cu.state, TaskState::Running) {
+        cu.state = TaskState::Suspend;
+    }
+    loop {
+        if let Some(mut idle) = idle_pop() {
+            idle.state = TaskState::Running;
+            // 切出前在本 CPU 的中断中被唤醒
+            if idle.pid == cu.pid {
+                return;
+            }
+            cu.switch_to(&idle);
+            return;
         }
-        idle.state = TaskState::Running;
-
-        cu.switch_to(&idle);
-    } else {
         debug!("No task idle");
-        loop {
-            PlatformImpl::wait_for_interrupt();
-        }
+        wait_for_irq();
     }
 }
 
+/// 等待中断，关中断时也让中断处理得以运行，以便其中唤醒任务
+fn wait_for_irq() {
+    PlatformImpl::wait_for_interrupt();
+    if !PlatformImpl::irq_all_is_enabled() {
+        PlatformImpl::irq_all_enable();
+        PlatformImpl::irq_all_disable();
+    }
+}
+
+// 中断中也会唤醒任务，访问 `IDLE` 时关中断
+
 pub fn idle_push(tcb: TaskControlBlock) {
+    let _g = NoIrqGuard::new();
     IDLE.lock().push_back(tcb);
 }
 
+/// 标记任务阻塞，已有未处理的唤醒时清除它并返回 `false`
+pub(super) fn try_block(mut tcb: TaskControlBlock) -> bool {
+    let _g = NoIrqGuard::new();
+    let _idle = IDLE.lock();
+    if core::mem::take(&mut tcb.wake_pending) {
+        return false;
+    }
+    tcb.state = TaskState::Blocked;
+    true
+}
+
+/// 唤醒任务，尚未阻塞时只记录，由 [`try_block`] 处理
+pub(super) fn wake(mut tcb: TaskControlBlock) {
+    let _g = NoIrqGuard::new();
+    let mut idle = IDLE.lock();
+    if matches!(tcb.state, TaskState::Blocked) {
+        tcb.state = TaskState::Idle;
+        idle.push_back(tcb);
+    } else {
+        tcb.wake_pending = true;
+    }
+}
+
 pub fn idle_pop() -> Option<TaskControlBlock> {
+    let _g = NoIrqGuard::new();
     let mut g = IDLE.lock();
     while let Some(one) = g.pop_front() {
         if matches!(one.state, TaskState::Stopped) {
@@ -47,6 +85,57 @@ pub fn finished_push(tcb: TaskControlBlock) {
     FINISHED.lock().push_back(tcb);
 }
 
+/// 记录正在切出的已结束任务，切换前它的栈仍在使用
+pub(super) fn set_exiting(tcb: TaskControlBlock) {
+    unsafe { cpu_global_mut() }.exiting = Some(tcb);
+}
+
+/// 切换到新任务后调用，已离开前一个任务的栈，此时它才可以回收
+pub(super) fn finish_switch() {
+    if let Some(tcb) = unsafe { cpu_global_mut() }.exiting.take() {
+        finished_push(tcb);
+        reap(false);
+    }
+}
+
+/// 释放已结束任务的 TCB 和它持有的进程，返回释放的字节数
+///
+/// `finished_push` 持锁时可能正处于内存不足路径，此时跳过
+fn reap(to_heap: bool) -> usize {
+    let Some(mut finished) = FINISHED.try_lock() else {
+        return 0;
+    };
+    let mut freed = 0;
+    while let Some(tcb) = finished.pop_front() {
+        freed += tcb.size();
+        unsafe {
+            if to_heap {
+                tcb.reclaim();
+            } else {
+                tcb.drop();
+            }
+        }
+    }
+    freed
+}
+
+/// 内存不足时的后备，切换时未能回收的任务在这里释放，不留在 magazine 中
+pub(crate) fn reap_finished() -> usize {
+    reap(true)
+}
+
+/// 让出 CPU，没有其他可运行的任务时直接返回
+pub fn yield_now() {
+    let empty = {
+        let _g = NoIrqGuard::new();
+        IDLE.lock().is_empty()
+    };
+    if empty {
+        return;
+    }
+    suspend();
+}
+
 pub fn suspend() {
     let mut current = current();
     current.state = TaskState::Suspend;
@@ -6,13 +6,24 @@ use core::{
     sync::atomic::{AtomicUsize, Ordering},
 };
 
+#[cfg(feature = "mmu")]
+use alloc::sync::Arc;
 use alloc::{boxed::Box, string::String};
 use log::trace;
 
-use crate::{platform, platform_if::PlatformImpl, task::schedule::*};
+#[cfg(feature = "mmu")]
+use crate::process::Process;
+use crate::{mem::slab::KmemCache, platform, platform_if::PlatformImpl, task::schedule::*};
 
 use super::{TaskConfig, TaskError};
 
+/// 只缓存控制块，栈单独从堆分配
+static TCB_CACHE: KmemCache = KmemCache::new(
+    "task_control_block",
+    size_of::<TaskControlBlockData>(),
+    align_of::<TaskControlBlockData>(),
+);
+
 #[repr(transparent)]
 #[derive(Clone, Copy)]
 pub struct TaskControlBlock(*mut u8);
@@ -42,6 +53,12 @@ impl Default for Pid {
     }
 }
 
+impl From<Pid> for usize {
+    fn from(value: Pid) -> Self {
+        value.0
+    }
+}
+
 impl Debug for Pid {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         writeln!(f, "{:?}", self.0)
@@ -55,19 +72,20 @@ impl TaskControlBlock {
     {
         let entry_box = Box::new(entry);
 
-        let buffer = NonNull::new(unsafe {
-            alloc::alloc::alloc_zeroed(
-                Layout::from_size_align(Self::tcb_size(config.stack_size), platform::page_size())
-                    .unwrap(),
-            )
-        })
-        .ok_or(TaskError::NoMemory)?;
+        let buffer = TCB_CACHE.alloc_zeroed().ok_or(TaskError::NoMemory)?;
+        let Some(stack) =
+            NonNull::new(unsafe { alloc::alloc::alloc(Self::stack_layout(config.stack_size)) })
+        else {
+            unsafe { TCB_CACHE.free(buffer) };
+            return Err(TaskError::NoMemory);
+        };
 
         let pid = Pid::new();
 
         unsafe {
             let task_data = &mut *(buffer.as_ptr() as *mut TaskControlBlockData);
             task_data.pid = pid;
+            task_data.stack = stack.as_ptr() as usize;
             task_data.stack_size = config.stack_size;
             task_data.priority = config.priority;
             task_data.name = config.name;
@@ -91,13 +109,10 @@ impl TaskControlBlock {
     pub(super) fn new_main() -> Self {
         let entry_box = Box::new(|| {});
 
-        let buffer = NonNull::new(unsafe {
-            alloc::alloc::alloc_zeroed(
-                Layout::from_size_align(Self::tcb_size(0), platform::page_size()).unwrap(),
-            )
-        })
-        .ok_or(TaskError::NoMemory)
-        .expect("main task no memory");
+        let buffer = TCB_CACHE
+            .alloc_zeroed()
+            .ok_or(TaskError::NoMemory)
+            .expect("main task no memory");
 
         let pid = Pid::new();
 
@@ -113,27 +128,46 @@ impl TaskControlBlock {
         Self(buffer.as_ptr())
     }
 
-    fn tcb_size(stack_size: usize) -> usize {
-        size_of::<TaskControlBlockData>() + stack_size
+    /// 控制块和栈的总大小
+    pub(super) fn size(&self) -> usize {
+        size_of::<TaskControlBlockData>() + self.stack_size
     }
 
-    fn stack_bottom(&self) -> *mut u8 {
-        unsafe { self.0.add(size_of::<TaskControlBlockData>()) }
+    fn stack_layout(stack_size: usize) -> Layout {
+        Layout::from_size_align(stack_size, platform::page_size()).unwrap()
     }
 
     fn stack_top(&self) -> *mut u8 {
-        unsafe { self.stack_bottom().add(self.stack_size) }
+        (self.stack + self.stack_size) as _
     }
 
     pub(super) unsafe fn drop(self) {
-        let size = Self::tcb_size(self.stack_size);
+        unsafe { self.free(false) };
+    }
+
+    /// 内存回收时使用，直接归还给堆，不留在 `TCB_CACHE` 的 magazine 中
+    pub(super) unsafe fn reclaim(self) {
+        unsafe { self.free(true) };
+    }
+
+    unsafe fn free(mut self, to_heap: bool) {
+        #[cfg(feature = "mmu")]
+        {
+            self.process = None;
+        }
 
         unsafe {
-            alloc::alloc::dealloc(
-                self.0,
-                Layout::from_size_align_unchecked(size, platform::page_size()),
-            )
-        };
+            // 主任务使用启动栈
+            if self.stack_size > 0 {
+                alloc::alloc::dealloc(self.stack as _, Self::stack_layout(self.stack_size));
+            }
+            let ptr = NonNull::new_unchecked(self.0);
+            if to_heap {
+                TCB_CACHE.free_to_heap(ptr);
+            } else {
+                TCB_CACHE.free(ptr);
+            }
+        }
     }
 
     fn addr(&self) -> *mut u8 {
@@ -143,14 +177,29 @@ impl TaskControlBlock {
     pub(super) fn switch_to(&self, next: &TaskControlBlock) {
         trace!("switch {} -> {}", self.name, next.name);
         set_current(next);
+        #[cfg(feature = "mmu")]
+        if let Some(process) = &next.process {
+            process.activate();
+        }
         match self.state {
-            TaskState::Stopped => finished_push(*self),
+            // 仍在它的栈上运行，切换完成后才能回收
+            TaskState::Stopped => set_exiting(*self),
+            // 由等待队列持有
+            TaskState::Blocked => {}
             _ => idle_push(*self),
         }
 
+        // 中断开关不属于上下文，恢复运行时还原切换前的状态
+        let irq_enabled = PlatformImpl::irq_all_is_enabled();
         unsafe {
             PlatformImpl::cpu_context_switch(self.addr(), next.addr());
         }
+        finish_switch();
+        if irq_enabled {
+            PlatformImpl::irq_all_enable();
+        } else {
+            PlatformImpl::irq_all_disable();
+        }
     }
 }
 
@@ -172,10 +221,23 @@ pub struct TaskControlBlockData {
     pub pid: Pid,
     pub name: String,
     pub priority: usize,
+    /// 栈的低地址，主任务没有单独的栈
+    stack: usize,
     pub stack_size: usize,
     pub entry: Option<Box<dyn FnOnce()>>,
     pub state: TaskState,
+    /// 阻塞前已被唤醒，由 `IDLE` 的锁保护
+    pub(super) wake_pending: bool,
     pub sp: usize,
+    /// 用户态栈指针和线程指针，切换任务时由平台保存
+    pub user_sp: usize,
+    pub user_tls: usize,
+    /// 所属用户进程，内核任务为 `None`
+    #[cfg(feature = "mmu")]
+    pub process: Option<Arc<Process>>,
+    /// 见 [`crate::mem::with_alloc_tag`]
+    #[cfg(feature = "heap-trace")]
+    pub(crate) alloc_tag: Option<crate::mem::AllocTag>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -183,10 +245,12 @@ pub enum TaskState {
     Idle,
     Running,
     Suspend,
+    Blocked,
     Stopped,
 }
 
 extern "C" fn task_entry() -> ! {
+    finish_switch();
     let mut task = current();
 
     if let Some(entry) = task.entry.take() {
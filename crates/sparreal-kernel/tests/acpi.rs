@@ -0,0 +1,97 @@
+//! 生成的设备树交给 fdt-parser 解析，核对 UEFI 内存表的合并
+
+mod common;
+
+use sparreal_kernel::platform::acpi::Acpi;
+
+#[test]
+fn test_dtb_round_trip() {
+    let mut b = common::root(2);
+    b.begin_node("memory@40000000");
+    b.prop_str("device_type", "memory");
+    b.prop_u64s("reg", &[0x4000_0000, 0x1000_0000]);
+    b.end_node();
+    b.begin_node("gic@8000000");
+    b.prop_strs("compatible", &["arm,cortex-a15-gic", "arm,gic-400"]);
+    b.prop_empty("interrupt-controller");
+    b.prop_u32("phandle", 1);
+    b.end_node();
+    b.end_node();
+    let dtb = b.finish();
+
+    let fdt = common::parse(&dtb);
+    assert_eq!(fdt.total_size(), dtb.len());
+
+    let regions: Vec<_> = fdt.memory().flat_map(|m| m.regions()).collect();
+    assert_eq!(regions.len(), 1);
+    assert_eq!(regions[0].address as usize, 0x4000_0000);
+    assert_eq!(regions[0].size, 0x1000_0000);
+
+    let gic = fdt.find_compatible(&["arm,gic-400"]).next().unwrap();
+    assert_eq!(
+        gic.compatibles().collect::<Vec<_>>(),
+        ["arm,cortex-a15-gic", "arm,gic-400"]
+    );
+    assert!(gic.find_property("interrupt-controller").is_some());
+    assert_eq!(gic.phandle(), Some(1.into()));
+}
+
+/// 类型，起始地址，页数；描述符按 48 字节填充
+fn memory_map(descs: &[(u32, u64, u64)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for &(ty, start, pages) in descs {
+        let mut d = [0u8; 48];
+        d[0..4].copy_from_slice(&ty.to_le_bytes());
+        d[8..16].copy_from_slice(&start.to_le_bytes());
+        d[24..32].copy_from_slice(&pages.to_le_bytes());
+        out.extend_from_slice(&d);
+    }
+    out
+}
+
+#[test]
+fn test_memory_map_merge() {
+    let map = memory_map(&[
+        (7, 0x4100_0000, 0x100),
+        (2, 0x4000_0000, 0x1000),
+        (0, 0x4110_0000, 0x10),
+        (4, 0x4111_0000, 0x10),
+        (9, 0x4200_0000, 0x10),
+        (10, 0x4201_0000, 0x10),
+    ]);
+    let acpi = Acpi::new(0x4200_0000.into(), &map, 48);
+
+    let memorys: Vec<_> = acpi
+        .memorys()
+        .into_iter()
+        .map(|r| (r.start.raw(), r.end.raw()))
+        .collect();
+    // 保留内存把可用内存分成两段
+    assert_eq!(
+        memorys,
+        [(0x4000_0000, 0x4110_0000), (0x4111_0000, 0x4112_0000)]
+    );
+
+    let firmware: Vec<_> = acpi
+        .firmware_regions()
+        .iter()
+        .map(|r| (r.start.raw(), r.end.raw()))
+        .collect();
+    assert_eq!(firmware, [(0x4200_0000, 0x4202_0000)]);
+}
+
+#[test]
+fn test_rsdp_outside_acpi_memory() {
+    let map = memory_map(&[(7, 0x4000_0000, 0x100), (9, 0x4800_0000, 0x1)]);
+    let acpi = Acpi::new(0x4f00_0018.into(), &map, 48);
+
+    let firmware: Vec<_> = acpi
+        .firmware_regions()
+        .iter()
+        .map(|r| (r.start.raw(), r.end.raw()))
+        .collect();
+    assert_eq!(
+        firmware,
+        [(0x4800_0000, 0x4800_1000), (0x4f00_0000, 0x4f00_1000)]
+    );
+}
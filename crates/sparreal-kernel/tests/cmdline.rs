@@ -0,0 +1,69 @@
+//! 命令行拆分和参数取值
+
+use log::LevelFilter;
+use sparreal_kernel::{
+    cmdline::{KernelParam, ParamValue, name_eq, parse},
+    kernel_param,
+};
+
+kernel_param! {
+    /// 测试用
+    #[param("test-flag")]
+    static FLAG: bool = false;
+
+    #[param("test_count")]
+    static COUNT: usize = 4;
+}
+
+#[test]
+fn test_parse() {
+    let args: Vec<_> =
+        parse(r#" console=ttyAMA0  quiet init="/bin/sh -l" "a b" -- app=1 "#).collect();
+    assert_eq!(
+        args,
+        [
+            ("console", Some("ttyAMA0")),
+            ("quiet", None),
+            ("init", Some("/bin/sh -l")),
+            ("a b", None),
+            ("--", None),
+            ("app", Some("1")),
+        ]
+    );
+    assert_eq!(parse("   ").count(), 0);
+}
+
+#[test]
+fn test_values() {
+    assert!(name_eq("max-cpus", "max_cpus"));
+    assert!(!name_eq("maxcpus", "maxcpu"));
+
+    assert_eq!(bool::parse(""), Some(true));
+    assert_eq!(bool::parse("off"), Some(false));
+    assert_eq!(bool::parse("2"), None);
+    assert_eq!(usize::parse("0x10"), Some(16));
+    assert_eq!(u8::parse("256"), None);
+    assert_eq!(LevelFilter::parse("warn"), Some(LevelFilter::Warn));
+    assert_eq!(LevelFilter::parse("INFO"), Some(LevelFilter::Info));
+}
+
+#[test]
+fn test_param() {
+    assert!(!FLAG.get());
+    assert_eq!(FLAG.name(), "test-flag");
+    assert!(FLAG.set(""));
+    assert!(FLAG.get());
+
+    assert!(!COUNT.set("x"));
+    assert_eq!(COUNT.get(), 4);
+    assert!(COUNT.set("2"));
+    assert_eq!(COUNT.get(), 2);
+
+    let desc = KernelParam {
+        name: "test-flag",
+        help: "",
+        set: |s| FLAG.set(s),
+    };
+    assert!((desc.set)("0"));
+    assert!(!FLAG.get());
+}
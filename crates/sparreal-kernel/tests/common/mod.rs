@@ -0,0 +1,16 @@
+//! 测试共用的设备树构造
+
+use sparreal_kernel::platform::acpi::DtbBuilder;
+
+/// 开始根节点，地址和大小都占 `cells` 个 cell
+pub fn root(cells: u32) -> DtbBuilder {
+    let mut b = DtbBuilder::new();
+    b.begin_node("");
+    b.prop_u32("#address-cells", cells);
+    b.prop_u32("#size-cells", cells);
+    b
+}
+
+pub fn parse(dtb: &[u8]) -> fdt_parser::Fdt<'_> {
+    fdt_parser::Fdt::from_bytes(dtb).unwrap()
+}
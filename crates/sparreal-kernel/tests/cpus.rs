@@ -0,0 +1,150 @@
+//! 从设备树枚举 CPU：启动方式、`cpu-map` 拓扑和格式错误的节点
+
+mod common;
+
+use sparreal_kernel::{
+    mem::PhysAddr,
+    platform::{CpuTopology, EnableMethod, Topology, acpi::DtbBuilder, fdt::parse_cpus},
+};
+
+fn cpu(b: &mut DtbBuilder, mpidr: u64, phandle: u32) {
+    b.begin_node(&format!("cpu@{mpidr:x}"));
+    b.prop_str("device_type", "cpu");
+    b.prop_str("compatible", "arm,cortex-a55");
+    b.prop_u64s("reg", &[mpidr]);
+    b.prop_u32("phandle", phandle);
+}
+
+fn leaf(b: &mut DtbBuilder, name: &str, phandle: u32) {
+    b.begin_node(name);
+    b.prop_u32("cpu", phandle);
+    b.end_node();
+}
+
+fn build(with_map: bool) -> Vec<u8> {
+    let mut b = common::root(2);
+    b.begin_node("cpus");
+    b.prop_u32("#address-cells", 2);
+    b.prop_u32("#size-cells", 0);
+
+    cpu(&mut b, 0x0, 10);
+    b.prop_u32("capacity-dmips-mhz", 578);
+    b.prop_u32("numa-node-id", 0);
+    b.end_node();
+
+    cpu(&mut b, 0x1, 11);
+    b.prop_str("enable-method", "psci");
+    b.end_node();
+
+    cpu(&mut b, 0x100, 12);
+    b.prop_str("enable-method", "spin-table");
+    b.prop_u64s("cpu-release-addr", &[0x8000_fff8]);
+    b.prop_u32("numa-node-id", 1);
+    b.end_node();
+
+    cpu(&mut b, 0x101, 13);
+    b.prop_str("enable-method", "spin-table");
+    b.end_node();
+
+    cpu(&mut b, 0x200, 14);
+    b.prop_str("status", "disabled");
+    b.end_node();
+
+    b.begin_node("cpu@300");
+    b.prop_str("device_type", "cpu");
+    b.end_node();
+
+    // reg 少一个 cell
+    b.begin_node("cpu@400");
+    b.prop_str("device_type", "cpu");
+    b.prop_cells("reg", &[0x400]);
+    b.end_node();
+
+    if with_map {
+        b.begin_node("cpu-map");
+        b.begin_node("cluster0");
+        leaf(&mut b, "core0", 10);
+        leaf(&mut b, "core1", 11);
+        b.end_node();
+        b.begin_node("cluster1");
+        b.begin_node("core0");
+        leaf(&mut b, "thread0", 12);
+        leaf(&mut b, "thread1", 13);
+        b.end_node();
+        b.end_node();
+        b.end_node();
+    }
+
+    b.end_node();
+    b.end_node();
+
+    b.finish()
+}
+
+fn topo(package: usize, cluster: usize, core: usize, thread: usize) -> CpuTopology {
+    CpuTopology {
+        package,
+        cluster,
+        core,
+        thread,
+    }
+}
+
+#[test]
+fn test_cpu_map() {
+    let dtb = build(true);
+    let cpus = parse_cpus(&common::parse(&dtb));
+
+    let ids: Vec<usize> = cpus.iter().map(|c| c.cpu_id.into()).collect();
+    assert_eq!(ids, [0x0, 0x1, 0x100, 0x101]);
+
+    assert_eq!(cpus[0].enable_method, EnableMethod::None);
+    assert_eq!(cpus[0].capacity, Some(578));
+    assert_eq!(cpus[0].numa_node, Some(0));
+    assert_eq!(cpus[1].enable_method, EnableMethod::Psci);
+    assert_eq!(
+        cpus[2].enable_method,
+        EnableMethod::SpinTable {
+            release_addr: PhysAddr::from(0x8000_fff8)
+        }
+    );
+    assert_eq!(cpus[3].enable_method, EnableMethod::Unknown);
+
+    let topologies: Vec<_> = cpus.iter().map(|c| c.topology).collect();
+    assert_eq!(
+        topologies,
+        [
+            topo(0, 0, 0, 0),
+            topo(0, 0, 1, 0),
+            topo(0, 1, 0, 0),
+            topo(0, 1, 0, 1)
+        ]
+    );
+
+    let t = Topology::new(cpus);
+    assert_eq!(t.packages(), 1);
+    assert_eq!(t.clusters(), 2);
+    assert_eq!(t.numa_nodes(), 2);
+    let id = t.cpus[2].cpu_id;
+    assert_eq!(t.cluster_siblings(id).count(), 2);
+    assert_eq!(t.thread_siblings(id).count(), 2);
+    assert_eq!(t.thread_siblings(t.cpus[0].cpu_id).count(), 1);
+}
+
+#[test]
+fn test_mpidr_fallback() {
+    let dtb = build(false);
+    let cpus = parse_cpus(&common::parse(&dtb));
+
+    let topologies: Vec<_> = cpus.iter().map(|c| c.topology).collect();
+    assert_eq!(
+        topologies,
+        [
+            topo(0, 0, 0, 0),
+            topo(0, 0, 1, 0),
+            topo(0, 1, 0, 0),
+            topo(0, 1, 1, 0)
+        ]
+    );
+    assert_eq!(Topology::new(cpus).clusters(), 2);
+}
@@ -0,0 +1,91 @@
+//! 按设备树中各级总线的 `dma-ranges` 计算设备的总线地址
+
+mod common;
+
+use sparreal_kernel::{
+    mem::{PhysAddr, dma::DmaRanges},
+    platform::fdt::parse_dma_ranges,
+};
+
+/// `root` 为根节点的 `dma-ranges`，`soc` 为 `/soc` 的
+fn build(cells: u32, root: Option<&[u32]>, soc: Option<&[u32]>) -> Vec<u8> {
+    let mut b = common::root(cells);
+    if let Some(r) = root {
+        b.prop_cells("dma-ranges", r);
+    }
+
+    b.begin_node("dev@1000");
+    b.end_node();
+
+    b.begin_node("soc");
+    b.prop_u32("#address-cells", cells / 2);
+    b.prop_u32("#size-cells", cells / 2);
+    if let Some(r) = soc {
+        b.prop_cells("dma-ranges", r);
+    }
+    b.begin_node("dev@2000");
+    b.end_node();
+    b.end_node();
+
+    b.end_node();
+    b.finish()
+}
+
+fn ranges(dtb: &[u8], path: &str) -> DmaRanges {
+    let fdt = common::parse(dtb);
+    let node = fdt.find_nodes(path).next().unwrap();
+    parse_dma_ranges(&fdt, &node)
+}
+
+fn pa(addr: usize) -> PhysAddr {
+    PhysAddr::from(addr)
+}
+
+/// 总线 0 起的 1G 对应 CPU 的 `0x4000_0000`
+const ROOT: &[u32] = &[0, 0, 0, 0x4000_0000, 0, 0x4000_0000];
+
+#[test]
+fn test_identity() {
+    let dtb = build(2, None, None);
+    assert!(ranges(&dtb, "/dev@1000").is_identity());
+    assert!(ranges(&dtb, "/soc/dev@2000").is_identity());
+}
+
+#[test]
+fn test_root_level_device() {
+    let dtb = build(2, Some(ROOT), None);
+    let r = ranges(&dtb, "/dev@1000");
+    assert_eq!(r.to_bus(pa(0x4000_1000), 0x1000), Some(0x1000));
+    assert_eq!(r.to_bus(pa(0x1000), 0x1000), None);
+    assert_eq!(r.to_cpu(0x1000), Some(pa(0x4000_1000)));
+}
+
+#[test]
+fn test_nested() {
+    // soc 的总线 0 起的 512M 对应根节点的 `0x1000_0000`
+    let soc = [0, 0, 0x1000_0000, 0x2000_0000];
+    let dtb = build(2, Some(ROOT), Some(&soc));
+    let r = ranges(&dtb, "/soc/dev@2000");
+    assert_eq!(r.to_bus(pa(0x5000_0000), 0x1000), Some(0));
+    assert_eq!(r.to_bus(pa(0x6fff_f000), 0x1000), Some(0x1fff_f000));
+    assert_eq!(r.to_bus(pa(0x7000_0000), 0x1000), None);
+    assert_eq!(r.to_bus(pa(0x4000_0000), 0x1000), None);
+}
+
+#[test]
+fn test_disjoint_windows() {
+    // 根节点的窗口只接受 1G 以下的总线地址
+    let soc = [0, 0, 0x8000_0000, 0x1000_0000];
+    let dtb = build(2, Some(ROOT), Some(&soc));
+    let r = ranges(&dtb, "/soc/dev@2000");
+    assert!(r.is_unreachable());
+    assert_eq!(r.to_bus(pa(0x4000_0000), 0x1000), None);
+}
+
+#[test]
+fn test_zero_cells() {
+    let dtb = build(0, None, Some(&[1]));
+    let r = ranges(&dtb, "/soc/dev@2000");
+    assert!(r.is_unreachable());
+    assert_eq!(r.to_bus(pa(0x1000), 0x1000), None);
+}
@@ -0,0 +1,116 @@
+//! 用手工构造的 ELF 头核对解析结果
+
+use sparreal_kernel::{
+    mem::mmu::AccessSetting,
+    process::{Elf, ElfError},
+};
+
+const PT_LOAD: u32 = 1;
+const PT_INTERP: u32 = 3;
+
+/// 64 字节文件头后紧跟程序头，再附 `payload`
+fn build(phdrs: &[(u32, u32, u64, u64, u64)], payload: &[u8]) -> Vec<u8> {
+    let mut out = vec![0u8; 64];
+    out[..4].copy_from_slice(b"\x7fELF");
+    out[4] = 2;
+    out[5] = 1;
+    out[6] = 1;
+    out[16..18].copy_from_slice(&2u16.to_le_bytes());
+    out[18..20].copy_from_slice(&183u16.to_le_bytes());
+    out[24..32].copy_from_slice(&0x40_1000u64.to_le_bytes());
+    out[32..40].copy_from_slice(&64u64.to_le_bytes());
+    out[54..56].copy_from_slice(&56u16.to_le_bytes());
+    out[56..58].copy_from_slice(&(phdrs.len() as u16).to_le_bytes());
+
+    let data_off = 64 + 56 * phdrs.len() as u64;
+    for &(ty, flags, vaddr, filesz, memsz) in phdrs {
+        let mut ph = [0u8; 56];
+        ph[0..4].copy_from_slice(&ty.to_le_bytes());
+        ph[4..8].copy_from_slice(&flags.to_le_bytes());
+        ph[8..16].copy_from_slice(&data_off.to_le_bytes());
+        ph[16..24].copy_from_slice(&vaddr.to_le_bytes());
+        ph[32..40].copy_from_slice(&filesz.to_le_bytes());
+        ph[40..48].copy_from_slice(&memsz.to_le_bytes());
+        out.extend_from_slice(&ph);
+    }
+    out.extend_from_slice(payload);
+    out
+}
+
+#[test]
+fn test_segments() {
+    let data = build(
+        &[
+            (PT_LOAD, 5, 0x40_0000, 16, 16),
+            (4, 4, 0, 0, 0),
+            (PT_LOAD, 6, 0x41_0000, 8, 0x2000),
+        ],
+        &[0xaa; 16],
+    );
+    let elf = Elf::parse(&data).unwrap();
+    assert_eq!(elf.entry, 0x40_1000);
+
+    let segs = elf.segments().collect::<Result<Vec<_>, _>>().unwrap();
+    assert_eq!(segs.len(), 2);
+    assert_eq!(segs[0].vaddr, 0x40_0000..0x40_0010);
+    assert_eq!(segs[0].file, 232..248);
+    assert!(segs[0].access == AccessSetting::Read | AccessSetting::Execute);
+    assert_eq!(segs[1].vaddr, 0x41_0000..0x41_2000);
+    assert!(segs[1].access == AccessSetting::Read | AccessSetting::Write);
+}
+
+#[test]
+fn test_reject() {
+    let mut data = build(&[], &[]);
+    assert_eq!(Elf::parse(&data[..32]).unwrap_err(), ElfError::Truncated);
+    data[18] = 62;
+    assert_eq!(Elf::parse(&data).unwrap_err(), ElfError::BadMachine(62));
+    assert_eq!(Elf::parse(b"#!/bin/sh\n").unwrap_err(), ElfError::BadMagic);
+
+    let data = build(&[(PT_INTERP, 4, 0, 0, 0)], &[]);
+    let elf = Elf::parse(&data).unwrap();
+    assert_eq!(
+        elf.segments().next().unwrap().err(),
+        Some(ElfError::Dynamic)
+    );
+
+    let data = build(&[(PT_LOAD, 4, 0x40_0000, 0x100, 0x100)], &[]);
+    let elf = Elf::parse(&data).unwrap();
+    assert_eq!(
+        elf.segments().next().unwrap().err(),
+        Some(ElfError::Truncated)
+    );
+
+    let data = build(&[(PT_LOAD, 4, 1 << 47, 0, 0x1000)], &[]);
+    let elf = Elf::parse(&data).unwrap();
+    assert!(matches!(
+        elf.segments().next().unwrap(),
+        Err(ElfError::BadSegment(_))
+    ));
+}
+
+#[test]
+fn test_permissions() {
+    let data = build(&[(PT_LOAD, 7, 0x40_0000, 0, 0x2000)], &[]);
+    let elf = Elf::parse(&data).unwrap();
+    assert_eq!(
+        elf.segments().next().unwrap().err(),
+        Some(ElfError::WriteExec(0x40_0000..0x40_2000))
+    );
+
+    // 入口 0x40_1000
+    let data = build(&[(PT_LOAD, 5, 0x40_0000, 0, 0x2000)], &[]);
+    assert_eq!(Elf::parse(&data).unwrap().check_entry(), Ok(()));
+
+    let data = build(
+        &[
+            (PT_LOAD, 5, 0x40_0000, 0, 0x1000),
+            (PT_LOAD, 6, 0x40_1000, 0, 0x1000),
+        ],
+        &[],
+    );
+    assert_eq!(
+        Elf::parse(&data).unwrap().check_entry(),
+        Err(ElfError::BadEntry(0x40_1000))
+    );
+}
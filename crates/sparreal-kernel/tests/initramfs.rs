@@ -0,0 +1,123 @@
+//! 解包 newc 格式的 cpio 归档，按路径查找
+
+use sparreal_kernel::fs::{
+    FileType, FsError, RamFs,
+    cpio::{CpioError, Reader},
+};
+
+fn push(out: &mut Vec<u8>, name: &str, mode: u32, data: &[u8]) {
+    let fields = [
+        1,
+        mode,
+        0,
+        0,
+        1,
+        0x6000_0000,
+        data.len() as u32,
+        0,
+        0,
+        0,
+        0,
+        name.len() as u32 + 1,
+        0,
+    ];
+    out.extend_from_slice(b"070701");
+    for f in fields {
+        out.extend_from_slice(format!("{f:08x}").as_bytes());
+    }
+    out.extend_from_slice(name.as_bytes());
+    out.push(0);
+    out.resize(out.len().next_multiple_of(4), 0);
+    out.extend_from_slice(data);
+    out.resize(out.len().next_multiple_of(4), 0);
+}
+
+fn archive() -> Vec<u8> {
+    let mut out = Vec::new();
+    push(&mut out, ".", 0o40755, &[]);
+    push(&mut out, "bin", 0o40755, &[]);
+    push(&mut out, "bin/init", 0o100755, b"\x7fELF");
+    push(&mut out, "bin/sh", 0o120777, b"init");
+    push(&mut out, "etc/hostname", 0o100644, b"sparreal\n");
+    push(&mut out, "dev/console", 0o20600, &[]);
+    push(&mut out, "TRAILER!!!", 0, &[]);
+    // 第二个归档，之间填充 0
+    out.resize(out.len() + 512, 0);
+    push(&mut out, "lib", 0o120777, b"/bin");
+    push(&mut out, "loop", 0o120777, b"loop");
+    push(&mut out, "TRAILER!!!", 0, &[]);
+    out
+}
+
+#[test]
+fn test_reader() {
+    let data = archive();
+    let names: Vec<_> = Reader::new(&data).map(|e| e.unwrap().name).collect();
+    assert_eq!(
+        names,
+        [
+            ".",
+            "bin",
+            "bin/init",
+            "bin/sh",
+            "etc/hostname",
+            "dev/console",
+            "lib",
+            "loop"
+        ]
+    );
+
+    let init = Reader::new(&data).nth(2).unwrap().unwrap();
+    assert!(init.is_file());
+    assert_eq!(init.data, b"\x7fELF");
+    assert_eq!(init.mtime, 0x6000_0000);
+
+    let gzip = [0x1f, 0x8b, 0x08, 0x00];
+    assert_eq!(Reader::new(&gzip).next(), Some(Err(CpioError::Truncated)));
+    let mut bad = data.clone();
+    bad[0] = b'1';
+    let mut reader = Reader::new(&bad);
+    assert_eq!(reader.next(), Some(Err(CpioError::BadMagic(0))));
+    assert_eq!(reader.next(), None);
+    assert_eq!(
+        Reader::new(&data[..data.len() - 200]).last(),
+        Some(Err(CpioError::Truncated))
+    );
+}
+
+#[test]
+fn test_lookup() {
+    let data = archive();
+    let fs = RamFs::from_cpio(&data).unwrap();
+
+    assert_eq!(fs.read("/etc/hostname").unwrap(), b"sparreal\n");
+    assert_eq!(fs.read("etc//./hostname").unwrap(), b"sparreal\n");
+    assert_eq!(fs.read("/bin/sh").unwrap(), b"\x7fELF");
+    assert_eq!(fs.read("/lib/../etc/hostname").unwrap(), b"sparreal\n");
+    assert_eq!(fs.read("/lib/init").unwrap(), b"\x7fELF");
+
+    let meta = fs.metadata("/bin/init").unwrap();
+    assert_eq!(meta.file_type, FileType::File);
+    assert_eq!(meta.mode, 0o755);
+    assert_eq!(meta.len, 4);
+    assert_eq!(fs.metadata("/etc").unwrap().file_type, FileType::Dir);
+
+    assert_eq!(fs.read("/bin"), Err(FsError::IsADirectory));
+    assert_eq!(fs.read("/dev/console"), Err(FsError::NotFound));
+    assert_eq!(fs.read("/etc/hostname/x"), Err(FsError::NotADirectory));
+    assert_eq!(fs.read("/loop"), Err(FsError::TooManyLinks));
+    assert!(!fs.exists("/usr"));
+
+    let root: Vec<_> = fs.read_dir("/").unwrap().map(|(n, _)| n).collect();
+    assert_eq!(root, ["bin", "etc", "lib", "loop"]);
+    let lib: Vec<_> = fs
+        .read_dir("/lib")
+        .unwrap()
+        .map(|(n, m)| (n, m.file_type))
+        .collect();
+    assert_eq!(lib, [("init", FileType::File), ("sh", FileType::Symlink)]);
+    assert_eq!(
+        fs.read_dir("/etc/hostname").err(),
+        Some(FsError::NotADirectory)
+    );
+}
@@ -0,0 +1,216 @@
+//! 在宿主机内存中建表，用 `TableWalker` 核对转换结果和区域列表
+
+use std::{
+    alloc::{Layout, alloc_zeroed, dealloc},
+    ptr::NonNull,
+};
+
+use sparreal_kernel::mem::mmu::{
+    Access, AccessSetting, CacheSetting, MapConfig, PTEArch, PTEGeneric, PTESetting, PageTableRef,
+    TableWalker,
+};
+
+/// 简化的表项格式：
+/// bit0 有效，bit1 块，bit2 非全局，bit3..5 缓存，bit48..56 特权权限，bit56..64 用户权限
+#[derive(Clone, Copy)]
+struct TestPte;
+
+const PA_MASK: usize = 0xffff_ffff_f000;
+
+impl PTEArch for TestPte {
+    fn page_size() -> usize {
+        0x1000
+    }
+
+    fn level() -> usize {
+        4
+    }
+
+    fn new_pte(config: PTEGeneric) -> usize {
+        let s = config.setting;
+        let cache = match s.cache_setting {
+            CacheSetting::Normal => 0,
+            CacheSetting::Device => 1,
+            CacheSetting::NonCache => 2,
+        };
+        (config.paddr & PA_MASK)
+            | config.is_valid as usize
+            | (config.is_block as usize) << 1
+            | (!s.is_global as usize) << 2
+            | (s.privilege_access.bits() as usize) << 48
+            | (s.user_access.bits() as usize) << 56
+            | cache << 3
+    }
+
+    fn read_pte(pte: usize) -> PTEGeneric {
+        PTEGeneric {
+            paddr: pte & PA_MASK,
+            is_valid: pte & 1 != 0,
+            is_block: pte & 2 != 0,
+            setting: PTESetting {
+                is_global: pte & 4 == 0,
+                privilege_access: AccessSetting::from_bits_truncate((pte >> 48) as u8),
+                user_access: AccessSetting::from_bits_truncate((pte >> 56) as u8),
+                cache_setting: match (pte >> 3) & 3 {
+                    0 => CacheSetting::Normal,
+                    1 => CacheSetting::Device,
+                    _ => CacheSetting::NonCache,
+                },
+            },
+        }
+    }
+}
+
+/// 表内存直接取自宿主堆，物理地址即指针值
+struct HostAccess;
+
+impl Access for HostAccess {
+    fn va_offset(&self) -> usize {
+        0
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { alloc_zeroed(layout) })
+    }
+
+    unsafe fn dealloc(&mut self, ptr: NonNull<u8>, layout: Layout) {
+        unsafe { dealloc(ptr.as_ptr(), layout) }
+    }
+}
+
+const KIMAGE: usize = 0xffff_e000_0000_0000;
+const RX: AccessSetting = AccessSetting::Read.union(AccessSetting::Execute);
+const RW: AccessSetting = AccessSetting::Read.union(AccessSetting::Write);
+
+fn map(
+    table: &mut PageTableRef<'static, TestPte>,
+    va: usize,
+    pa: usize,
+    size: usize,
+    access: AccessSetting,
+    cache: CacheSetting,
+    huge: bool,
+) {
+    unsafe {
+        table
+            .map_region(
+                MapConfig::new(va as _, pa, access, cache),
+                size,
+                huge,
+                &mut HostAccess,
+            )
+            .unwrap()
+    };
+}
+
+fn build() -> PageTableRef<'static, TestPte> {
+    let mut table = PageTableRef::create_empty(&mut HostAccess).unwrap();
+    // 2M 块 + 紧接的 4K 页，属性相同应合并
+    map(
+        &mut table,
+        KIMAGE,
+        0x4000_0000,
+        0x20_0000,
+        RX,
+        CacheSetting::Normal,
+        true,
+    );
+    map(
+        &mut table,
+        KIMAGE + 0x20_0000,
+        0x4020_0000,
+        0x2000,
+        RX,
+        CacheSetting::Normal,
+        false,
+    );
+    // 权限不同
+    map(
+        &mut table,
+        KIMAGE + 0x20_2000,
+        0x4020_2000,
+        0x1000,
+        RW,
+        CacheSetting::Normal,
+        false,
+    );
+    // 物理地址不连续
+    map(
+        &mut table,
+        KIMAGE + 0x20_3000,
+        0x5000_0000,
+        0x1000,
+        RW,
+        CacheSetting::Normal,
+        false,
+    );
+    // 低半区设备映射
+    map(
+        &mut table,
+        0x900_0000,
+        0x900_0000,
+        0x1000,
+        RW,
+        CacheSetting::Device,
+        false,
+    );
+    table
+}
+
+#[test]
+fn test_translate() {
+    let mut table = build();
+    let walker = TableWalker::<TestPte>::new(table.paddr(), 0);
+
+    let t = walker.translate(KIMAGE + 0x1_2345).unwrap();
+    assert_eq!(t.paddr, 0x4001_2345);
+    assert_eq!(t.level, 2);
+    assert!(t.pte.is_block);
+    assert!(t.pte.setting.privilege_access == RX);
+
+    let t = walker.translate(KIMAGE + 0x20_3010).unwrap();
+    assert_eq!(t.paddr, 0x5000_0010);
+    assert_eq!(t.level, 1);
+
+    let t = walker.translate(0x900_0004).unwrap();
+    assert_eq!(t.paddr, 0x900_0004);
+    assert_eq!(t.pte.setting.cache_setting, CacheSetting::Device);
+
+    assert!(walker.translate(KIMAGE + 0x20_4000).is_none());
+    assert!(walker.translate(0).is_none());
+    assert_eq!(walker.steps(0).len(), 3);
+    assert_eq!(walker.steps(0x80_0000_0000).len(), 1);
+    assert_eq!(walker.steps(KIMAGE + 0x20_4000).len(), 4);
+
+    table.release(&mut HostAccess);
+}
+
+#[test]
+fn test_regions() {
+    let mut table = build();
+    let walker = TableWalker::<TestPte>::new(table.paddr(), 0);
+    let regions = walker.regions();
+
+    let got: Vec<_> = regions
+        .iter()
+        .map(|r| (r.vaddr.start, r.vaddr.end, r.paddr))
+        .collect();
+    assert_eq!(
+        got,
+        [
+            (0x900_0000, 0x900_1000, 0x900_0000),
+            (KIMAGE, KIMAGE + 0x20_2000, 0x4000_0000),
+            (KIMAGE + 0x20_2000, KIMAGE + 0x20_3000, 0x4020_2000),
+            (KIMAGE + 0x20_3000, KIMAGE + 0x20_4000, 0x5000_0000),
+        ]
+    );
+    assert!(regions[1].setting.privilege_access == RX);
+    assert!(regions[2].setting.privilege_access == RW);
+
+    let dump = walker.to_string();
+    assert_eq!(dump.lines().count(), 4);
+    assert!(dump.lines().next().unwrap().contains("P:rw- U:--- Device"));
+    assert!(dump.contains("[0xffffe00000000000, 0xffffe00000202000)"));
+
+    table.release(&mut HostAccess);
+}
@@ -150,7 +150,8 @@ pub fn api_impl(_args: TokenStream, item: TokenStream) -> TokenStream {
 pub fn build_test_setup(_input: TokenStream) -> TokenStream {
     quote! {
     println!("cargo::rustc-link-arg-tests=-Tlink.x");
-    println!("cargo::rustc-link-arg-tests=-no-pie");
+    println!("cargo::rustc-link-arg-tests=-pie");
+    println!("cargo::rustc-link-arg-tests=--no-dynamic-linker");
     println!("cargo::rustc-link-arg-tests=-znostart-stop-gc");
     }
     .into()
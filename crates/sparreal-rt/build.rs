@@ -14,7 +14,9 @@ const ENTRY_VADDR: u64 = 0xffff_e000_0000_0000;
 
 fn main() {
     println!("cargo::rustc-link-arg=-Tlink.x");
-    println!("cargo::rustc-link-arg=-no-pie");
+    // 需配合 `-C relocation-model=pie`，启动时自行处理 `.rela.dyn`
+    println!("cargo::rustc-link-arg=-pie");
+    println!("cargo::rustc-link-arg=--no-dynamic-linker");
     println!("cargo::rustc-link-arg=-znostart-stop-gc");
     println!("cargo:rerun-if-changed=link.ld");
     println!("cargo:rerun-if-changed=build.rs");
@@ -70,15 +72,28 @@ impl Arch {
 
         let ld_content =
             ld_content.replace("%STACK_SIZE%", &format!("{:#x}", DEFAULT_KERNEL_STACK_SIZE));
+        let ld_content = ld_content.replace("%PAGE_SIZE%", &format!("{:#x}", page_size()));
         std::fs::write(out_dir().join("link.x"), ld_content).expect("link.x write failed");
     }
 }
 
+/// 段按页对齐，才能分别设置权限
+fn page_size() -> usize {
+    if std::env::var("CARGO_FEATURE_GRANULE_64K").is_ok() {
+        0x10000
+    } else if std::env::var("CARGO_FEATURE_GRANULE_16K").is_ok() {
+        0x4000
+    } else {
+        0x1000
+    }
+}
+
 fn gen_const() {
     let const_content = format!(
         r#"pub const KERNEL_STACK_SIZE: usize = {:#x};
+pub const KERNEL_VADDR: usize = {:#x};
             "#,
-        DEFAULT_KERNEL_STACK_SIZE
+        DEFAULT_KERNEL_STACK_SIZE, ENTRY_VADDR
     );
 
     std::fs::write(out_dir().join("constant.rs"), const_content).expect("const write failed");
@@ -1,24 +1,49 @@
-use core::arch::{asm, naked_asm};
+use core::{
+    arch::{asm, naked_asm},
+    ffi::CStr,
+};
 
 use aarch64_cpu::{asm::barrier, registers::*};
 use sparreal_kernel::{globals::PlatformInfoKind, io::print::*, platform::shutdown};
 
-use super::debug;
+use super::{
+    adr_l, debug,
+    efi::EfiBoot,
+    paging::GRANULE,
+    relocate::{self, relocate},
+};
 use crate::mem::{self, clean_bss};
 
 const FLAG_LE: usize = 0b0;
-const FLAG_PAGE_SIZE_4K: usize = 0b10;
+const FLAG_PAGE_SIZE: usize = GRANULE.image_header_flag();
 const FLAG_ANY_MEM: usize = 0b1000;
 
+const IMAGE_FILE_MACHINE_ARM64: u16 = 0xaa64;
+/// EXECUTABLE_IMAGE | LINE_NUMS_STRIPPED | DEBUG_STRIPPED
+const IMAGE_FILE_CHARACTERISTICS: u16 = 0x0206;
+const PE_OPT_MAGIC_PE32PLUS: u16 = 0x020b;
+const IMAGE_SUBSYSTEM_EFI_APPLICATION: u16 = 10;
+/// CNT_CODE | MEM_EXECUTE | MEM_READ
+const SCN_TEXT: u32 = 0x6000_0020;
+/// CNT_INITIALIZED_DATA | MEM_READ | MEM_WRITE
+const SCN_DATA: u32 = 0xc000_0040;
+/// 段在文件中的偏移与加载后的偏移相同，都按页对齐
+const PE_SECTION_ALIGN: usize = GRANULE.page_size();
+const PE_FILE_ALIGN: usize = 0x200;
+
 #[naked]
 #[unsafe(no_mangle)]
 #[unsafe(link_section = ".text.head")]
 /// The entry point of the kernel.
+///
+/// Linux arm64 Image 头同时也是 PE/COFF 头，UEFI 从 [`efi_main`](super::efi::efi_main) 进入。
 pub unsafe extern "C" fn _start() -> ! {
     unsafe {
         naked_asm!(
-            // code0/code1
-            "nop",
+            "0:",
+            // code0: "MZ"，作为指令执行时不影响启动
+            "ccmp     x18, #0, #0xd, pl",
+            // code1
             "bl {entry}",
             // text_offset
             ".quad 0",
@@ -32,10 +57,86 @@ pub unsafe extern "C" fn _start() -> ! {
             ".quad 0",
             // magic - yes 0x644d5241 is the same as ASCII string "ARM\x64"
             ".ascii \"ARM\\x64\"",
-            // Another reserved field at the end of the header
-            ".byte 0, 0, 0, 0",
-            flags = const FLAG_LE | FLAG_PAGE_SIZE_4K | FLAG_ANY_MEM,
+            // PE 头的偏移
+            ".long 1f - 0b",
+            // PE 签名和 COFF 文件头
+            "1:",
+            ".ascii \"PE\\0\\0\"",
+            ".short {machine}",
+            // NumberOfSections
+            ".short 2",
+            // TimeDateStamp, PointerToSymbolTable, NumberOfSymbols
+            ".long 0, 0, 0",
+            ".short 3f - 2f",
+            ".short {characteristics}",
+            // PE32+ 可选头
+            "2:",
+            ".short {pe32plus}",
+            // LinkerVersion
+            ".byte 0x02, 0x14",
+            // SizeOfCode, SizeOfInitializedData, SizeOfUninitializedData
+            ".long _etext - 4f",
+            ".long _pe_data_size",
+            ".long 0",
+            // AddressOfEntryPoint, BaseOfCode
+            ".long {efi_main} - 0b",
+            ".long 4f - 0b",
+            // ImageBase，固件可加载到任意地址，入口会自行重定位
+            ".quad 0",
+            ".long {section_align}",
+            ".long {file_align}",
+            // OS, image 和 subsystem 版本
+            ".short 0, 0, 0, 0, 0, 0",
+            // Win32VersionValue
+            ".long 0",
+            // SizeOfImage, SizeOfHeaders, CheckSum
+            ".long _pe_image_size",
+            ".long 4f - 0b",
+            ".long 0",
+            ".short {subsystem}",
+            // DllCharacteristics
+            ".short 0",
+            // 栈和堆的 reserve/commit
+            ".quad 0, 0, 0, 0",
+            // LoaderFlags, NumberOfRvaAndSizes
+            ".long 0",
+            ".long 6",
+            // 不需要导出、导入、重定位等数据目录
+            ".quad 0, 0, 0, 0, 0, 0",
+            // 段表
+            "3:",
+            ".ascii \".text\\0\\0\\0\"",
+            // VirtualSize, VirtualAddress, SizeOfRawData, PointerToRawData
+            ".long _etext - 4f",
+            ".long 4f - 0b",
+            ".long _etext - 4f",
+            ".long 4f - 0b",
+            // 重定位和行号
+            ".long 0, 0",
+            ".short 0, 0",
+            ".long {scn_text}",
+            // 之后到镜像末尾，包括 .bss、启动栈和复制 FDT 的空间
+            ".ascii \".data\\0\\0\\0\"",
+            ".long _pe_data_size",
+            ".long _etext - 0b",
+            ".long _pe_data_raw_size",
+            ".long _etext - 0b",
+            ".long 0, 0",
+            ".short 0, 0",
+            ".long {scn_data}",
+            ".balign {section_align}",
+            "4:",
+            flags = const FLAG_LE | FLAG_PAGE_SIZE | FLAG_ANY_MEM,
             entry = sym primary_entry,
+            efi_main = sym super::efi::efi_main,
+            machine = const IMAGE_FILE_MACHINE_ARM64,
+            characteristics = const IMAGE_FILE_CHARACTERISTICS,
+            pe32plus = const PE_OPT_MAGIC_PE32PLUS,
+            subsystem = const IMAGE_SUBSYSTEM_EFI_APPLICATION,
+            section_align = const PE_SECTION_ALIGN,
+            file_align = const PE_FILE_ALIGN,
+            scn_text = const SCN_TEXT,
+            scn_data = const SCN_DATA,
         )
     }
 }
@@ -43,44 +144,72 @@ pub unsafe extern "C" fn _start() -> ! {
 #[naked]
 #[unsafe(link_section = ".text.boot")]
 /// The entry point of the kernel.
-unsafe extern "C" fn primary_entry() -> ! {
+pub(super) unsafe extern "C" fn primary_entry() -> ! {
     unsafe {
         naked_asm!(
-            "ADR      x11, .",
-            "LDR      x10, ={this_func}",
-            "SUB      x18, x10, x11", // x18 = va_offset
             "MOV      x19, x0",        // x19 = dtb_addr
+            "MOV      x20, x1",        // x20 = EFI 启动信息，Image 启动时为 0
 
             // setup stack
-            "LDR      x1,  =_stack_top",
-            "SUB      x1,  x1, x18", // X1 == STACK_TOP
+            "ADRP     x1,  _stack_top",
+            "ADD      x1,  x1, :lo12:_stack_top",
             "MOV      sp,  x1",
 
             "BL       {switch_to_elx}",
+            // 重定位会记录错误，先清零 .bss
+            "BL       {clean_bss}",
+
+            // 偏移在重定位前确定，镜像只重定位一次
+            "MOV      x0,  x19",
+            "BL       {kaslr}",
+            "MOV      x21, x0",        // x21 = kaslr
+            "BL       {relocate}",
+            "MOV      x18, x0",        // x18 = va_offset
 
             "MOV      x0,  x18",
-            "MOV      x1,  x19",
+            "MOV      x1,  x21",
+            "MOV      x2,  x19",
+            "MOV      x3,  x20",
             "BL       {entry}",
-            this_func = sym primary_entry,
             switch_to_elx = sym switch_to_elx,
+            clean_bss = sym clean_bss,
+            kaslr = sym kaslr_offset,
+            relocate = sym relocate,
             entry = sym rust_entry,
         )
     }
 }
 
-fn rust_entry(text_va: usize, fdt: *mut u8) -> ! {
-    clean_bss();
+fn rust_entry(text_va: usize, kaslr: usize, fdt: *mut u8, efi: *const EfiBoot) -> ! {
     enable_fp();
     unsafe {
         mem::mmu::set_text_va_offset(text_va);
         debug::setup_by_fdt(fdt, |r| r as _);
-        asm!(
-            "
-        LDR      x0, =vector_table_el1
-        MSR      VBAR_EL1, x0
-        ",
-        );
     }
+
+    // 固件只提供 ACPI 时，EFI 桩传入 RSDP 和内存表
+    let acpi = match unsafe { efi.as_ref() } {
+        Some(efi) if fdt.is_null() && efi.rsdp != 0 => Some(PlatformInfoKind::new_acpi(
+            efi.rsdp.into(),
+            efi.memory_map(),
+            efi.desc_size,
+        )),
+        _ => None,
+    };
+    if let Some(port) = acpi.as_ref().and_then(PlatformInfoKind::debugcon) {
+        debug::setup_by_port(&port, |r| r as _);
+    }
+
+    if let Some(ty) = relocate::unsupported() {
+        early_dbg("unsupported relocation type ");
+        early_dbg_hexln(ty);
+        shutdown();
+    }
+
+    if cfg!(feature = "kaslr") && kaslr == 0 {
+        early_dbgln("KASLR: no entropy, disabled");
+    }
+
     match CurrentEL.read(CurrentEL::EL) {
         1 => early_dbgln("EL1"),
         2 => early_dbgln("EL2"),
@@ -93,17 +222,136 @@ fn rust_entry(text_va: usize, fdt: *mut u8) -> ! {
 
         let platform_info: PlatformInfoKind = if let Some(addr) = fdt {
             PlatformInfoKind::new_fdt((addr.as_ptr() as usize).into())
+        } else if let Some(acpi) = acpi {
+            acpi
         } else {
             todo!()
         };
 
-        if let Err(s) = sparreal_kernel::boot::start(text_va, platform_info) {
+        VBAR_EL1.set((adr_l!("vector_table_el1") + text_va) as _);
+
+        // EFI 的内存在进入内核后可能被覆盖，先复制出来；有设备树时内存表也要保留其中的固件区间
+        let firmware = efi
+            .as_ref()
+            .map(|e| {
+                let mut firmware = e.firmware.clone();
+                firmware.add_efi_memory_map(e.memory_map(), e.desc_size);
+                firmware
+            })
+            .unwrap_or_default();
+
+        if let Err(s) = sparreal_kernel::boot::start(text_va, kaslr, platform_info, firmware) {
             early_dbgln(s);
         }
     }
     shutdown()
 }
 
+/// 种子优先取 FDT `/chosen`，其次 RNDR，都没有时不随机化
+///
+/// 在重定位前调用，不能使用需要重定位的数据，也还不能输出
+extern "C" fn kaslr_offset(fdt: *const u8) -> usize {
+    if !cfg!(feature = "kaslr") {
+        return 0;
+    }
+    match fdt_kaslr_seed(fdt).or_else(rndr) {
+        Some(seed) => mem::mmu::kaslr_offset_from_seed(seed),
+        None => 0,
+    }
+}
+
+const FDT_MAGIC: u32 = 0xd00d_feed;
+const FDT_BEGIN_NODE: u32 = 1;
+const FDT_END_NODE: u32 = 2;
+const FDT_PROP: u32 = 3;
+const FDT_NOP: u32 = 4;
+
+/// `/chosen` 下的 `kaslr-seed`，没有时将 `rng-seed` 折叠为 64 位
+///
+/// 直接遍历结构块，`fdt-parser` 需要重定位后才能使用
+fn fdt_kaslr_seed(fdt: *const u8) -> Option<u64> {
+    if fdt.is_null() {
+        return None;
+    }
+    let be32 = |off: usize| u32::from_be(unsafe { fdt.add(off).cast::<u32>().read_unaligned() });
+    let bytes = |off: usize, len: usize| unsafe { core::slice::from_raw_parts(fdt.add(off), len) };
+    let cstr = |off: usize| unsafe { CStr::from_ptr(fdt.add(off).cast()) }.to_bytes();
+
+    if be32(0) != FDT_MAGIC {
+        return None;
+    }
+    let strings = be32(12) as usize;
+    let mut off = be32(8) as usize;
+    let end = off + be32(36) as usize;
+    let mut depth = 0;
+    let mut in_chosen = false;
+    let mut rng = None;
+
+    while off < end {
+        let token = be32(off);
+        off += 4;
+        match token {
+            FDT_BEGIN_NODE => {
+                let name = cstr(off);
+                depth += 1;
+                in_chosen = depth == 2 && name == b"chosen";
+                off += (name.len() + 1).next_multiple_of(4);
+            }
+            FDT_END_NODE => {
+                if in_chosen {
+                    break;
+                }
+                depth -= 1;
+            }
+            FDT_PROP => {
+                let len = be32(off) as usize;
+                let name = cstr(strings + be32(off + 4) as usize);
+                off += 8;
+                if in_chosen {
+                    let value = bytes(off, len);
+                    match name {
+                        b"kaslr-seed" if len == 8 => {
+                            let seed = u64::from_be_bytes(value.try_into().ok()?);
+                            if seed != 0 {
+                                return Some(seed);
+                            }
+                        }
+                        b"rng-seed" => rng = Some(value),
+                        _ => {}
+                    }
+                }
+                off += len.next_multiple_of(4);
+            }
+            FDT_NOP => {}
+            _ => break,
+        }
+    }
+
+    let (chunks, _) = rng?.as_chunks::<8>();
+    let seed = chunks.iter().fold(0u64, |acc, c| {
+        (acc ^ u64::from_be_bytes(*c)).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+    });
+    (seed != 0).then_some(seed)
+}
+
+fn rndr() -> Option<u64> {
+    if !ID_AA64ISAR0_EL1.matches_all(ID_AA64ISAR0_EL1::RNDR::Supported) {
+        return None;
+    }
+    let seed: u64;
+    let ok: u64;
+    unsafe {
+        // RNDR 失败时置 NZCV 为 0b0100
+        asm!(
+            "mrs {seed}, s3_3_c2_c4_0",
+            "cset {ok}, ne",
+            seed = out(reg) seed,
+            ok = out(reg) ok,
+        );
+    }
+    (ok != 0).then_some(seed)
+}
+
 fn switch_to_elx() {
     #[cfg(feature = "vm")]
     switch_to_el2();
@@ -114,6 +362,7 @@ fn switch_to_elx() {
 fn switch_to_el1() {
     SPSel.write(SPSel::SP::ELx);
     SP_EL0.set(0);
+    TPIDR_EL1.set(0);
     let current_el = CurrentEL.read(CurrentEL::EL);
     if current_el >= 2 {
         if current_el == 3 {
@@ -156,6 +405,7 @@ fn switch_to_el1() {
             mov     x8, sp
             msr     sp_el1, x8
             MOV     x0, x19
+            MOV     x1, x20
             adr     x2, {}
             msr     elr_el2, x2
             eret
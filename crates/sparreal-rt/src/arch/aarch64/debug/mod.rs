@@ -6,25 +6,35 @@ use core::{
 
 use any_uart::block;
 pub use any_uart::{FnPhysToVirt, Sender};
+use sparreal_kernel::platform::SerialPort;
+
+mod raw;
+
+use raw::RawUart;
 
 static UART: UartWapper = UartWapper(UnsafeCell::new(None));
 static REGBASE: AtomicUsize = AtomicUsize::new(0);
 
-struct UartWapper(UnsafeCell<Option<Sender>>);
+enum Console {
+    Fdt(Sender),
+    Raw(RawUart),
+}
+
+struct UartWapper(UnsafeCell<Option<Console>>);
 
 unsafe impl Send for UartWapper {}
 unsafe impl Sync for UartWapper {}
 
 impl UartWapper {
-    fn set(&self, uart: Sender) {
+    fn set(&self, uart: Console) {
         unsafe {
             *self.0.get() = Some(uart);
         }
     }
 
     #[allow(clippy::mut_from_ref)]
-    fn get(&self) -> &mut Sender {
-        unsafe { &mut *self.0.get().as_mut().unwrap().as_mut().unwrap() }
+    fn get(&self) -> Option<&mut Console> {
+        unsafe { (*self.0.get()).as_mut() }
     }
 }
 
@@ -33,7 +43,13 @@ pub fn reg() -> usize {
 }
 
 pub fn put(byte: u8) {
-    let _ = block!(UART.get().write(byte));
+    match UART.get() {
+        Some(Console::Fdt(tx)) => {
+            let _ = block!(tx.write(byte));
+        }
+        Some(Console::Raw(uart)) => uart.put(byte),
+        None => {}
+    }
 }
 pub fn setup_by_fdt(fdt: *mut u8, f: FnPhysToVirt) -> Option<()> {
     let mut uart = any_uart::init(NonNull::new(fdt)?, f)?;
@@ -43,7 +59,27 @@ pub fn setup_by_fdt(fdt: *mut u8, f: FnPhysToVirt) -> Option<()> {
         REGBASE.store(tx.mmio(), Ordering::SeqCst);
     }
 
-    UART.set(tx);
+    UART.set(Console::Fdt(tx));
 
     Some(())
 }
+
+/// 没有设备树时按平台信息中的串口直接访问寄存器
+pub fn setup_by_port(port: &SerialPort, f: FnPhysToVirt) -> Option<()> {
+    let addr = port.addr.raw();
+    let uart = RawUart::new(port.compatibles(), f(addr) as usize)?;
+    if REGBASE.load(Ordering::SeqCst) == 0 {
+        REGBASE.store(addr, Ordering::SeqCst);
+    }
+
+    UART.set(Console::Raw(uart));
+
+    Some(())
+}
+
+/// 开启 MMU 前更新直接访问的串口地址
+pub fn remap(f: FnPhysToVirt) {
+    if let Some(Console::Raw(uart)) = UART.get() {
+        uart.set_base(f(reg()) as usize);
+    }
+}
@@ -0,0 +1,62 @@
+//! 按 compatible 直接访问寄存器的串口，只实现发送
+
+use core::{
+    hint::spin_loop,
+    ptr::{read_volatile, write_volatile},
+};
+
+const PL011_DR: usize = 0x00;
+const PL011_FR: usize = 0x18;
+const PL011_FR_TXFF: u32 = 1 << 5;
+
+const UART8250_THR: usize = 0;
+const UART8250_LSR: usize = 5;
+const UART8250_LSR_THRE: u8 = 1 << 5;
+
+#[derive(Clone, Copy)]
+enum Kind {
+    /// 也用于 SBSA 通用串口，发送相关寄存器相同
+    Pl011,
+    Uart8250,
+}
+
+pub struct RawUart {
+    kind: Kind,
+    base: usize,
+}
+
+impl RawUart {
+    pub fn new<'a>(mut compatibles: impl Iterator<Item = &'a str>, base: usize) -> Option<Self> {
+        let kind = compatibles.find_map(|c| match c {
+            "arm,pl011" | "arm,sbsa-uart" => Some(Kind::Pl011),
+            "ns16550a" | "ns16550" => Some(Kind::Uart8250),
+            _ => None,
+        })?;
+        Some(Self { kind, base })
+    }
+
+    pub fn set_base(&mut self, base: usize) {
+        self.base = base;
+    }
+
+    pub fn put(&self, byte: u8) {
+        unsafe {
+            match self.kind {
+                Kind::Pl011 => {
+                    while read_volatile((self.base + PL011_FR) as *const u32) & PL011_FR_TXFF != 0 {
+                        spin_loop();
+                    }
+                    write_volatile((self.base + PL011_DR) as *mut u32, byte as u32);
+                }
+                Kind::Uart8250 => {
+                    while read_volatile((self.base + UART8250_LSR) as *const u8) & UART8250_LSR_THRE
+                        == 0
+                    {
+                        spin_loop();
+                    }
+                    write_volatile((self.base + UART8250_THR) as *mut u8, byte);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,462 @@
+//! EFI 桩：作为 UEFI 应用启动时取得 FDT 或 ACPI 表、内存表、帧缓冲、控制台参数和命令行，
+//! 退出启动服务后按 Image 协议进入内核
+//!
+//! 固件调用 [`efi_main`] 时镜像还未重定位，这里不能使用需要重定位的数据，
+//! 例如函数指针表和保存在静态变量中的引用。
+
+use core::{arch::asm, ffi::c_void, ptr::null_mut};
+
+use arrayvec::ArrayString;
+use sparreal_kernel::platform::{
+    FIRMWARE_BOOTARGS_MAX, FirmwareConsole, FirmwareInfo, Framebuffer, PixelFormat,
+};
+
+use super::{CacheOp, adr_l, boot::primary_entry, cache::dcache_range};
+
+type Handle = *mut c_void;
+type Status = usize;
+
+const SUCCESS: Status = 0;
+const ERROR_BIT: Status = 1 << 63;
+const UNSUPPORTED: Status = ERROR_BIT | 3;
+const BUFFER_TOO_SMALL: Status = ERROR_BIT | 5;
+
+const EFI_PAGE_SIZE: usize = 0x1000;
+const LOADER_DATA: u32 = 2;
+const ACPI_RECLAIM_MEMORY: u32 = 9;
+const ACPI_MEMORY_NVS: u32 = 10;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+struct Guid(u32, u16, u16, [u8; 8]);
+
+const FDT_GUID: Guid = Guid(
+    0xb1b6_21d5,
+    0xf19c,
+    0x41a5,
+    [0x83, 0x0b, 0xd9, 0x15, 0x2c, 0x69, 0xaa, 0xe0],
+);
+const ACPI_20_GUID: Guid = Guid(
+    0x8868_e871,
+    0xe4f1,
+    0x11d3,
+    [0xbc, 0x22, 0x00, 0x80, 0xc7, 0x3c, 0x88, 0x81],
+);
+const ACPI_GUID: Guid = Guid(
+    0xeb9d_2d30,
+    0x2d88,
+    0x11d3,
+    [0x9a, 0x16, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0x4d],
+);
+const LOADED_IMAGE_GUID: Guid = Guid(
+    0x5b1b_31a1,
+    0x9562,
+    0x11d2,
+    [0x8e, 0x3f, 0x00, 0xa0, 0xc9, 0x69, 0x72, 0x3b],
+);
+const GOP_GUID: Guid = Guid(
+    0x9042_a9de,
+    0x23dc,
+    0x4a38,
+    [0x96, 0xfb, 0x7a, 0xde, 0xd0, 0x80, 0x51, 0x6a],
+);
+const SERIAL_IO_GUID: Guid = Guid(
+    0xbb25_cf6f,
+    0xf1d4,
+    0x11d2,
+    [0x9a, 0x0c, 0x00, 0x90, 0x27, 0x3f, 0xc1, 0xfd],
+);
+
+/// 表头，签名、版本、大小和校验和
+type TableHeader = [u64; 3];
+
+#[repr(C)]
+pub(super) struct SystemTable {
+    _hdr: TableHeader,
+    _firmware_vendor: *const u16,
+    _firmware_revision: u32,
+    _console_in: [Handle; 2],
+    _console_out_handle: Handle,
+    con_out: *mut TextOutput,
+    _std_err: [Handle; 2],
+    _runtime_services: *mut c_void,
+    boot_services: *const BootServices,
+    number_of_table_entries: usize,
+    configuration_table: *const ConfigurationTable,
+}
+
+/// 固件的文本控制台，输出到 GOP 和串口
+#[repr(C)]
+struct TextOutput {
+    _reset: usize,
+    output_string: unsafe extern "efiapi" fn(*mut TextOutput, *const u16) -> Status,
+    _test_string: usize,
+    query_mode: unsafe extern "efiapi" fn(*mut TextOutput, usize, *mut usize, *mut usize) -> Status,
+    /// SetMode 到 EnableCursor
+    _unused: [usize; 5],
+    mode: *const TextOutputMode,
+}
+
+#[repr(C)]
+struct TextOutputMode {
+    _max_mode: i32,
+    mode: i32,
+}
+
+#[repr(C)]
+struct BootServices {
+    _hdr: TableHeader,
+    /// RaiseTPL 到 FreePages
+    _unused0: [usize; 4],
+    get_memory_map:
+        unsafe extern "efiapi" fn(*mut usize, *mut u8, *mut usize, *mut usize, *mut u32) -> Status,
+    allocate_pool: unsafe extern "efiapi" fn(u32, usize, *mut *mut u8) -> Status,
+    /// FreePool 到 UninstallProtocolInterface
+    _unused1: [usize; 10],
+    handle_protocol: unsafe extern "efiapi" fn(Handle, *const Guid, *mut *mut c_void) -> Status,
+    /// Reserved 到 UnloadImage
+    _unused2: [usize; 9],
+    exit_boot_services: unsafe extern "efiapi" fn(Handle, usize) -> Status,
+    /// GetNextMonotonicCount 到 LocateHandleBuffer
+    _unused3: [usize; 10],
+    locate_protocol:
+        unsafe extern "efiapi" fn(*const Guid, *mut c_void, *mut *mut c_void) -> Status,
+}
+
+#[repr(C)]
+struct LoadedImage {
+    _revision: u32,
+    _parent_handle: Handle,
+    _system_table: *const SystemTable,
+    _device_handle: Handle,
+    _file_path: *const c_void,
+    _reserved: *const c_void,
+    load_options_size: u32,
+    load_options: *const u16,
+}
+
+#[repr(C)]
+struct GraphicsOutput {
+    /// QueryMode, SetMode, Blt
+    _unused: [usize; 3],
+    mode: *const GraphicsMode,
+}
+
+#[repr(C)]
+struct GraphicsMode {
+    _max_mode: u32,
+    _mode: u32,
+    info: *const GraphicsModeInfo,
+    _size_of_info: usize,
+    frame_buffer_base: u64,
+    frame_buffer_size: usize,
+}
+
+#[repr(C)]
+struct GraphicsModeInfo {
+    _version: u32,
+    horizontal_resolution: u32,
+    vertical_resolution: u32,
+    pixel_format: u32,
+    /// 红、绿、蓝和保留位的掩码
+    pixel_bitmask: [u32; 4],
+    pixels_per_scan_line: u32,
+}
+
+#[repr(C)]
+struct SerialIo {
+    _revision: u32,
+    /// Reset 到 Read
+    _unused: [usize; 6],
+    mode: *const SerialIoMode,
+}
+
+#[repr(C)]
+struct SerialIoMode {
+    _control_mask: u32,
+    _timeout: u32,
+    baud_rate: u64,
+}
+
+#[repr(C)]
+struct ConfigurationTable {
+    guid: Guid,
+    table: *mut u8,
+}
+
+/// 退出启动服务后经 `x1` 交给 `primary_entry`，Image 启动时为空
+#[repr(C)]
+pub(super) struct EfiBoot {
+    pub rsdp: usize,
+    memory_map: *const u8,
+    memory_map_size: usize,
+    pub desc_size: usize,
+    pub firmware: FirmwareInfo,
+}
+
+impl EfiBoot {
+    pub fn memory_map(&self) -> &[u8] {
+        unsafe { core::slice::from_raw_parts(self.memory_map, self.memory_map_size) }
+    }
+}
+
+fn print(out: *mut TextOutput, s: &str) {
+    let mut chars = s.encode_utf16().peekable();
+    while chars.peek().is_some() {
+        // 末尾留一个 0 结束
+        let mut buff = [0u16; 64];
+        for (b, c) in buff[..63].iter_mut().zip(chars.by_ref()) {
+            *b = c;
+        }
+        unsafe { ((*out).output_string)(out, buff.as_ptr()) };
+    }
+}
+
+/// 镜像的 `LoadOptions`，UCS-2 转为 UTF-8，遇到 0 或无效字符结束
+fn load_options(bs: &BootServices, image: Handle) -> Option<ArrayString<FIRMWARE_BOOTARGS_MAX>> {
+    let mut loaded: *mut c_void = null_mut();
+    let status = unsafe { (bs.handle_protocol)(image, &LOADED_IMAGE_GUID, &mut loaded) };
+    if status != SUCCESS || loaded.is_null() {
+        return None;
+    }
+    let loaded = unsafe { &*(loaded as *const LoadedImage) };
+    if loaded.load_options.is_null() {
+        return None;
+    }
+    let options = unsafe {
+        core::slice::from_raw_parts(loaded.load_options, loaded.load_options_size as usize / 2)
+    };
+
+    let mut out = ArrayString::new();
+    for c in char::decode_utf16(options.iter().copied()) {
+        match c {
+            Ok(c) if c != '\0' => {
+                if out.try_push(c).is_err() {
+                    break;
+                }
+            }
+            _ => break,
+        }
+    }
+    let trimmed = out.trim();
+    if trimmed.is_empty() {
+        return None;
+    }
+    ArrayString::from(trimmed).ok()
+}
+
+fn locate<T>(bs: &BootServices, guid: &Guid) -> Option<&'static T> {
+    let mut interface: *mut c_void = null_mut();
+    let status = unsafe { (bs.locate_protocol)(guid, null_mut(), &mut interface) };
+    if status != SUCCESS {
+        return None;
+    }
+    unsafe { (interface as *const T).as_ref() }
+}
+
+/// GOP 当前模式的帧缓冲，只支持 Blt 时没有
+fn framebuffer(bs: &BootServices) -> Option<Framebuffer> {
+    let gop = locate::<GraphicsOutput>(bs, &GOP_GUID)?;
+    let mode = unsafe { gop.mode.as_ref()? };
+    let info = unsafe { mode.info.as_ref()? };
+    let format = match info.pixel_format {
+        0 => PixelFormat::Rgb,
+        1 => PixelFormat::Bgr,
+        2 => PixelFormat::Bitmask {
+            red: info.pixel_bitmask[0],
+            green: info.pixel_bitmask[1],
+            blue: info.pixel_bitmask[2],
+        },
+        _ => return None,
+    };
+    Some(Framebuffer {
+        addr: (mode.frame_buffer_base as usize).into(),
+        size: mode.frame_buffer_size,
+        width: info.horizontal_resolution,
+        height: info.vertical_resolution,
+        stride: info.pixels_per_scan_line,
+        format,
+    })
+}
+
+fn console(bs: &BootServices, con_out: *mut TextOutput) -> Option<FirmwareConsole> {
+    let baud_rate = locate::<SerialIo>(bs, &SERIAL_IO_GUID)
+        .and_then(|serial| unsafe { serial.mode.as_ref() })
+        .map(|mode| mode.baud_rate)
+        .filter(|&baud| baud != 0);
+
+    let text_size = unsafe { con_out.as_mut() }.and_then(|out| {
+        let mode = unsafe { out.mode.as_ref()? };
+        let (mut columns, mut rows) = (0, 0);
+        let status = unsafe { (out.query_mode)(out, mode.mode as usize, &mut columns, &mut rows) };
+        (status == SUCCESS).then_some((columns, rows))
+    });
+
+    (baud_rate.is_some() || text_size.is_some()).then_some(FirmwareConsole {
+        baud_rate,
+        text_size,
+    })
+}
+
+/// 关闭 MMU 后内核直接访问内存，写回并失效 `[start, start + size)` 的缓存
+fn flush(start: usize, size: usize) {
+    dcache_range(CacheOp::CleanAndInvalidate, start, size);
+}
+
+/// PE/COFF 入口，固件已开启 MMU 并恒等映射
+pub(super) unsafe extern "efiapi" fn efi_main(image: Handle, st: *const SystemTable) -> Status {
+    let st = unsafe { &*st };
+    let bs = unsafe { &*st.boot_services };
+    let con_out = st.con_out;
+    print(con_out, "Sparreal EFI stub\r\n");
+
+    let tables =
+        unsafe { core::slice::from_raw_parts(st.configuration_table, st.number_of_table_entries) };
+    let find = |guid: Guid| {
+        tables
+            .iter()
+            .find(|t| t.guid == guid)
+            .map_or(null_mut(), |t| t.table)
+    };
+    let fdt = find(FDT_GUID);
+    let mut rsdp = find(ACPI_20_GUID);
+    if rsdp.is_null() {
+        rsdp = find(ACPI_GUID);
+    }
+    if fdt.is_null() && rsdp.is_null() {
+        print(con_out, "No FDT or ACPI tables\r\n");
+        return UNSUPPORTED;
+    }
+
+    // 启动服务退出后协议都不可用，先取出需要的信息
+    let firmware = FirmwareInfo {
+        bootargs: load_options(bs, image),
+        framebuffer: framebuffer(bs),
+        console: console(bs, con_out),
+    };
+
+    let mut map_size = 0;
+    let mut map_key = 0;
+    let mut desc_size = 0;
+    let mut desc_version = 0;
+    let status = unsafe {
+        (bs.get_memory_map)(
+            &mut map_size,
+            null_mut(),
+            &mut map_key,
+            &mut desc_size,
+            &mut desc_version,
+        )
+    };
+    if status != BUFFER_TOO_SMALL {
+        print(con_out, "GetMemoryMap failed\r\n");
+        return status;
+    }
+
+    // 分配本身会拆分描述符，多留几项
+    let capacity = map_size + 8 * desc_size;
+    let mut buff = null_mut();
+    let status =
+        unsafe { (bs.allocate_pool)(LOADER_DATA, size_of::<EfiBoot>() + capacity, &mut buff) };
+    if status != SUCCESS {
+        print(con_out, "AllocatePool failed\r\n");
+        return status;
+    }
+    let map = unsafe { buff.add(size_of::<EfiBoot>()) };
+
+    print(con_out, "Exit boot services\r\n");
+    // 取内存表和退出之间内存表有变化时退出失败，须重新获取，此后不能再调用其他服务
+    let mut status = BUFFER_TOO_SMALL;
+    for _ in 0..4 {
+        map_size = capacity;
+        status = unsafe {
+            (bs.get_memory_map)(
+                &mut map_size,
+                map,
+                &mut map_key,
+                &mut desc_size,
+                &mut desc_version,
+            )
+        };
+        if status != SUCCESS {
+            break;
+        }
+        status = unsafe { (bs.exit_boot_services)(image, map_key) };
+        if status == SUCCESS {
+            break;
+        }
+    }
+    if status != SUCCESS {
+        return status;
+    }
+
+    let boot = buff as *mut EfiBoot;
+    unsafe {
+        boot.write(EfiBoot {
+            rsdp: rsdp as usize,
+            memory_map: map,
+            memory_map_size: map_size,
+            desc_size,
+            firmware,
+        })
+    };
+
+    let image_start = adr_l!("_skernel");
+    flush(image_start, adr_l!("_pe_image_end") - image_start);
+    flush(buff as usize, size_of::<EfiBoot>() + map_size);
+    if !fdt.is_null() {
+        let total_size = u32::from_be(unsafe { (fdt as *const u32).add(1).read_unaligned() });
+        flush(fdt as usize, total_size as usize);
+    }
+    for i in 0..map_size.checked_div(desc_size).unwrap_or_default() {
+        // 类型、物理起始地址、页数分别在偏移 0、8、24
+        let desc = unsafe { map.add(i * desc_size) };
+        let ty = unsafe { (desc as *const u32).read_unaligned() };
+        if matches!(ty, ACPI_RECLAIM_MEMORY | ACPI_MEMORY_NVS) {
+            let (start, pages) = unsafe {
+                (
+                    (desc.add(8) as *const u64).read_unaligned(),
+                    (desc.add(24) as *const u64).read_unaligned(),
+                )
+            };
+            flush(start as usize, pages as usize * EFI_PAGE_SIZE);
+        }
+    }
+
+    unsafe { enter_kernel(fdt, boot) }
+}
+
+/// 关闭当前异常级别的 MMU 和数据缓存，按 Image 启动协议进入 `primary_entry`
+unsafe fn enter_kernel(fdt: *mut u8, boot: *const EfiBoot) -> ! {
+    unsafe {
+        asm!(
+            "msr      daifset, #0xf",
+            "mrs      x8,  CurrentEL",
+            "cmp      x8,  #(2 << 2)",
+            "b.ne     1f",
+            "mrs      x8,  sctlr_el2",
+            // M, C
+            "bic      x8,  x8, #(1 << 0)",
+            "bic      x8,  x8, #(1 << 2)",
+            "msr      sctlr_el2, x8",
+            "b        2f",
+            "1:",
+            "mrs      x8,  sctlr_el1",
+            "bic      x8,  x8, #(1 << 0)",
+            "bic      x8,  x8, #(1 << 2)",
+            "msr      sctlr_el1, x8",
+            "2:",
+            "isb",
+            "ic       iallu",
+            "dsb      nsh",
+            "isb",
+            "adrp     x8,  {entry}",
+            "add      x8,  x8, :lo12:{entry}",
+            "br       x8",
+            entry = sym primary_entry,
+            in("x0") fdt,
+            in("x1") boot,
+            options(noreturn)
+        )
+    }
+}
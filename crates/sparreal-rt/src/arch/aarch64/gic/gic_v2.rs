@@ -29,11 +29,13 @@ fn probe_gic(info: FdtInfo<'_>) -> Result<Vec<HardwareKind>, Box<dyn Error>> {
     let gicd = iomap(
         (gicd_reg.address as usize).into(),
         gicd_reg.size.unwrap_or(0x1000),
-    );
+    )?
+    .leak();
     let gicc = iomap(
         (gicc_reg.address as usize).into(),
         gicc_reg.size.unwrap_or(0x1000),
-    );
+    )?
+    .leak();
     Ok(alloc::vec![HardwareKind::Intc(Box::new(Gic::new(
         gicd, gicc
     )))])
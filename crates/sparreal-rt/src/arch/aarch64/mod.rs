@@ -5,15 +5,37 @@ use context::{__tcb_switch, Context};
 use log::trace;
 use sparreal_kernel::{platform_if::*, task::TaskControlBlock};
 
-use crate::{consts, mem::driver_registers};
+use crate::{
+    consts,
+    mem::{driver_registers, kernel_params, syscalls},
+};
+
+/// PC 相对寻址取符号地址，不经过 GOT，MMU 开启前得到的是物理地址
+macro_rules! adr_l {
+    ($sym:expr) => {{
+        let addr: usize;
+        unsafe {
+            core::arch::asm!(
+                concat!("adrp {0}, ", $sym),
+                concat!("add {0}, {0}, :lo12:", $sym),
+                out(reg) addr,
+                options(pure, nomem, nostack),
+            )
+        };
+        addr
+    }};
+}
+pub(crate) use adr_l;
 
 mod boot;
 mod cache;
 mod context;
 mod debug;
+mod efi;
 mod gic;
 mod paging;
 mod power;
+mod relocate;
 mod timer;
 mod trap;
 
@@ -48,11 +70,11 @@ impl Platform for PlatformImpl {
     }
 
     unsafe fn get_current_tcb_addr() -> *mut u8 {
-        SP_EL0.get() as usize as _
+        TPIDR_EL1.get() as usize as _
     }
 
     unsafe fn set_current_tcb_addr(addr: *mut u8) {
-        SP_EL0.set(addr as usize as _);
+        TPIDR_EL1.set(addr as usize as _);
     }
 
     /// # Safety
@@ -77,11 +99,68 @@ impl Platform for PlatformImpl {
     }
 
     unsafe fn cpu_context_switch(prev_ptr: *mut u8, next_ptr: *mut u8) {
+        let mut prev = TaskControlBlock::from(prev_ptr);
         let next = TaskControlBlock::from(next_ptr);
+        // 用户态寄存器不在内核上下文中，随任务保存
+        prev.user_sp = SP_EL0.get() as _;
+        prev.user_tls = TPIDR_EL0.get() as _;
+        SP_EL0.set(next.user_sp as _);
+        TPIDR_EL0.set(next.user_tls as _);
         trace!("switch to: {:?}", unsafe { &*(next.sp as *const Context) });
         unsafe { __tcb_switch(prev_ptr, next_ptr) };
     }
 
+    unsafe fn enter_user(pc: usize, sp: usize) -> ! {
+        SP_EL0.set(sp as _);
+        TPIDR_EL0.set(0);
+        ELR_EL1.set(pc as _);
+        // EL0t，不屏蔽中断
+        SPSR_EL1.set(0);
+        unsafe {
+            asm!(
+                "mov x0, xzr",
+                "mov x1, xzr",
+                "mov x2, xzr",
+                "mov x3, xzr",
+                "mov x4, xzr",
+                "mov x5, xzr",
+                "mov x6, xzr",
+                "mov x7, xzr",
+                "mov x8, xzr",
+                "mov x9, xzr",
+                "mov x10, xzr",
+                "mov x11, xzr",
+                "mov x12, xzr",
+                "mov x13, xzr",
+                "mov x14, xzr",
+                "mov x15, xzr",
+                "mov x16, xzr",
+                "mov x17, xzr",
+                "mov x18, xzr",
+                "mov x19, xzr",
+                "mov x20, xzr",
+                "mov x21, xzr",
+                "mov x22, xzr",
+                "mov x23, xzr",
+                "mov x24, xzr",
+                "mov x25, xzr",
+                "mov x26, xzr",
+                "mov x27, xzr",
+                "mov x28, xzr",
+                "mov x29, xzr",
+                "mov x30, xzr",
+                "eret",
+                options(noreturn)
+            )
+        }
+    }
+
+    fn frame_pointer() -> usize {
+        let fp: usize;
+        unsafe { asm!("mov {}, x29", out(reg) fp) };
+        fp
+    }
+
     fn wait_for_interrupt() {
         aarch64_cpu::asm::wfi();
     }
@@ -114,4 +193,12 @@ impl Platform for PlatformImpl {
     fn driver_registers() -> DriverRegisterSlice {
         DriverRegisterSlice::from_raw(driver_registers())
     }
+
+    fn kernel_params() -> KernelParamSlice {
+        KernelParamSlice::from_raw(kernel_params())
+    }
+
+    fn syscalls() -> SyscallSlice {
+        SyscallSlice::from_raw(syscalls())
+    }
 }
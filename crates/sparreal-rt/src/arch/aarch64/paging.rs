@@ -2,22 +2,65 @@ use core::arch::asm;
 
 use aarch64_cpu::{asm::barrier::*, registers::*};
 use page_table_arm::*;
-use sparreal_kernel::{io::print::*, mem::PhysAddr, platform_if::*};
+use sparreal_kernel::{io::print::*, mem::PhysAddr, platform::shutdown, platform_if::*};
 
 use crate::mem::fdt_addr;
 
 use super::cache;
 
+#[cfg(all(feature = "granule-16k", feature = "granule-64k"))]
+compile_error!("features `granule-16k` and `granule-64k` are mutually exclusive");
+
+pub const GRANULE: Granule = if cfg!(feature = "granule-64k") {
+    Granule::Size64K
+} else if cfg!(feature = "granule-16k") {
+    Granule::Size16K
+} else {
+    Granule::Size4K
+};
+
+#[cfg(all(feature = "mair-device-ngnre", feature = "mair-device-gre"))]
+compile_error!("features `mair-device-ngnre` and `mair-device-gre` are mutually exclusive");
+
+/// 写入 `MAIR_EL1` 的内存类型，`CacheSetting` 经它映射到具体类型和共享属性
+///
+/// 类型表与 [`MAIRDefault`] 相同，映射由 `mair-*` feature 选择
+pub struct MAIRImpl;
+
+impl MAIRSetting for MAIRImpl {
+    const ATTRS: &'static [MAIRKind] = MAIRDefault::ATTRS;
+
+    fn kind_of(cache: CacheSetting) -> MAIRKind {
+        match cache {
+            CacheSetting::Device if cfg!(feature = "mair-device-gre") => MAIRKind::DeviceGRE,
+            CacheSetting::Device if cfg!(feature = "mair-device-ngnre") => MAIRKind::DeviceNGnRE,
+            CacheSetting::Normal if cfg!(feature = "mair-normal-wt") => MAIRKind::WriteThrough,
+            _ => MAIRKind::from_cache(cache),
+        }
+    }
+}
+
+/// `ID_AA64MMFR0_EL1` 中 `GRANULE` 对应的 TGran 字段，16K 的编码与另外两种相反
+fn granule_supported() -> bool {
+    let mmfr0 = ID_AA64MMFR0_EL1.extract();
+    match GRANULE {
+        Granule::Size4K => mmfr0.read(ID_AA64MMFR0_EL1::TGran4) != 0b1111,
+        Granule::Size16K => mmfr0.read(ID_AA64MMFR0_EL1::TGran16) != 0b0000,
+        Granule::Size64K => mmfr0.read(ID_AA64MMFR0_EL1::TGran64) != 0b1111,
+    }
+}
+
 pub struct PageTableImpl;
 
 #[api_impl]
 impl MMU for PageTableImpl {
     unsafe fn boot_regions() -> BootRsvRegionVec {
         let mut ret = crate::mem::rsv_regions();
-        let debug_reg = PhysAddr::new(super::debug::reg()).align_down(0x1000);
+        let page_size = GRANULE.page_size();
+        let debug_reg = PhysAddr::new(super::debug::reg()).align_down(page_size);
 
         ret.push(BootRegion::new(
-            debug_reg..debug_reg + 0x1000,
+            debug_reg..debug_reg + page_size,
             c"debug_uart",
             AccessSetting::Read | AccessSetting::Write,
             CacheSetting::Device,
@@ -36,63 +79,36 @@ impl MMU for PageTableImpl {
     }
 
     fn page_size() -> usize {
-        0x1000
+        GRANULE.page_size()
     }
 
     fn table_level() -> usize {
-        4
+        GRANULE.levels()
     }
 
     fn new_pte(config: PTEGeneric) -> usize {
-        let mut pte = PTE::from_paddr(config.paddr);
+        let mut pte = PTE::from_paddr_with(config.paddr, GRANULE);
         let mut flags = PTEFlags::empty();
 
+        // AF 只表示是否访问过，不表示权限。表描述符也经这里生成，不能用无效描述符表示无权限；
+        // 特权级总是可读，无权限时只读且不可执行
         if config.is_valid {
-            flags |= PTEFlags::VALID;
+            flags |= PTEFlags::VALID | PTEFlags::AF;
         }
 
         if !config.is_block {
             flags |= PTEFlags::NON_BLOCK;
         }
 
-        pte.set_mair_idx(MAIRDefault::get_idx(match config.setting.cache_setting {
-            CacheSetting::Normal => MAIRKind::Normal,
-            CacheSetting::Device => MAIRKind::Device,
-            CacheSetting::NonCache => MAIRKind::NonCache,
-        }));
-
-        let privilege = &config.setting.privilege_access;
+        let kind = MAIRImpl::kind_of(config.setting.cache_setting);
+        pte.set_mair_idx(MAIRImpl::get_idx(kind));
+        flags |= MAIRImpl::shareability(kind);
 
         if !config.setting.is_global {
             flags |= PTEFlags::NG;
         }
 
-        if privilege.readable() {
-            flags |= PTEFlags::AF;
-        }
-
-        if !privilege.writable() {
-            flags |= PTEFlags::AP_RO;
-        }
-
-        if !privilege.executable() {
-            flags |= PTEFlags::PXN;
-        }
-
-        let user = &config.setting.user_access;
-
-        if user.readable() {
-            flags |= PTEFlags::AP_EL0;
-        }
-
-        if user.writable() {
-            flags |= PTEFlags::AP_EL0;
-            flags.remove(PTEFlags::AP_RO);
-        }
-
-        if !user.executable() {
-            flags |= PTEFlags::UXN;
-        }
+        flags |= PTEFlags::from_access(config.setting.privilege_access, config.setting.user_access);
 
         pte.set_flags(flags);
 
@@ -103,7 +119,7 @@ impl MMU for PageTableImpl {
 
     fn read_pte(pte: usize) -> PTEGeneric {
         let pte = PTE::from(pte as u64);
-        let paddr = pte.paddr();
+        let paddr = pte.paddr_with(GRANULE);
         let flags = pte.get_flags();
         let is_valid = flags.contains(PTEFlags::VALID);
         let is_block = !flags.contains(PTEFlags::NON_BLOCK);
@@ -115,17 +131,14 @@ impl MMU for PageTableImpl {
         if is_valid {
             let mair_idx = pte.get_mair_idx();
 
-            cache_setting = match MAIRDefault::from_idx(mair_idx) {
-                MAIRKind::Device => CacheSetting::Device,
-                MAIRKind::Normal => CacheSetting::Normal,
-                MAIRKind::NonCache => CacheSetting::NonCache,
-            };
+            cache_setting = MAIRImpl::from_idx(mair_idx).cache_setting();
 
-            if flags.contains(PTEFlags::AF) {
-                privilege_access |= AccessSetting::Read;
-            }
+            // 权限只由 AP/PXN/UXN 决定，与 AF 无关
+            privilege_access |= AccessSetting::Read;
+
+            let writable = !flags.contains(PTEFlags::AP_RO) || flags.contains(PTEFlags::DBM);
 
-            if !flags.contains(PTEFlags::AP_RO) {
+            if writable {
                 privilege_access |= AccessSetting::Write;
             }
 
@@ -136,7 +149,7 @@ impl MMU for PageTableImpl {
             if flags.contains(PTEFlags::AP_EL0) {
                 user_access |= AccessSetting::Read;
 
-                if !flags.contains(PTEFlags::AP_RO) {
+                if writable {
                     user_access |= AccessSetting::Write;
                 }
             }
@@ -159,13 +172,48 @@ impl MMU for PageTableImpl {
         }
     }
 
+    fn page_state_tracked() -> PageState {
+        PageState {
+            accessed: TCR_EL1.is_set(TCR_EL1::HA),
+            dirty: TCR_EL1.is_set(TCR_EL1::HD),
+        }
+    }
+
+    fn read_page_state(pte: usize) -> PageState {
+        let pte = PTE::from(pte as u64);
+        PageState {
+            accessed: pte.is_accessed(),
+            dirty: pte.is_dirty(),
+        }
+    }
+
+    fn clear_page_state(pte: usize, clear: PageState) -> usize {
+        let mut pte = PTE::from(pte as u64);
+        if clear.accessed {
+            pte.clear_accessed();
+        }
+        if clear.dirty {
+            pte.clear_dirty();
+        }
+        u64::from(pte) as _
+    }
+
+    fn track_dirty(pte: usize) -> usize {
+        let mut pte = PTE::from(pte as u64);
+        // 可写页带 DBM，清脏后由硬件在首次写入时恢复可写；表描述符中该位为 RES0
+        if cfg!(feature = "hafdbs") && !pte.get_flags().contains(PTEFlags::AP_RO) {
+            pte.set_flags(PTEFlags::DBM);
+        }
+        u64::from(pte) as _
+    }
+
     fn set_kernel_table(addr: usize) {
-        TTBR1_EL1.set_baddr(addr as _);
+        TTBR1_EL1.set_baddr((addr + GRANULE.ttbr1_offset()) as _);
         Self::flush_tlb_all();
     }
 
     fn get_kernel_table() -> usize {
-        TTBR1_EL1.get_baddr() as _
+        TTBR1_EL1.get_baddr() as usize - GRANULE.ttbr1_offset()
     }
 
     fn set_user_table(addr: usize) {
@@ -178,31 +226,56 @@ impl MMU for PageTableImpl {
     }
 
     fn enable_mmu(stack_top: usize, jump_to: usize) -> ! {
-        MAIRDefault::mair_el1_apply();
+        if !granule_supported() {
+            early_dbg("page granule not supported by CPU: ");
+            early_dbg_hexln(GRANULE.page_size() as _);
+            shutdown();
+        }
+
+        MAIRImpl::mair_el1_apply();
 
-        // Enable TTBR0 and TTBR1 walks, page size = 4K, vaddr size = 48 bits, paddr size = 40 bits.
+        // Enable TTBR0 and TTBR1 walks, page size and vaddr size follow `GRANULE`.
+        let (tg0, tg1) = match GRANULE {
+            Granule::Size4K => (TCR_EL1::TG0::KiB_4, TCR_EL1::TG1::KiB_4),
+            Granule::Size16K => (TCR_EL1::TG0::KiB_16, TCR_EL1::TG1::KiB_16),
+            Granule::Size64K => (TCR_EL1::TG0::KiB_64, TCR_EL1::TG1::KiB_64),
+        };
         let tcr_flags0 = TCR_EL1::EPD0::EnableTTBR0Walks
-            + TCR_EL1::TG0::KiB_4
+            + tg0
             + TCR_EL1::SH0::Inner
             + TCR_EL1::ORGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::IRGN0::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-            + TCR_EL1::T0SZ.val(16);
+            + TCR_EL1::T0SZ.val(GRANULE.tsz());
         let tcr_flags1 = TCR_EL1::EPD1::EnableTTBR1Walks
-            + TCR_EL1::TG1::KiB_4
+            + tg1
             + TCR_EL1::SH1::Inner
             + TCR_EL1::ORGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
             + TCR_EL1::IRGN1::WriteBack_ReadAlloc_WriteAlloc_Cacheable
-            + TCR_EL1::T1SZ.val(16);
+            + TCR_EL1::T1SZ.val(GRANULE.tsz());
         TCR_EL1.write(TCR_EL1::IPS::Bits_48 + tcr_flags0 + tcr_flags1);
 
+        if cfg!(feature = "hafdbs") {
+            match HwUpdate::detect() {
+                HwUpdate::None => {}
+                HwUpdate::AccessFlag => TCR_EL1.modify(TCR_EL1::HA::Enable),
+                HwUpdate::AccessFlagDirty => {
+                    TCR_EL1.modify(TCR_EL1::HA::Enable + TCR_EL1::HD::Enable)
+                }
+            }
+        }
+
         cache::dcache_all(CacheOp::CleanAndInvalidate);
 
         early_dbg("TCR_EL1: ");
         early_dbg_hexln(TCR_EL1.get());
         unsafe {
-            super::debug::setup_by_fdt(fdt_addr().unwrap().raw() as _, |r| {
-                (r + RegionKind::Other.va_offset()) as _
-            });
+            let to_virt: super::debug::FnPhysToVirt = |r| (r + RegionKind::Other.va_offset()) as _;
+            match fdt_addr() {
+                Some(fdt) => {
+                    super::debug::setup_by_fdt(fdt.raw() as _, to_virt);
+                }
+                None => super::debug::remap(to_virt),
+            }
 
             MMUImpl::flush_tlb_all();
             // Enable the MMU and turn on I-cache and D-cache
@@ -0,0 +1,55 @@
+//! 自重定位：镜像以 PIE 链接，启动时处理 `.rela.dyn`，可在任意地址运行
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::adr_l;
+use crate::consts::KERNEL_VADDR;
+
+const R_AARCH64_NONE: u64 = 0;
+const R_AARCH64_RELATIVE: u64 = 1027;
+
+/// 遇到的第一个不支持的重定位类型，串口就绪后由入口报告
+static UNSUPPORTED: AtomicU64 = AtomicU64::new(0);
+
+#[repr(C)]
+struct Rela {
+    offset: usize,
+    info: u64,
+    addend: usize,
+}
+
+/// 按最终虚拟基址 `KERNEL_VADDR + slide` 写入重定位项，返回 text 段虚拟地址偏移
+///
+/// 在 MMU 开启前调用，通过当前的物理地址写入，不能使用需要重定位的数据。
+/// 只在入口调用一次，`slide` 须事先确定。
+pub(crate) extern "C" fn relocate(slide: usize) -> usize {
+    let pa = adr_l!("_skernel");
+    let start = adr_l!("_srela") as *const Rela;
+    let end = adr_l!("_erela");
+    let count = (end - start as usize) / size_of::<Rela>();
+    let relas = unsafe { core::slice::from_raw_parts(start, count) };
+
+    for r in relas {
+        match r.info & 0xffff_ffff {
+            R_AARCH64_RELATIVE => unsafe {
+                let loc = (r.offset - KERNEL_VADDR + pa) as *mut usize;
+                loc.write(r.addend.wrapping_add(slide));
+            },
+            R_AARCH64_NONE => {}
+            // 静态 PIE 只应有 RELATIVE，此时串口尚未初始化，先记下
+            ty => {
+                let _ = UNSUPPORTED.compare_exchange(0, ty, Ordering::Relaxed, Ordering::Relaxed);
+            }
+        }
+    }
+
+    KERNEL_VADDR + slide - pa
+}
+
+/// 重定位时遇到的不支持的类型
+pub(crate) fn unsupported() -> Option<u64> {
+    match UNSUPPORTED.load(Ordering::Relaxed) {
+        0 => None,
+        ty => Some(ty),
+    }
+}
@@ -1,7 +1,13 @@
 use aarch64_cpu::registers::*;
 use core::arch::global_asm;
 use log::*;
-use sparreal_kernel::mem::VirtAddr;
+use sparreal_kernel::{
+    mem::{
+        VirtAddr,
+        mmu::{self, FaultAccess, PageFault},
+    },
+    process, syscall,
+};
 use sparreal_macros::aarch64_trap_handler;
 
 use super::context::Context;
@@ -21,7 +27,7 @@ fn handle_fiq(ctx: &Context) -> usize {
 }
 
 #[aarch64_trap_handler(kind = "sync")]
-fn handle_sync(ctx: &Context) -> usize {
+fn handle_sync(ctx: &mut Context) -> usize {
     let sp = ctx.sp;
     let esr = ESR_EL1.extract();
     let iss = esr.read(ESR_EL1::ISS);
@@ -29,15 +35,30 @@ fn handle_sync(ctx: &Context) -> usize {
 
     if let Some(code) = esr.read_as_enum(ESR_EL1::EC) {
         match code {
+            ESR_EL1::EC::Value::SVC64 if from_user(ctx) => handle_syscall(ctx),
             ESR_EL1::EC::Value::SVC64 => {
-                warn!("No syscall is supported currently!");
+                warn!("SVC from kernel is not supported!");
+            }
+            ESR_EL1::EC::Value::DataAbortLowerEL => handle_data_abort(ctx, iss, true),
+            ESR_EL1::EC::Value::DataAbortCurrentEL => handle_data_abort(ctx, iss, false),
+            ESR_EL1::EC::Value::InstrAbortLowerEL => {
+                handle_abort(ctx, iss, FaultAccess::Execute, true)
+            }
+            ESR_EL1::EC::Value::InstrAbortCurrentEL => {
+                handle_abort(ctx, iss, FaultAccess::Execute, false)
             }
-            ESR_EL1::EC::Value::DataAbortLowerEL => handle_data_abort(iss, true),
-            ESR_EL1::EC::Value::DataAbortCurrentEL => handle_data_abort(iss, false),
             ESR_EL1::EC::Value::Brk64 => {
                 // debug!("BRK #{:#x} @ {:#x} ", iss, tf.elr);
                 // tf.elr += 4;
             }
+            _ if from_user(ctx) => {
+                error!(
+                    "Unhandled synchronous exception from EL0 @ {:p}: ESR={:#x}, kill process",
+                    elr,
+                    esr.get(),
+                );
+                process::exit_current(-11);
+            }
             _ => {
                 panic!(
                     "\r\n{:?}\r\nUnhandled synchronous exception @ {:p}: ESR={:#x} (EC {:#08b}, ISS {:#x})",
@@ -53,6 +74,18 @@ fn handle_sync(ctx: &Context) -> usize {
     sp as _
 }
 
+/// x8 为调用号，x0..x5 为参数，返回值写回 x0
+fn handle_syscall(ctx: &mut Context) {
+    let mut args = [0; 6];
+    args.copy_from_slice(&ctx.x[..6]);
+    ctx.x[0] = syscall::dispatch(ctx.x[8], args);
+}
+
+/// 异常来自 EL0
+fn from_user(ctx: &Context) -> bool {
+    (ctx.spsr & 0xf) == 0
+}
+
 #[aarch64_trap_handler(kind = "serror")]
 fn handle_serror(ctx: &Context) -> usize {
     error!("SError exception:");
@@ -69,27 +102,95 @@ fn handle_serror(ctx: &Context) -> usize {
     );
 }
 
-fn handle_data_abort(iss: u64, _is_user: bool) {
+fn handle_data_abort(ctx: &Context, iss: u64, is_user: bool) {
     let wnr = (iss & (1 << 6)) != 0; // WnR: Write not Read
     let cm = (iss & (1 << 8)) != 0; // CM: Cache maintenance
-    let reason = if wnr & !cm {
-        PageFaultReason::Write
+    let access = if wnr & !cm {
+        FaultAccess::Write
     } else {
-        PageFaultReason::Read
+        FaultAccess::Read
     };
-    let vaddr = VirtAddr::from(FAR_EL1.get() as usize);
+    handle_abort(ctx, iss, access, is_user);
+}
 
-    handle_page_fault(vaddr, reason);
+fn handle_abort(ctx: &Context, iss: u64, access: FaultAccess, is_user: bool) {
+    let fnv = (iss & (1 << 10)) != 0; // FnV: FAR not Valid
+    let fault = PageFault {
+        vaddr: VirtAddr::from(FAR_EL1.get() as usize),
+        access,
+        user: is_user,
+        pc: ctx.pc as usize,
+    };
+
+    // 只有转换错误和访问标志错误可能由按需映射解决
+    let dfsc = iss & 0x3f;
+    let resolvable = matches!(dfsc >> 2, 0b0001 | 0b0010);
+    if !fnv && resolvable && mmu::handle_page_fault(&fault) {
+        return;
+    }
+
+    handle_page_fault(ctx, &fault, iss);
 }
 
-#[derive(Debug)]
-pub enum PageFaultReason {
-    Read,
-    Write,
+/// 未解决的缺页，打印 ESR 解码和页表遍历后 panic，来自用户态时结束进程
+fn handle_page_fault(ctx: &Context, fault: &PageFault, iss: u64) -> ! {
+    let esr = ESR_EL1.extract();
+    let dfsc = iss & 0x3f;
+    error!("{:?}", ctx);
+    error!(
+        "ESR={:#x} EC {:?} ISS {:#x}",
+        esr.get(),
+        esr.read_as_enum::<ESR_EL1::EC::Value>(ESR_EL1::EC),
+        iss
+    );
+    error!(
+        "  {} (DFSC {:#08b}), WnR {}, S1PTW {}, FnV {}",
+        fault_status(dfsc),
+        dfsc,
+        (iss >> 6) & 1,
+        (iss >> 7) & 1,
+        (iss >> 10) & 1,
+    );
+    error!(
+        "  FAR {:?} ELR {:#x} access {:?} from {}",
+        fault.vaddr,
+        fault.pc,
+        fault.access,
+        if fault.user { "EL0" } else { "EL1" }
+    );
+    match mmu::describe_fault_addr(fault.vaddr) {
+        Some((name, true)) => error!("  hit guard page below lazy region {name}"),
+        Some((name, false)) => error!("  inside lazy region {name}"),
+        None => {}
+    }
+    for step in mmu::walk(fault.vaddr) {
+        error!(
+            "  L{} [{:>3}] {:#018x} {:?}",
+            step.level, step.index, step.raw, step.pte
+        );
+    }
+    if fault.user {
+        error!("  kill process");
+        process::exit_current(-11);
+    }
+    panic!(
+        "Invalid addr fault @{:?} ({:?}), pc {:#x}",
+        fault.vaddr, fault.access, fault.pc
+    );
 }
 
-pub fn handle_page_fault(vaddr: VirtAddr, reason: PageFaultReason) {
-    panic!("Invalid addr fault @{vaddr:?}, reason: {reason:?}");
+fn fault_status(dfsc: u64) -> &'static str {
+    match dfsc {
+        0b00_0000..=0b00_0011 => "address size fault",
+        0b00_0100..=0b00_0111 => "translation fault",
+        0b00_1000..=0b00_1011 => "access flag fault",
+        0b00_1100..=0b00_1111 => "permission fault",
+        0b01_0000 => "synchronous external abort",
+        0b01_0100..=0b01_0111 => "external abort on table walk",
+        0b10_0001 => "alignment fault",
+        0b11_0000 => "TLB conflict abort",
+        _ => "unknown fault",
+    }
 }
 
 global_asm!(
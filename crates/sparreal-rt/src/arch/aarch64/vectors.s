@@ -32,17 +32,20 @@ vector_table_el1:
     // current SP.
     .balign 0x80
     lower_el_aarch64_sync: // The exception handler for the synchronous
-    B .
+    B {sync_handler}
     // exception from a lower EL (AArch64).
  
     .balign 0x80
     lower_el_aarch64_irq: // The exception handler for the IRQ exception 
+    B {irq_handler}
     // from a lower EL (AArch64).
     .balign 0x80
     lower_el_aarch64_fiq: // The exception handler for the FIQ exception 
+    B {fiq_handler}
     // from a lower EL (AArch64).
     .balign 0x80
     lower_el_aarch64_serror: // The exception handler for the system error 
+    B {serror_handler}
     // exception from a lower EL(AArch64).
     .balign 0x80
     lower_el_aarch32_sync: // The exception handler for the synchronous
@@ -1,7 +1,6 @@
 #![no_std]
 #![no_main]
 #![feature(naked_functions)]
-#![feature(concat_idents)]
 #![feature(used_with_arg)]
 
 extern crate alloc;
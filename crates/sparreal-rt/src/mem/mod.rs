@@ -2,20 +2,19 @@ use arrayvec::ArrayVec;
 use core::ops::Range;
 use core::ptr::{NonNull, slice_from_raw_parts, slice_from_raw_parts_mut};
 use core::sync::atomic::{AtomicUsize, Ordering};
-use memory_addr::MemoryAddr;
 use sparreal_kernel::mem::mmu::*;
 pub use sparreal_kernel::mem::*;
+use sparreal_kernel::platform::fdt::Fdt;
 use sparreal_kernel::platform_if::BootRegion;
 
+use crate::arch::adr_l;
+
 static FDT_ADDR: AtomicUsize = AtomicUsize::new(0);
 static FDT_LEN: AtomicUsize = AtomicUsize::new(0);
 
 macro_rules! pa_of {
     ($name:ident) => {{
-        unsafe extern "C" {
-            static $name: u8;
-        }
-        let mut pa = &raw const $name as *const u8 as usize;
+        let mut pa = adr_l!(stringify!($name));
 
         if crate::arch::is_mmu_enabled() {
             pa -= get_text_va_offset()
@@ -26,7 +25,7 @@ macro_rules! pa_of {
 }
 
 pub(crate) unsafe fn save_fdt(ptr: *mut u8) -> Option<NonNull<u8>> {
-    let fdt_addr = _stack_top as usize;
+    let fdt_addr = adr_l!("_stack_top");
     let fdt = fdt_parser::Fdt::from_ptr(NonNull::new(ptr)?).ok()?;
     let len = fdt.total_size();
 
@@ -42,24 +41,12 @@ pub(crate) unsafe fn save_fdt(ptr: *mut u8) -> Option<NonNull<u8>> {
     NonNull::new(FDT_ADDR.load(Ordering::SeqCst) as _)
 }
 
-unsafe extern "C" {
-    fn _stext();
-    fn _etext();
-    fn _srodata();
-    fn _erodata();
-    fn _sdata();
-    fn _edata();
-    fn _sbss();
-    fn _ebss();
-    fn _stack_bottom();
-    fn _stack_top();
-}
-
+// 链接脚本符号一律 PC 相对取址，MMU 开启前得到物理地址
 macro_rules! fn_ld_range {
     ($name:ident) => {
         pub fn $name() -> &'static [u8] {
-            let start = concat_idents!(_s, $name) as *const u8 as usize;
-            let end = concat_idents!(_e, $name) as *const u8 as usize;
+            let start = adr_l!(concat!("_s", stringify!($name)));
+            let end = adr_l!(concat!("_e", stringify!($name)));
             unsafe { &*slice_from_raw_parts(start as *mut u8, end - start) }
         }
     };
@@ -70,14 +57,14 @@ fn_ld_range!(data);
 fn_ld_range!(bss);
 
 pub fn stack_cpu0() -> &'static [u8] {
-    let start = _stack_bottom as *const u8 as usize;
-    let end = _stack_top as *const u8 as usize;
+    let start = adr_l!("_stack_bottom");
+    let end = adr_l!("_stack_top");
     unsafe { &*slice_from_raw_parts(start as *mut u8, end - start) }
 }
 
 pub fn clean_bss() {
-    let start = _sbss as *const u8 as usize;
-    let end = _ebss as *const u8 as usize;
+    let start = adr_l!("_sbss");
+    let end = adr_l!("_ebss");
     let bss = unsafe { &mut *slice_from_raw_parts_mut(start as *mut u8, end - start) };
     bss.fill(0);
 }
@@ -91,7 +78,8 @@ fn fdt_addr_range() -> Option<Range<PhysAddr>> {
     let len = FDT_LEN.load(Ordering::Relaxed);
     if len != 0 {
         let fdt_addr = FDT_ADDR.load(Ordering::Relaxed);
-        Some(fdt_addr.into()..(fdt_addr + len.align_up_4k()).into())
+        let end = (fdt_addr + len).next_multiple_of(page_size());
+        Some(fdt_addr.into()..end.into())
     } else {
         None
     }
@@ -120,7 +108,7 @@ pub fn rsv_regions<const N: usize>() -> ArrayVec<BootRegion, N> {
     rsv_regions.push(BootRegion::new(
         slice_to_phys_range(rodata()),
         c".rodata",
-        AccessSetting::Read | AccessSetting::Execute,
+        AccessSetting::Read,
         CacheSetting::Normal,
         RegionKind::KImage,
     ));
@@ -128,7 +116,7 @@ pub fn rsv_regions<const N: usize>() -> ArrayVec<BootRegion, N> {
     rsv_regions.push(BootRegion::new(
         slice_to_phys_range(data()),
         c".data",
-        AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute,
+        AccessSetting::Read | AccessSetting::Write,
         CacheSetting::Normal,
         RegionKind::KImage,
     ));
@@ -136,7 +124,7 @@ pub fn rsv_regions<const N: usize>() -> ArrayVec<BootRegion, N> {
     rsv_regions.push(BootRegion::new(
         slice_to_phys_range(bss()),
         c".bss",
-        AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute,
+        AccessSetting::Read | AccessSetting::Write,
         CacheSetting::Normal,
         RegionKind::KImage,
     ));
@@ -144,7 +132,7 @@ pub fn rsv_regions<const N: usize>() -> ArrayVec<BootRegion, N> {
     rsv_regions.push(BootRegion::new(
         slice_to_phys_range(stack_cpu0()),
         c".stack",
-        AccessSetting::Read | AccessSetting::Write | AccessSetting::Execute,
+        AccessSetting::Read | AccessSetting::Write,
         CacheSetting::Normal,
         RegionKind::Stack,
     ));
@@ -159,6 +147,16 @@ pub fn rsv_regions<const N: usize>() -> ArrayVec<BootRegion, N> {
         ));
     }
 
+    if let Some(initrd) = fdt_addr().and_then(|fdt| Fdt::new(fdt).initrd()) {
+        rsv_regions.push(BootRegion::new(
+            initrd.start.align_down(page_size())..initrd.end.align_up(page_size()),
+            c"initrd",
+            AccessSetting::Read,
+            CacheSetting::Normal,
+            RegionKind::Other,
+        ));
+    }
+
     rsv_regions
 }
 
@@ -170,3 +168,26 @@ pub fn driver_registers() -> &'static [u8] {
 
     unsafe { &*slice_from_raw_parts(_sdriver as *const u8, _edriver as usize - _sdriver as usize) }
 }
+
+pub fn syscalls() -> &'static [u8] {
+    unsafe extern "C" {
+        fn _ssyscall();
+        fn _esyscall();
+    }
+
+    unsafe {
+        &*slice_from_raw_parts(
+            _ssyscall as *const u8,
+            _esyscall as usize - _ssyscall as usize,
+        )
+    }
+}
+
+pub fn kernel_params() -> &'static [u8] {
+    unsafe extern "C" {
+        fn _skparam();
+        fn _ekparam();
+    }
+
+    unsafe { &*slice_from_raw_parts(_skparam as *const u8, _ekparam as usize - _skparam as usize) }
+}
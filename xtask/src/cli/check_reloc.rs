@@ -0,0 +1,85 @@
+use std::{fs, process::exit};
+
+use super::CheckRelocArgs;
+
+const EM_AARCH64: u16 = 183;
+const SHT_RELA: u32 = 4;
+const SHT_REL: u32 = 9;
+const R_AARCH64_NONE: u32 = 0;
+const R_AARCH64_RELATIVE: u32 = 1027;
+
+/// 启动时的自重定位只处理 `R_AARCH64_RELATIVE`，其他类型须在构建后发现
+pub fn exec(args: &CheckRelocArgs) {
+    let elf = fs::read(&args.elf).unwrap();
+
+    match check(&elf) {
+        Ok(count) => println!(
+            "{}: {} relocations, all R_AARCH64_RELATIVE",
+            args.elf.display(),
+            count
+        ),
+        Err(e) => {
+            eprintln!("{}: {}", args.elf.display(), e);
+            exit(1);
+        }
+    }
+}
+
+fn check(elf: &[u8]) -> Result<usize, String> {
+    if elf.get(..4) != Some(b"\x7fELF") || elf.get(4) != Some(&2) || elf.get(5) != Some(&1) {
+        return Err("not a little-endian ELF64 file".into());
+    }
+    if read_u16(elf, 0x12)? != EM_AARCH64 {
+        return Err("not an AArch64 image".into());
+    }
+
+    let shoff = read_u64(elf, 0x28)? as usize;
+    let shentsize = read_u16(elf, 0x3a)? as usize;
+    let shnum = read_u16(elf, 0x3c)? as usize;
+
+    let mut count = 0;
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        match read_u32(elf, sh + 4)? {
+            SHT_RELA => {}
+            SHT_REL => return Err(format!("section {i}: REL relocations are not supported")),
+            _ => continue,
+        }
+        let offset = read_u64(elf, sh + 0x18)? as usize;
+        let size = read_u64(elf, sh + 0x20)? as usize;
+        let entsize = (read_u64(elf, sh + 0x38)? as usize).max(24);
+
+        for rela in (offset..offset + size).step_by(entsize) {
+            match read_u64(elf, rela + 8)? as u32 {
+                R_AARCH64_NONE => {}
+                R_AARCH64_RELATIVE => count += 1,
+                ty => {
+                    return Err(format!(
+                        "unsupported relocation type {} at {:#x}",
+                        ty,
+                        read_u64(elf, rela)?
+                    ));
+                }
+            }
+        }
+    }
+    Ok(count)
+}
+
+fn read<const N: usize>(elf: &[u8], offset: usize) -> Result<[u8; N], String> {
+    elf.get(offset..offset + N)
+        .and_then(|b| b.try_into().ok())
+        .ok_or_else(|| format!("truncated at {offset:#x}"))
+}
+
+fn read_u16(elf: &[u8], offset: usize) -> Result<u16, String> {
+    read(elf, offset).map(u16::from_le_bytes)
+}
+
+fn read_u32(elf: &[u8], offset: usize) -> Result<u32, String> {
+    read(elf, offset).map(u32::from_le_bytes)
+}
+
+fn read_u64(elf: &[u8], offset: usize) -> Result<u64, String> {
+    read(elf, offset).map(u64::from_le_bytes)
+}
@@ -1,5 +1,8 @@
+use std::path::PathBuf;
+
 use clap::{Args, Parser, Subcommand, ValueEnum};
 
+mod check_reloc;
 mod up_version;
 
 #[derive(Parser)]
@@ -13,6 +16,8 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     UpVersion(UpVersionArgs),
+    /// 检查内核 ELF 只含 `R_AARCH64_RELATIVE` 重定位
+    CheckReloc(CheckRelocArgs),
 }
 
 #[derive(Args, Debug)]
@@ -23,6 +28,11 @@ struct UpVersionArgs {
     break_change: bool,
 }
 
+#[derive(Args, Debug)]
+struct CheckRelocArgs {
+    elf: PathBuf,
+}
+
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum Module {
     Sparreal,
@@ -33,5 +43,6 @@ pub fn main() {
 
     match &cli.command {
         Commands::UpVersion(args) => up_version::exec(args),
+        Commands::CheckReloc(args) => check_reloc::exec(args),
     }
 }